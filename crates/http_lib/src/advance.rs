@@ -7,7 +7,6 @@ pub trait Advance {
     where
         F: FnMut(&u8) -> bool;
 
-    fn split_one_byte(&mut self) -> Option<u8>;
     fn split_while<F>(&mut self, predicate: F) -> Self
     where
         F: FnMut(&u8) -> bool;
@@ -45,16 +44,6 @@ impl Advance for Bytes {
         amt
     }
 
-    fn split_one_byte(&mut self) -> Option<u8> {
-        if self.is_empty() {
-            None
-        } else {
-            let res = self[0];
-            *self = self.slice(1..);
-            Some(res)
-        }
-    }
-
     fn split_while<F>(&mut self, predicate: F) -> Self
     where
         F: FnMut(&u8) -> bool 
@@ -0,0 +1,359 @@
+use std::fmt;
+
+use bytes::{Buf as _, Bytes, BytesMut};
+
+use crate::chars::{CRLF, QUOTED_TEXT_MAP, TCHAR_MAP};
+use crate::{field, Fields, HeaderName};
+
+const MAX_LINE_LEN: usize = 4096;
+const MAX_TRAILERS_LEN: usize = 16 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParsingError {
+    InvalidSize,
+    InvalidExtension,
+    LineTooLong,
+    MissingCrlf,
+    BodyTooLong,
+    TrailersTooLong,
+    Trailer(field::ParsingError),
+}
+
+impl ParsingError {
+    pub const fn as_str(self) -> &'static str {
+        use ParsingError::*;
+        match self {
+            InvalidSize => "invalid chunk size",
+            InvalidExtension => "invalid chunk extension",
+            LineTooLong => "chunk size line too long",
+            MissingCrlf => "chunk data is not terminated with CRLF",
+            BodyTooLong => "chunked body exceeds the size limit",
+            TrailersTooLong => "trailers exceed the size limit",
+            Trailer(_) => "malformed trailer",
+        }
+    }
+}
+
+impl fmt::Display for ParsingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::error::Error for ParsingError {}
+
+pub struct Decoded {
+    pub body: Bytes,
+    pub trailers: Fields,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Size,
+    Data { remaining: usize },
+    DataEnd,
+    Trailers,
+}
+
+/// An incremental decoder of the chunked transfer coding, see
+/// https://httpwg.org/specs/rfc9112.html#chunked.encoding
+pub struct Decoder {
+    state: State,
+    body: BytesMut,
+    max_len: usize,
+}
+
+impl Decoder {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            state: State::Size,
+            body: BytesMut::new(),
+            max_len,
+        }
+    }
+
+    /// Consumes as much of `buffer` as possible. Returns the body and trailers
+    /// once the last chunk and the trailer section have been received.
+    pub fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<Decoded>, ParsingError> {
+        loop {
+            match self.state {
+                State::Size => {
+                    let Some(line_len) = find(buffer, CRLF) else {
+                        return if buffer.len() > MAX_LINE_LEN {
+                            Err(ParsingError::LineTooLong)
+                        } else {
+                            Ok(None)
+                        };
+                    };
+
+                    if line_len > MAX_LINE_LEN {
+                        return Err(ParsingError::LineTooLong);
+                    }
+
+                    let line = buffer.split_to(line_len + CRLF.len());
+                    let size = parse_size_line(&line[..line_len])?;
+                    if self.body.len().saturating_add(size) > self.max_len {
+                        return Err(ParsingError::BodyTooLong);
+                    }
+
+                    self.state = if size == 0 {
+                        State::Trailers
+                    } else {
+                        State::Data { remaining: size }
+                    };
+                }
+                State::Data { remaining } => {
+                    if buffer.is_empty() {
+                        return Ok(None);
+                    }
+
+                    let amt = remaining.min(buffer.len());
+                    self.body.extend_from_slice(&buffer.split_to(amt));
+                    self.state = if amt == remaining {
+                        State::DataEnd
+                    } else {
+                        State::Data {
+                            remaining: remaining - amt,
+                        }
+                    };
+                }
+                State::DataEnd => {
+                    if buffer.len() < CRLF.len() {
+                        return Ok(None);
+                    }
+
+                    if !buffer.starts_with(CRLF) {
+                        return Err(ParsingError::MissingCrlf);
+                    }
+
+                    buffer.advance(CRLF.len());
+                    self.state = State::Size;
+                }
+                State::Trailers => {
+                    let end = if buffer.starts_with(CRLF) {
+                        Some(CRLF.len())
+                    } else {
+                        find(buffer, b"\r\n\r\n").map(|pos| pos + 4)
+                    };
+
+                    let Some(end) = end else {
+                        return if buffer.len() > MAX_TRAILERS_LEN {
+                            Err(ParsingError::TrailersTooLong)
+                        } else {
+                            Ok(None)
+                        };
+                    };
+
+                    if end > MAX_TRAILERS_LEN {
+                        return Err(ParsingError::TrailersTooLong);
+                    }
+
+                    let mut section = buffer.split_to(end).freeze();
+                    let trailers =
+                        Fields::from_bytes(&mut section).map_err(ParsingError::Trailer)?;
+                    self.state = State::Size;
+
+                    return Ok(Some(Decoded {
+                        body: self.body.split().freeze(),
+                        trailers,
+                    }));
+                }
+            }
+        }
+    }
+}
+
+/// Returns `true` if chunked is the final transfer coding applied to the body.
+pub fn is_chunked(headers: &Fields) -> bool {
+    headers
+        .get(&HeaderName::TRANSFER_ENCODING)
+        .and_then(|vs| vs.iter_slices().last())
+        .is_some_and(|v| v.eq_ignore_ascii_case(b"chunked"))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn skip_whitespace(line: &[u8]) -> &[u8] {
+    let amt = line
+        .iter()
+        .take_while(|&&b| b == b' ' || b == b'\t')
+        .count();
+    &line[amt..]
+}
+
+fn split_token(line: &[u8]) -> (&[u8], &[u8]) {
+    let amt = line
+        .iter()
+        .take_while(|&&b| TCHAR_MAP[b as usize] != 0)
+        .count();
+    line.split_at(amt)
+}
+
+fn split_quoted_string(line: &[u8]) -> Option<&[u8]> {
+    let mut escaped = false;
+    for (i, &b) in line.iter().enumerate().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if b == b'\\' {
+            escaped = true;
+        } else if b == b'"' {
+            return Some(&line[i + 1..]);
+        } else if QUOTED_TEXT_MAP[b as usize] == 0 {
+            return None;
+        }
+    }
+
+    None
+}
+
+// Parses `chunk-size [ chunk-ext ]`, validating and discarding the extensions.
+fn parse_size_line(line: &[u8]) -> Result<usize, ParsingError> {
+    let digits = line.iter().take_while(|b| b.is_ascii_hexdigit()).count();
+    if digits == 0 {
+        return Err(ParsingError::InvalidSize);
+    }
+
+    let size = std::str::from_utf8(&line[..digits])
+        .ok()
+        .and_then(|s| usize::from_str_radix(s, 16).ok())
+        .ok_or(ParsingError::InvalidSize)?;
+
+    let mut rest = &line[digits..];
+    loop {
+        rest = skip_whitespace(rest);
+        if rest.is_empty() {
+            return Ok(size);
+        }
+
+        if rest[0] != b';' {
+            return Err(ParsingError::InvalidExtension);
+        }
+
+        let (name, after_name) = split_token(skip_whitespace(&rest[1..]));
+        if name.is_empty() {
+            return Err(ParsingError::InvalidExtension);
+        }
+
+        rest = skip_whitespace(after_name);
+        if rest.first() == Some(&b'=') {
+            rest = skip_whitespace(&rest[1..]);
+            rest = if rest.first() == Some(&b'"') {
+                split_quoted_string(rest).ok_or(ParsingError::InvalidExtension)?
+            } else {
+                let (value, after_value) = split_token(rest);
+                if value.is_empty() {
+                    return Err(ParsingError::InvalidExtension);
+                }
+                after_value
+            };
+        }
+    }
+}
+
+/// Writes a single chunk. Empty data is skipped, since an empty chunk would
+/// terminate the body.
+pub fn write_chunk(buffer: &mut Vec<u8>, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+
+    buffer.extend_from_slice(format!("{:X}", data.len()).as_bytes());
+    buffer.extend_from_slice(CRLF);
+    buffer.extend_from_slice(data);
+    buffer.extend_from_slice(CRLF);
+}
+
+/// Writes the last chunk followed by the trailer section.
+pub fn write_last_chunk(buffer: &mut Vec<u8>, trailers: &Fields) {
+    buffer.extend_from_slice(b"0");
+    buffer.extend_from_slice(CRLF);
+    trailers.write_to_buffer(buffer);
+    if trailers.is_empty() {
+        buffer.extend_from_slice(CRLF);
+    }
+}
+
+/// Writes the whole body as a single chunk, followed by the trailers.
+pub fn write_body(buffer: &mut Vec<u8>, body: &[u8], trailers: &Fields) {
+    write_chunk(buffer, body);
+    write_last_chunk(buffer, trailers);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::field::test::assert_headers;
+
+    const ENCODED: &str = "\
+        6;name=value\r\n\
+        Hello \r\n\
+        6 ; quoted=\"a \\\" b\"; flag\r\n\
+        world!\r\n\
+        0\r\n\
+        Expires: Sun, 09 Jul 2023 18:36:58 GMT\r\n\
+        Server-Timing: total;dur=123\r\n\
+        \r\n";
+
+    const TRAILERS: [(&str, &[&str]); 2] = [
+        ("Expires", &["Sun, 09 Jul 2023 18:36:58 GMT"]),
+        ("Server-Timing", &["total;dur=123"]),
+    ];
+
+    #[test]
+    fn decode() {
+        let mut buffer = BytesMut::from(ENCODED.as_bytes());
+        let decoded = Decoder::new(1024).decode(&mut buffer).unwrap().unwrap();
+        assert_eq!(&*decoded.body, b"Hello world!");
+        assert_headers(&decoded.trailers, &TRAILERS);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn decode_byte_by_byte() {
+        let mut decoder = Decoder::new(1024);
+        let mut buffer = BytesMut::new();
+        let mut decoded = None;
+        for &b in ENCODED.as_bytes() {
+            assert!(decoded.is_none());
+            buffer.extend_from_slice(&[b]);
+            decoded = decoder.decode(&mut buffer).unwrap();
+        }
+        assert_eq!(&*decoded.unwrap().body, b"Hello world!");
+    }
+
+    #[test]
+    fn decode_errors() {
+        let decode = |src: &str, max_len| {
+            let mut buffer = BytesMut::from(src.as_bytes());
+            Decoder::new(max_len).decode(&mut buffer).err()
+        };
+
+        assert_eq!(decode("x\r\n", 16), Some(ParsingError::InvalidSize));
+        assert_eq!(decode("1;=a\r\n", 16), Some(ParsingError::InvalidExtension));
+        assert_eq!(decode("1\r\nabc", 16), Some(ParsingError::MissingCrlf));
+        assert_eq!(decode("11\r\n", 16), Some(ParsingError::BodyTooLong));
+    }
+
+    #[test]
+    fn encode() {
+        let mut buffer = Vec::new();
+        write_chunk(&mut buffer, b"Hello ");
+        write_chunk(&mut buffer, b"");
+        write_chunk(&mut buffer, b"world!");
+        write_last_chunk(&mut buffer, &Fields::copy_from_str(TRAILERS));
+
+        let mut buffer = BytesMut::from(buffer.as_slice());
+        let decoded = Decoder::new(1024).decode(&mut buffer).unwrap().unwrap();
+        assert_eq!(&*decoded.body, b"Hello world!");
+        assert_headers(&decoded.trailers, &TRAILERS);
+    }
+
+    #[test]
+    fn encode_without_trailers() {
+        let mut buffer = Vec::new();
+        write_body(&mut buffer, b"Hello world!", &Fields::new());
+        assert_eq!(buffer, b"C\r\nHello world!\r\n0\r\n\r\n");
+    }
+}
@@ -0,0 +1,192 @@
+use std::fmt;
+
+use crate::field::Values;
+
+/// A content coding, see https://httpwg.org/specs/rfc9110.html#content.codings
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ContentCoding {
+    Identity,
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+impl ContentCoding {
+    pub const fn as_str(self) -> &'static str {
+        use ContentCoding::*;
+        match self {
+            Identity => "identity",
+            Gzip => "gzip",
+            Deflate => "deflate",
+            Brotli => "br",
+            Zstd => "zstd",
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        use ContentCoding::*;
+        let coding = match bytes.to_ascii_lowercase().as_slice() {
+            b"identity" => Identity,
+            b"gzip" | b"x-gzip" => Gzip,
+            b"deflate" => Deflate,
+            b"br" => Brotli,
+            b"zstd" => Zstd,
+            _ => return None,
+        };
+
+        Some(coding)
+    }
+}
+
+impl fmt::Display for ContentCoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+fn trim(mut bytes: &[u8]) -> &[u8] {
+    while let [b' ' | b'\t', rest @ ..] = bytes {
+        bytes = rest;
+    }
+
+    while let [rest @ .., b' ' | b'\t'] = bytes {
+        bytes = rest;
+    }
+
+    bytes
+}
+
+/// Parses a quality value in thousandths, see
+/// https://httpwg.org/specs/rfc9110.html#quality.values
+pub fn parse_qvalue(bytes: &[u8]) -> Option<u16> {
+    match bytes {
+        [b'0'] => Some(0),
+        [b'1'] => Some(1000),
+        [b'1', b'.', rest @ ..] if rest.len() <= 3 && rest.iter().all(|&b| b == b'0') => Some(1000),
+        [b'0', b'.', rest @ ..] if rest.len() <= 3 && rest.iter().all(u8::is_ascii_digit) => {
+            let mut qvalue = 0;
+            for i in 0..3 {
+                let digit = rest.get(i).map_or(0, |&b| u16::from(b - b'0'));
+                qvalue = qvalue * 10 + digit;
+            }
+            Some(qvalue)
+        }
+        _ => None,
+    }
+}
+
+// Splits an `Accept-Encoding` member into the coding and its weight. Returns
+// `None` if the weight is malformed.
+fn parse_member(member: &[u8]) -> Option<(&[u8], u16)> {
+    let mut parts = member.split(|&b| b == b';').map(trim);
+    let coding = parts.next()?;
+
+    let mut qvalue = 1000;
+    for param in parts {
+        let (name, value) = match param.iter().position(|&b| b == b'=') {
+            Some(eq) => (trim(&param[..eq]), trim(&param[eq + 1..])),
+            None => return None,
+        };
+
+        if name.eq_ignore_ascii_case(b"q") {
+            qvalue = parse_qvalue(value)?;
+        }
+    }
+
+    Some((coding, qvalue))
+}
+
+/// Picks a coding for the response from the value of `Accept-Encoding`.
+/// `supported` lists the codings the server can apply, in order of preference.
+/// Returns `Identity` if the request has no `Accept-Encoding` field, or if the
+/// client prefers an unencoded response.
+///
+/// See https://httpwg.org/specs/rfc9110.html#field.accept-encoding
+pub fn negotiate(accept_encoding: Option<&Values>, supported: &[ContentCoding]) -> ContentCoding {
+    let Some(values) = accept_encoding else {
+        return ContentCoding::Identity;
+    };
+
+    let mut wildcard = None;
+    let mut weights = Vec::with_capacity(values.count());
+    for member in values.iter_slices() {
+        let Some((coding, qvalue)) = parse_member(member) else {
+            continue;
+        };
+
+        if coding == b"*" {
+            wildcard = Some(qvalue);
+        } else if let Some(coding) = ContentCoding::from_bytes(coding) {
+            weights.push((coding, qvalue));
+        }
+    }
+
+    let weight_of = |coding: ContentCoding| {
+        weights
+            .iter()
+            .find(|(c, _)| *c == coding)
+            .map(|(_, qvalue)| *qvalue)
+            .or(wildcard)
+    };
+
+    // identity is always acceptable, but only preferred if it is weighted
+    // explicitly
+    let identity = weight_of(ContentCoding::Identity).unwrap_or(0);
+    let best = supported
+        .iter()
+        .map(|&coding| (coding, weight_of(coding).unwrap_or(0)))
+        .filter(|&(_, qvalue)| qvalue > 0)
+        .fold(
+            None,
+            |best: Option<(ContentCoding, u16)>, candidate| match best {
+                Some(best) if best.1 >= candidate.1 => Some(best),
+                _ => Some(candidate),
+            },
+        );
+
+    match best {
+        Some((coding, qvalue)) if qvalue >= identity => coding,
+        _ => ContentCoding::Identity,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Fields;
+    use ContentCoding::*;
+
+    const SUPPORTED: [ContentCoding; 3] = [Brotli, Gzip, Deflate];
+
+    fn check(value: &[&str]) -> ContentCoding {
+        let fields = Fields::copy_from_str([("Accept-Encoding", value)]);
+        negotiate(fields.get(b"Accept-Encoding"), &SUPPORTED)
+    }
+
+    #[test]
+    fn qvalue() {
+        assert_eq!(parse_qvalue(b"0"), Some(0));
+        assert_eq!(parse_qvalue(b"1.000"), Some(1000));
+        assert_eq!(parse_qvalue(b"0.5"), Some(500));
+        assert_eq!(parse_qvalue(b"0.123"), Some(123));
+        assert_eq!(parse_qvalue(b"0.1234"), None);
+        assert_eq!(parse_qvalue(b"1.5"), None);
+        assert_eq!(parse_qvalue(b".5"), None);
+    }
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(negotiate(None, &SUPPORTED), Identity);
+        assert_eq!(check(&["gzip", "deflate", "br"]), Brotli);
+        assert_eq!(check(&["gzip", "deflate", "br;q=0.9"]), Gzip);
+        assert_eq!(check(&["GZIP;Q=0.5", "deflate;q=0.8"]), Deflate);
+        assert_eq!(check(&["br;q=0", "*"]), Gzip);
+        assert_eq!(check(&["*;q=0", "identity"]), Identity);
+        assert_eq!(check(&["gzip;q=0.5"]), Gzip);
+        assert_eq!(check(&["gzip;q=0.5", "identity;q=0.8"]), Identity);
+        assert_eq!(check(&["compress", "gzip;q=bogus"]), Identity);
+        assert_eq!(check(&[""]), Identity);
+        assert_eq!(check(&["zstd", "gzip;q=0.9"]), Gzip);
+    }
+}
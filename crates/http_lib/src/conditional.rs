@@ -0,0 +1,184 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::etag::{EntityTag, EntityTagList};
+use crate::{Fields, HeaderName, Method};
+
+/// The result of evaluating the preconditions of a request.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    Proceed,
+    NotModified,
+    PreconditionFailed,
+}
+
+/// Validators of the selected representation.
+#[derive(Clone, Copy)]
+pub struct Validators<'a> {
+    pub etag: Option<&'a EntityTag>,
+    pub last_modified: Option<SystemTime>,
+}
+
+// HTTP dates have a resolution of one second.
+fn to_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn get_date(headers: &Fields, name: &[u8]) -> Option<SystemTime> {
+    let value = headers.get(name)?.to_buffer();
+    let value = std::str::from_utf8(&value).ok()?;
+    httpdate::parse_http_date(value).ok()
+}
+
+fn get_tags(headers: &Fields, name: &[u8]) -> Option<EntityTagList> {
+    EntityTagList::from_values(headers.get(name)?)
+}
+
+/// Evaluates `If-Match`, `If-Unmodified-Since`, `If-None-Match` and
+/// `If-Modified-Since` in the order defined by
+/// https://httpwg.org/specs/rfc9110.html#precedence
+///
+/// Invalid fields are ignored. `If-Range` is evaluated separately, since it
+/// only applies to range requests.
+pub fn evaluate(method: Method, headers: &Fields, validators: Validators) -> Outcome {
+    let is_get_or_head = matches!(method, Method::Get | Method::Head);
+
+    if let Some(if_match) = get_tags(headers, &HeaderName::IF_MATCH) {
+        let matches = match (&if_match, validators.etag) {
+            (EntityTagList::Any, _) => true,
+            (_, Some(etag)) => if_match.any_strong_eq(etag),
+            (_, None) => false,
+        };
+
+        if !matches {
+            return Outcome::PreconditionFailed;
+        }
+    } else if let Some(since) = get_date(headers, &HeaderName::IF_UNMODIFIED_SINCE) {
+        if let Some(last_modified) = validators.last_modified {
+            if to_secs(last_modified) > to_secs(since) {
+                return Outcome::PreconditionFailed;
+            }
+        }
+    }
+
+    let failed = if let Some(if_none_match) = get_tags(headers, &HeaderName::IF_NONE_MATCH) {
+        match (&if_none_match, validators.etag) {
+            (EntityTagList::Any, _) => true,
+            (_, Some(etag)) => if_none_match.any_weak_eq(etag),
+            (_, None) => false,
+        }
+    } else if is_get_or_head {
+        let since = get_date(headers, &HeaderName::IF_MODIFIED_SINCE);
+        match (since, validators.last_modified) {
+            (Some(since), Some(last_modified)) => to_secs(last_modified) <= to_secs(since),
+            _ => false,
+        }
+    } else {
+        false
+    };
+
+    match (failed, is_get_or_head) {
+        (false, _) => Outcome::Proceed,
+        (true, true) => Outcome::NotModified,
+        (true, false) => Outcome::PreconditionFailed,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    const MODIFIED: &str = "Sat, 10 Jun 2023 12:00:00 GMT";
+    const EARLIER: &str = "Fri, 09 Jun 2023 12:00:00 GMT";
+
+    fn check<const N: usize>(method: Method, fields: [(&str, &[&str]); N]) -> Outcome {
+        let etag = EntityTag::strong("abc").unwrap();
+        let last_modified =
+            httpdate::parse_http_date(MODIFIED).unwrap() + Duration::from_millis(500);
+        let validators = Validators {
+            etag: Some(&etag),
+            last_modified: Some(last_modified),
+        };
+        evaluate(method, &Fields::copy_from_str(fields), validators)
+    }
+
+    #[test]
+    fn none_match() {
+        use Outcome::*;
+        assert_eq!(
+            check(Method::Get, [("If-None-Match", &["\"abc\""])]),
+            NotModified
+        );
+        assert_eq!(
+            check(Method::Get, [("If-None-Match", &["W/\"abc\""])]),
+            NotModified
+        );
+        assert_eq!(
+            check(Method::Get, [("If-None-Match", &["\"xyz\""])]),
+            Proceed
+        );
+        assert_eq!(
+            check(Method::Put, [("If-None-Match", &["*"])]),
+            PreconditionFailed
+        );
+    }
+
+    #[test]
+    fn modified_since() {
+        use Outcome::*;
+        assert_eq!(
+            check(Method::Get, [("If-Modified-Since", &[MODIFIED])]),
+            NotModified
+        );
+        assert_eq!(
+            check(Method::Get, [("If-Modified-Since", &[EARLIER])]),
+            Proceed
+        );
+        assert_eq!(
+            check(Method::Post, [("If-Modified-Since", &[MODIFIED])]),
+            Proceed
+        );
+        // If-None-Match takes precedence
+        assert_eq!(
+            check(
+                Method::Get,
+                [
+                    ("If-None-Match", &["\"xyz\""]),
+                    ("If-Modified-Since", &[MODIFIED])
+                ]
+            ),
+            Proceed
+        );
+    }
+
+    #[test]
+    fn match_and_unmodified_since() {
+        use Outcome::*;
+        assert_eq!(check(Method::Get, [("If-Match", &["\"abc\""])]), Proceed);
+        assert_eq!(
+            check(Method::Get, [("If-Match", &["W/\"abc\""])]),
+            PreconditionFailed
+        );
+        assert_eq!(check(Method::Get, [("If-Match", &["*"])]), Proceed);
+        assert_eq!(
+            check(Method::Get, [("If-Unmodified-Since", &[MODIFIED])]),
+            Proceed
+        );
+        assert_eq!(
+            check(Method::Get, [("If-Unmodified-Since", &[EARLIER])]),
+            PreconditionFailed
+        );
+        // If-Match takes precedence
+        assert_eq!(
+            check(
+                Method::Get,
+                [
+                    ("If-Match", &["\"abc\""]),
+                    ("If-Unmodified-Since", &[EARLIER])
+                ]
+            ),
+            Proceed
+        );
+    }
+}
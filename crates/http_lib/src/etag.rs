@@ -0,0 +1,176 @@
+use std::fmt;
+
+use bytes::Bytes;
+
+use crate::field::Values;
+
+/// An entity tag, see https://httpwg.org/specs/rfc9110.html#field.etag
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EntityTag {
+    weak: bool,
+    tag: Bytes,
+}
+
+#[inline]
+fn is_etagc(b: u8) -> bool {
+    b == 0x21 || (0x23..=0x7e).contains(&b) || b >= 0x80
+}
+
+impl EntityTag {
+    /// Creates a strong entity tag. Returns `None` if `tag` contains characters
+    /// which are not allowed in an entity tag.
+    pub fn strong(tag: impl Into<Bytes>) -> Option<Self> {
+        Self::new(false, tag.into())
+    }
+
+    /// Creates a weak entity tag. Returns `None` if `tag` contains characters
+    /// which are not allowed in an entity tag.
+    pub fn weak(tag: impl Into<Bytes>) -> Option<Self> {
+        Self::new(true, tag.into())
+    }
+
+    fn new(weak: bool, tag: Bytes) -> Option<Self> {
+        tag.iter()
+            .copied()
+            .all(is_etagc)
+            .then_some(Self { weak, tag })
+    }
+
+    pub fn from_bytes(value: &[u8]) -> Option<Self> {
+        let (weak, quoted) = match value.strip_prefix(b"W/") {
+            Some(quoted) => (true, quoted),
+            None => (false, value),
+        };
+
+        let tag = quoted.strip_prefix(b"\"")?.strip_suffix(b"\"")?;
+        Self::new(weak, Bytes::copy_from_slice(tag))
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// Returns the tag without quotes.
+    pub fn tag(&self) -> &[u8] {
+        &self.tag
+    }
+
+    /// Both tags must be strong and identical.
+    pub fn strong_eq(&self, other: &Self) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// The tags must be identical, regardless of whether they are weak.
+    pub fn weak_eq(&self, other: &Self) -> bool {
+        self.tag == other.tag
+    }
+
+    pub fn write_to_buffer(&self, buffer: &mut Vec<u8>) {
+        if self.weak {
+            buffer.extend_from_slice(b"W/");
+        }
+
+        buffer.push(b'"');
+        buffer.extend_from_slice(&self.tag);
+        buffer.push(b'"');
+    }
+
+    pub fn to_buffer(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(self.tag.len() + 4);
+        self.write_to_buffer(&mut buffer);
+        buffer
+    }
+}
+
+impl fmt::Display for EntityTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.to_buffer()))
+    }
+}
+
+/// The value of `If-Match` or `If-None-Match`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum EntityTagList {
+    Any,
+    Tags(Vec<EntityTag>),
+}
+
+impl EntityTagList {
+    /// Returns `None` if any of the values is not a valid entity tag.
+    pub fn from_values(values: &Values) -> Option<Self> {
+        if values.is_single() && values.first_slice() == b"*" {
+            return Some(Self::Any);
+        }
+
+        values
+            .iter_slices()
+            .map(EntityTag::from_bytes)
+            .collect::<Option<_>>()
+            .map(Self::Tags)
+    }
+
+    pub fn any_strong_eq(&self, etag: &EntityTag) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Tags(tags) => tags.iter().any(|t| t.strong_eq(etag)),
+        }
+    }
+
+    pub fn any_weak_eq(&self, etag: &EntityTag) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Tags(tags) => tags.iter().any(|t| t.weak_eq(etag)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Fields;
+
+    #[test]
+    fn from_bytes() {
+        let strong = EntityTag::from_bytes(b"\"xyzzy\"").unwrap();
+        assert!(!strong.is_weak());
+        assert_eq!(strong.tag(), b"xyzzy");
+
+        let weak = EntityTag::from_bytes(b"W/\"xyzzy\"").unwrap();
+        assert!(weak.is_weak());
+        assert_eq!(weak.to_buffer(), b"W/\"xyzzy\"");
+
+        assert!(EntityTag::from_bytes(b"xyzzy").is_none());
+        assert!(EntityTag::from_bytes(b"\"xy\"zzy\"").is_none());
+    }
+
+    #[test]
+    fn comparison() {
+        // https://httpwg.org/specs/rfc9110.html#entity.tag.comparison
+        let w1 = EntityTag::weak("1").unwrap();
+        let w2 = EntityTag::weak("2").unwrap();
+        let s1 = EntityTag::strong("1").unwrap();
+        let s2 = EntityTag::strong("2").unwrap();
+
+        assert!(!w1.strong_eq(&w1) && w1.weak_eq(&w1));
+        assert!(!w1.strong_eq(&w2) && !w1.weak_eq(&w2));
+        assert!(!w1.strong_eq(&s1) && w1.weak_eq(&s1));
+        assert!(s1.strong_eq(&s1) && s1.weak_eq(&s1));
+        assert!(!s1.strong_eq(&s2));
+    }
+
+    #[test]
+    fn list() {
+        let fields = Fields::copy_from_str([
+            ("If-None-Match", &["\"a\"", "W/\"b\""]),
+            ("If-Match", &["*"]),
+        ]);
+
+        let none_match = EntityTagList::from_values(fields.get(b"If-None-Match").unwrap());
+        let none_match = none_match.unwrap();
+        assert!(none_match.any_weak_eq(&EntityTag::strong("b").unwrap()));
+        assert!(!none_match.any_strong_eq(&EntityTag::strong("b").unwrap()));
+
+        let any = EntityTagList::from_values(fields.get(b"If-Match").unwrap());
+        assert_eq!(any, Some(EntityTagList::Any));
+    }
+}
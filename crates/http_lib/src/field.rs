@@ -1,11 +1,13 @@
-use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::str::FromStr;
 use std::{fmt, iter, slice};
 
 use bytes::Bytes;
-use indexmap::IndexMap;
-use once_cell::sync::Lazy;
+use indexmap::{Equivalent, IndexMap};
 
 use crate::chars::{CRLF, CTEXT_MAP, DATE_MAP, QUOTED_TEXT_MAP, TCHAR_MAP, TOKEN_MAP};
+use crate::header::TypedHeader;
 use crate::Advance;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -53,10 +55,216 @@ impl fmt::Display for InvalidData {
 
 impl std::error::Error for InvalidData {}
 
-static DATE_FIELDS: Lazy<HashSet<Vec<u8>>> = Lazy::new(|| {
-    let values = ["Date", "Last-Modified", "Expires"];
-    values.into_iter().map(Vec::from).collect()
-});
+/// A field name which is empty or contains a character other than a token
+/// character, e.g. a space or a colon.
+#[derive(Debug)]
+pub struct InvalidHeaderName;
+
+impl fmt::Display for InvalidHeaderName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid field name")
+    }
+}
+
+impl std::error::Error for InvalidHeaderName {}
+
+/// The name of a field. Names are compared and hashed ignoring ASCII case, as
+/// clients and proxies are free to change it, but they are written with the
+/// casing they were created with.
+///
+/// Names are checked when they are created, with `TryFrom` or `parse`. Only
+/// string literals are converted with `From`, which panics if they are not
+/// valid.
+#[derive(Clone)]
+pub struct HeaderName(Bytes);
+
+macro_rules! header_names {
+    ($($constant:ident => $name:literal,)*) => {
+        impl HeaderName {
+            $(pub const $constant: Self = Self::from_static($name);)*
+        }
+    };
+}
+
+header_names! {
+    ACCEPT => "Accept",
+    ACCEPT_ENCODING => "Accept-Encoding",
+    ACCEPT_RANGES => "Accept-Ranges",
+    ALLOW => "Allow",
+    AUTHORIZATION => "Authorization",
+    CACHE_CONTROL => "Cache-Control",
+    CONNECTION => "Connection",
+    CONTENT_DISPOSITION => "Content-Disposition",
+    CONTENT_ENCODING => "Content-Encoding",
+    CONTENT_LENGTH => "Content-Length",
+    CONTENT_RANGE => "Content-Range",
+    CONTENT_TYPE => "Content-Type",
+    DATE => "Date",
+    ETAG => "ETag",
+    EXPIRES => "Expires",
+    HOST => "Host",
+    IF_MATCH => "If-Match",
+    IF_MODIFIED_SINCE => "If-Modified-Since",
+    IF_NONE_MATCH => "If-None-Match",
+    IF_RANGE => "If-Range",
+    IF_UNMODIFIED_SINCE => "If-Unmodified-Since",
+    KEEP_ALIVE => "Keep-Alive",
+    LAST_MODIFIED => "Last-Modified",
+    LOCATION => "Location",
+    RANGE => "Range",
+    REFERER => "Referer",
+    TRANSFER_ENCODING => "Transfer-Encoding",
+    USER_AGENT => "User-Agent",
+    VARY => "Vary",
+    WWW_AUTHENTICATE => "WWW-Authenticate",
+    X_CONTENT_TYPE_OPTIONS => "X-Content-Type-Options",
+    X_REQUEST_ID => "X-Request-Id",
+}
+
+impl HeaderName {
+    /// Panics if `name` is not a valid field name, which fails the build if
+    /// it is used for a constant.
+    pub const fn from_static(name: &'static str) -> Self {
+        let bytes = name.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            assert!(TCHAR_MAP[bytes[i] as usize] != 0, "invalid field name");
+            i += 1;
+        }
+        assert!(!bytes.is_empty(), "invalid field name");
+        Self(Bytes::from_static(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+}
+
+fn hash_ignoring_case<H: Hasher>(name: &[u8], state: &mut H) {
+    state.write_usize(name.len());
+    for b in name {
+        state.write_u8(b.to_ascii_lowercase());
+    }
+}
+
+impl PartialEq for HeaderName {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+}
+
+impl Eq for HeaderName {}
+
+impl Hash for HeaderName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_ignoring_case(&self.0, state);
+    }
+}
+
+impl Deref for HeaderName {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for HeaderName {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for HeaderName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+impl From<&'static str> for HeaderName {
+    fn from(name: &'static str) -> Self {
+        Self::from_static(name)
+    }
+}
+
+fn check_name(name: &[u8]) -> Result<(), InvalidHeaderName> {
+    if !name.is_empty() && name.iter().all(|&b| TCHAR_MAP[b as usize] != 0) {
+        Ok(())
+    } else {
+        Err(InvalidHeaderName)
+    }
+}
+
+impl TryFrom<&[u8]> for HeaderName {
+    type Error = InvalidHeaderName;
+
+    fn try_from(name: &[u8]) -> Result<Self, Self::Error> {
+        check_name(name)?;
+        Ok(Self(Bytes::copy_from_slice(name)))
+    }
+}
+
+impl TryFrom<Vec<u8>> for HeaderName {
+    type Error = InvalidHeaderName;
+
+    fn try_from(name: Vec<u8>) -> Result<Self, Self::Error> {
+        check_name(&name)?;
+        Ok(Self(name.into()))
+    }
+}
+
+impl TryFrom<String> for HeaderName {
+    type Error = InvalidHeaderName;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        Self::try_from(name.into_bytes())
+    }
+}
+
+impl TryFrom<Bytes> for HeaderName {
+    type Error = InvalidHeaderName;
+
+    fn try_from(name: Bytes) -> Result<Self, Self::Error> {
+        check_name(&name)?;
+        Ok(Self(name))
+    }
+}
+
+impl FromStr for HeaderName {
+    type Err = InvalidHeaderName;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::try_from(name.as_bytes())
+    }
+}
+
+// Looks up fields by a name which is not a `HeaderName`, e.g. `b"host"`.
+struct NameRef<'a>(&'a [u8]);
+
+impl Hash for NameRef<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_ignoring_case(self.0, state);
+    }
+}
+
+impl Equivalent<HeaderName> for NameRef<'_> {
+    fn equivalent(&self, key: &HeaderName) -> bool {
+        self.0.eq_ignore_ascii_case(&key.0)
+    }
+}
+
+const DATE_FIELDS: [HeaderName; 6] = [
+    HeaderName::DATE,
+    HeaderName::LAST_MODIFIED,
+    HeaderName::EXPIRES,
+    HeaderName::IF_RANGE,
+    HeaderName::IF_MODIFIED_SINCE,
+    HeaderName::IF_UNMODIFIED_SINCE,
+];
 
 #[derive(Clone, Copy)]
 pub struct Config<'a> {
@@ -207,7 +415,7 @@ impl<'a> Validator<'a> {
     }
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Value {
     value: Bytes,
     is_valid_ascii: bool,
@@ -292,6 +500,8 @@ impl Value {
         Err(ParsingError::IncorrectlyTerminated)
     }
 
+    // `is_valid_ascii` must only be set if `value` is valid ASCII.
+    #[allow(dead_code)]
     unsafe fn from_raw(value: Bytes, is_valid_ascii: bool) -> Self {
         Self {
             value,
@@ -346,6 +556,7 @@ impl Value {
     }
 }
 
+#[derive(Clone)]
 pub struct Values<'a> {
     first: Value,
     extra: Vec<Value>,
@@ -455,7 +666,7 @@ impl<'a> Values<'a> {
 }
 
 fn config_for_name(name: &[u8]) -> Config<'static> {
-    let map = if DATE_FIELDS.contains(name) {
+    let map = if DATE_FIELDS.iter().any(|n| n.eq_ignore_ascii_case(name)) {
         &DATE_MAP
     } else {
         &TOKEN_MAP
@@ -468,14 +679,14 @@ fn config_for_name(name: &[u8]) -> Config<'static> {
     }
 }
 
-fn field_name_from_bytes(bytes: &mut Bytes) -> Bytes {
+fn field_name_from_bytes(bytes: &mut Bytes) -> HeaderName {
     let field_name_len = bytes
         .iter()
         .copied()
         .take_while(|&c| TCHAR_MAP[c as usize] != 0)
         .take(1024)
         .count();
-    bytes.split_to(field_name_len)
+    HeaderName(bytes.split_to(field_name_len))
 }
 
 fn write_field_to_buffer(buffer: &mut Vec<u8>, name: &[u8], values: &Values) {
@@ -484,7 +695,8 @@ fn write_field_to_buffer(buffer: &mut Vec<u8>, name: &[u8], values: &Values) {
     values.write_to_buffer(buffer);
 }
 
-pub struct Fields(IndexMap<Bytes, Values<'static>>);
+#[derive(Clone)]
+pub struct Fields(IndexMap<HeaderName, Values<'static>>);
 
 impl Fields {
     pub fn new() -> Self {
@@ -496,7 +708,7 @@ impl Fields {
     }
 
     pub fn from_bytes(bytes: &mut Bytes) -> Result<Self, ParsingError> {
-        let mut fields: IndexMap<Bytes, Values> = IndexMap::new();
+        let mut fields: IndexMap<HeaderName, Values> = IndexMap::new();
 
         while !bytes.starts_with(CRLF) && bytes.first().is_some_and(u8::is_ascii_alphanumeric) {
             let name = field_name_from_bytes(bytes);
@@ -538,13 +750,13 @@ impl Fields {
                 for v in &vs[1..] {
                     values.push((*v).to_string().into());
                 }
-                ((*n).to_string().into(), values)
+                (HeaderName::try_from(n.as_bytes()).expect("invalid field name"), values)
             })
             .collect();
         Self(inner)
     }
 
-    pub fn add_header_value(&mut self, name: Bytes, value: Bytes) {
+    pub fn add_header_value(&mut self, name: HeaderName, value: Bytes) {
         if let Some(values) = self.0.get_mut(&name) {
             values.push(value);
         } else {
@@ -553,8 +765,12 @@ impl Fields {
         }
     }
 
+    pub fn remove(&mut self, name: &[u8]) -> Option<Values<'static>> {
+        self.0.shift_remove(&NameRef(name))
+    }
+
     pub fn get(&self, name: &[u8]) -> Option<&Values<'static>> {
-        self.0.get(name)
+        self.0.get(&NameRef(name))
     }
 
     pub fn get_single(&self, name: &[u8]) -> Option<&[u8]> {
@@ -570,23 +786,21 @@ impl Fields {
     }
 
     pub fn contains_name(&self, name: &[u8]) -> bool {
-        self.0.contains_key(name)
+        self.0.contains_key(&NameRef(name))
     }
 
     pub fn contains_value(&self, name: &[u8], value: &[u8]) -> bool {
-        self.0
-            .get(name)
+        self.get(name)
             .is_some_and(|vs| vs.iter_slices().any(|v| v == value))
     }
 
     pub fn contains_value_exact(&self, name: &[u8], value: &[u8]) -> bool {
-        self.0
-            .get(name)
+        self.get(name)
             .is_some_and(|v| v.is_single() && v.first_slice() == value)
     }
 
     pub fn contains_values<const N: usize>(&self, name: &[u8], values: [&[u8]; N]) -> bool {
-        self.0.get(name).is_some_and(|vs| {
+        self.get(name).is_some_and(|vs| {
             values
                 .into_iter()
                 .all(|ex| vs.iter_slices().any(|v| v == ex))
@@ -594,7 +808,7 @@ impl Fields {
     }
 
     pub fn contains_values_exact<const N: usize>(&self, name: &[u8], values: [&[u8]; N]) -> bool {
-        self.0.get(name).is_some_and(|vs| {
+        self.get(name).is_some_and(|vs| {
             vs.count() == values.len()
                 && values
                     .into_iter()
@@ -603,18 +817,31 @@ impl Fields {
     }
 
     pub fn get_slices(&self, name: &[u8]) -> Option<(&[u8], Vec<&[u8]>)> {
-        self.0.get(name).map(Values::get_slices)
+        self.get(name).map(Values::get_slices)
+    }
+
+    /// Returns `None` if the field is missing or its values are invalid.
+    pub fn typed_get<H: TypedHeader>(&self) -> Option<H> {
+        H::decode(self.get(&H::NAME)?)
     }
 
-    pub fn from_inner(inner: IndexMap<Bytes, Values<'static>>) -> Self {
+    /// Replaces all the values of the field.
+    pub fn typed_insert<H: TypedHeader>(&mut self, header: H) {
+        self.remove(&H::NAME);
+        for value in header.encode() {
+            self.add_header_value(H::NAME, value);
+        }
+    }
+
+    pub fn from_inner(inner: IndexMap<HeaderName, Values<'static>>) -> Self {
         Self(inner)
     }
 
-    pub fn as_inner(&self) -> &IndexMap<Bytes, Values<'static>> {
+    pub fn as_inner(&self) -> &IndexMap<HeaderName, Values<'static>> {
         &self.0
     }
 
-    pub fn into_inner(self) -> IndexMap<Bytes, Values<'static>> {
+    pub fn into_inner(self) -> IndexMap<HeaderName, Values<'static>> {
         self.0
     }
 
@@ -792,6 +1019,71 @@ pub(crate) mod test {
         assert_headers(&actual, &CHROME_INTERNAL);
     }
 
+    #[test]
+    fn names_ignore_case() {
+        let mut bytes =
+            "content-type: text/plain\r\nACCEPT: */*\r\nAccept: text/html\r\n\r\n".into();
+        let fields = Fields::from_bytes(&mut bytes).unwrap();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(
+            fields.get_single(&HeaderName::CONTENT_TYPE),
+            Some(&b"text/plain"[..])
+        );
+        assert!(fields.contains_values_exact(b"accept", [b"*/*", b"text/html"]));
+        assert_eq!(
+            HeaderName::from("x-custom"),
+            HeaderName::try_from("X-Custom".to_string()).unwrap()
+        );
+    }
+
+    #[test]
+    fn names_validated() {
+        for valid in ["Content-Type", "x-custom_1", "!#$%&'*+.^`|~"] {
+            let name: HeaderName = valid.parse().unwrap();
+            assert_eq!(name.as_bytes(), valid.as_bytes());
+        }
+
+        for invalid in [
+            "",
+            "Content Type",
+            "Host:",
+            " Host",
+            "X-(a)",
+            "X-\"a\"",
+            "caf\u{e9}",
+        ] {
+            assert!(invalid.parse::<HeaderName>().is_err(), "{invalid:?}");
+            assert!(HeaderName::try_from(invalid.as_bytes().to_vec()).is_err());
+            assert!(HeaderName::try_from(invalid.to_string()).is_err());
+            assert!(HeaderName::try_from(Bytes::from(invalid)).is_err());
+        }
+
+        assert!(HeaderName::try_from(&b"X-Line\r\nInjected: 1"[..]).is_err());
+        assert_eq!(
+            HeaderName::try_from(&b"etag"[..]).unwrap(),
+            HeaderName::ETAG
+        );
+    }
+
+    #[test]
+    #[should_panic = "invalid field name"]
+    fn static_name_validated() {
+        let _ = HeaderName::from("X-Line\r\nInjected: 1");
+    }
+
+    #[test]
+    fn names_keep_casing() {
+        let mut fields = Fields::new();
+        fields.add_header_value("x-lower".into(), "1".into());
+        fields.add_header_value(HeaderName::ETAG, "\"a\"".into());
+        fields.add_header_value("X-LOWER".into(), "2".into());
+        let actual = String::from_utf8(fields.to_buffer()).unwrap();
+        assert_eq!(actual, "x-lower: 1, 2\r\nETag: \"a\"\r\n\r\n");
+
+        assert!(fields.remove(b"etag").is_some());
+        assert!(!fields.contains_name(&HeaderName::ETAG));
+    }
+
     #[test]
     fn chrome_to_buffer() {
         let actual = String::from_utf8(Fields::copy_from_str(CHROME_INTERNAL).to_buffer()).unwrap();
@@ -0,0 +1,284 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::transcode::percent_decode;
+
+#[cfg(feature = "serde")]
+mod de;
+#[cfg(feature = "serde")]
+pub use de::Error as DeserializeError;
+
+pub const CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParsingError {
+    InvalidPercentEncoding,
+    InvalidUtf8,
+    UnexpectedContentType,
+}
+
+impl ParsingError {
+    pub const fn as_str(self) -> &'static str {
+        use ParsingError::*;
+        match self {
+            InvalidPercentEncoding => "invalid percent-encoding",
+            InvalidUtf8 => "decoded data is not valid UTF-8",
+            UnexpectedContentType => "body is not application/x-www-form-urlencoded",
+        }
+    }
+}
+
+impl fmt::Display for ParsingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::error::Error for ParsingError {}
+
+/// Name-value pairs of a query string or a form post, in the order they were
+/// sent. The same name can occur more than once, e.g. `tag=a&tag=b`. See
+/// https://url.spec.whatwg.org/#application/x-www-form-urlencoded
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct FormData(Vec<(String, String)>);
+
+impl FormData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes `a=1&b=x+y`, where `+` stands for a space. Empty pairs are
+    /// skipped and a pair without `=` has an empty value.
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParsingError> {
+        let mut pairs = Vec::new();
+        for pair in bytes.split(|&b| b == b'&') {
+            if pair.is_empty() {
+                continue;
+            }
+
+            let (name, value) = match pair.iter().position(|&b| b == b'=') {
+                Some(eq) => (&pair[..eq], &pair[eq + 1..]),
+                None => (pair, &[][..]),
+            };
+
+            pairs.push((decode(name)?, decode(value)?));
+        }
+
+        Ok(Self(pairs))
+    }
+
+    /// The first value of the name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.iter()
+            .filter(move |(n, _)| *n == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.0.push((name.into(), value.into()));
+    }
+
+    /// Removes all the values of the name.
+    pub fn remove(&mut self, name: &str) {
+        self.0.retain(|(n, _)| n != name);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Encodes the pairs, the reverse of `parse`.
+    pub fn write_to_buffer(&self, buffer: &mut Vec<u8>) {
+        for (i, (name, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                buffer.push(b'&');
+            }
+
+            encode(buffer, name.as_bytes());
+            buffer.push(b'=');
+            encode(buffer, value.as_bytes());
+        }
+    }
+
+    /// Deserializes the pairs into e.g. a struct with a field for every name.
+    /// Fields of sequence types collect all the values of their name, other
+    /// fields take the first one.
+    #[cfg(feature = "serde")]
+    pub fn deserialize<'de, T: serde::Deserialize<'de>>(&'de self) -> Result<T, DeserializeError> {
+        de::from_form(self)
+    }
+}
+
+impl FromStr for FormData {
+    type Err = ParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s.as_bytes())
+    }
+}
+
+impl fmt::Display for FormData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buffer = Vec::new();
+        self.write_to_buffer(&mut buffer);
+        // only ASCII is written
+        f.write_str(std::str::from_utf8(&buffer).map_err(|_| fmt::Error)?)
+    }
+}
+
+impl<N: Into<String>, V: Into<String>> FromIterator<(N, V)> for FormData {
+    fn from_iter<I: IntoIterator<Item = (N, V)>>(iter: I) -> Self {
+        Self(
+            iter.into_iter()
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect(),
+        )
+    }
+}
+
+impl IntoIterator for FormData {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+fn decode(bytes: &[u8]) -> Result<String, ParsingError> {
+    let plus_decoded: Vec<u8> = bytes
+        .iter()
+        .map(|&b| if b == b'+' { b' ' } else { b })
+        .collect();
+    let decoded =
+        percent_decode(&plus_decoded).map_err(|_| ParsingError::InvalidPercentEncoding)?;
+    String::from_utf8(decoded).map_err(|_| ParsingError::InvalidUtf8)
+}
+
+// Leaves alphanumerics and `*-._` as they are, like browsers do.
+fn encode(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    for &b in bytes {
+        match b {
+            b' ' => buffer.push(b'+'),
+            b'*' | b'-' | b'.' | b'_' => buffer.push(b),
+            b if b.is_ascii_alphanumeric() => buffer.push(b),
+            _ => buffer.extend_from_slice(&[
+                b'%',
+                HEX[usize::from(b >> 4)],
+                HEX[usize::from(b & 0xf)],
+            ]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let form: FormData = "a=1&b=x+y&a=%C5%82%26&&flag&=empty".parse().unwrap();
+        let pairs: Vec<_> = form.iter().collect();
+        assert_eq!(
+            pairs,
+            [
+                ("a", "1"),
+                ("b", "x y"),
+                ("a", "ł&"),
+                ("flag", ""),
+                ("", "empty"),
+            ]
+        );
+
+        assert_eq!(form.get("a"), Some("1"));
+        assert_eq!(form.get_all("a").collect::<Vec<_>>(), ["1", "ł&"]);
+        assert_eq!(form.get("c"), None);
+        assert!(form.contains("flag"));
+
+        assert_eq!(
+            FormData::parse(b"a=%zz"),
+            Err(ParsingError::InvalidPercentEncoding)
+        );
+        assert_eq!(FormData::parse(b"a=%ff"), Err(ParsingError::InvalidUtf8));
+        assert!(FormData::parse(b"").unwrap().is_empty());
+    }
+
+    #[test]
+    fn serialize() {
+        let mut form: FormData = [("q", "rust http"), ("lang", "pl")].into_iter().collect();
+        form.append("x", "a+b=ł/~*");
+        assert_eq!(
+            form.to_string(),
+            "q=rust+http&lang=pl&x=a%2Bb%3D%C5%82%2F%7E*"
+        );
+        assert_eq!(form.to_string().parse::<FormData>().unwrap(), form);
+
+        form.remove("q");
+        assert_eq!(form.len(), 2);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize() {
+        use serde::Deserialize;
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        #[serde(rename_all = "lowercase")]
+        enum Order {
+            Asc,
+            Desc,
+        }
+
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Search<'a> {
+            q: &'a str,
+            page: u32,
+            #[serde(default)]
+            tag: Vec<String>,
+            order: Option<Order>,
+            limit: Option<u8>,
+            exact: bool,
+        }
+
+        let form: FormData = "q=a+b&page=2&tag=x&tag=y&order=desc&limit=&exact=on"
+            .parse()
+            .unwrap();
+        let search: Search = form.deserialize().unwrap();
+        assert_eq!(
+            search,
+            Search {
+                q: "a b",
+                page: 2,
+                tag: vec!["x".to_string(), "y".to_string()],
+                order: Some(Order::Desc),
+                limit: None,
+                exact: true,
+            }
+        );
+
+        let form: FormData = "q=a&page=two&exact=false".parse().unwrap();
+        let err = form.deserialize::<Search>().unwrap_err();
+        assert!(err.to_string().starts_with("page: "), "{err}");
+    }
+}
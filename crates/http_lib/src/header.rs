@@ -0,0 +1,676 @@
+use std::str;
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use mime::Mime;
+
+use crate::chars::{QUOTED_TEXT_MAP, TCHAR_MAP};
+use crate::etag::{EntityTag, EntityTagList};
+use crate::field::{HeaderName, Values};
+use crate::uri::Authority;
+use crate::{Method, Uri};
+
+/// A field with a parsed value, see `Fields::typed_get` and
+/// `Fields::typed_insert`.
+pub trait TypedHeader: Sized {
+    const NAME: HeaderName;
+
+    /// Returns `None` if the values are not valid for the field.
+    fn decode(values: &Values) -> Option<Self>;
+
+    /// Elements of a list are separate values, as if the field was parsed.
+    fn encode(&self) -> Vec<Bytes>;
+}
+
+macro_rules! date_headers {
+    ($($ty:ident => $name:ident,)*) => {
+        $(
+            #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+            pub struct $ty(pub SystemTime);
+
+            impl TypedHeader for $ty {
+                const NAME: HeaderName = HeaderName::$name;
+
+                fn decode(values: &Values) -> Option<Self> {
+                    let value = String::from_utf8(values.to_buffer()).ok()?;
+                    httpdate::parse_http_date(&value).ok().map(Self)
+                }
+
+                fn encode(&self) -> Vec<Bytes> {
+                    vec![httpdate::fmt_http_date(self.0).into()]
+                }
+            }
+        )*
+    };
+}
+
+date_headers! {
+    Date => DATE,
+    LastModified => LAST_MODIFIED,
+    Expires => EXPIRES,
+}
+
+#[inline]
+fn is_token(bytes: &[u8]) -> bool {
+    !bytes.is_empty() && bytes.iter().all(|&b| TCHAR_MAP[b as usize] != 0)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ContentLength(pub u64);
+
+impl TypedHeader for ContentLength {
+    const NAME: HeaderName = HeaderName::CONTENT_LENGTH;
+
+    // a list of identical values is allowed, see
+    // https://httpwg.org/specs/rfc9110.html#field.content-length
+    fn decode(values: &Values) -> Option<Self> {
+        let first = values.first_slice();
+        if first.is_empty()
+            || !first.iter().all(u8::is_ascii_digit)
+            || values.iter_slices().any(|v| v != first)
+        {
+            return None;
+        }
+
+        str::from_utf8(first).ok()?.parse().ok().map(Self)
+    }
+
+    fn encode(&self) -> Vec<Bytes> {
+        vec![self.0.to_string().into()]
+    }
+}
+
+/// The media type of the content, parameters such as `charset` are kept.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ContentType(pub Mime);
+
+impl TypedHeader for ContentType {
+    const NAME: HeaderName = HeaderName::CONTENT_TYPE;
+
+    fn decode(values: &Values) -> Option<Self> {
+        if !values.is_single() {
+            return None;
+        }
+
+        str::from_utf8(values.first_slice())
+            .ok()?
+            .parse()
+            .ok()
+            .map(Self)
+    }
+
+    fn encode(&self) -> Vec<Bytes> {
+        vec![self.0.to_string().into()]
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Host(pub Authority);
+
+impl TypedHeader for Host {
+    const NAME: HeaderName = HeaderName::HOST;
+
+    fn decode(values: &Values) -> Option<Self> {
+        if !values.is_single() {
+            return None;
+        }
+
+        str::from_utf8(values.first_slice())
+            .ok()?
+            .parse()
+            .ok()
+            .map(Self)
+    }
+
+    fn encode(&self) -> Vec<Bytes> {
+        vec![self.0.to_string().into()]
+    }
+}
+
+/// Connection options, stored in lowercase as they are case-insensitive.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Connection(Vec<String>);
+
+impl Connection {
+    pub fn close() -> Self {
+        Self(vec!["close".to_string()])
+    }
+
+    pub fn keep_alive() -> Self {
+        Self(vec!["keep-alive".to_string()])
+    }
+
+    pub fn contains(&self, option: &str) -> bool {
+        self.0.iter().any(|o| o.eq_ignore_ascii_case(option))
+    }
+
+    pub fn is_close(&self) -> bool {
+        self.contains("close")
+    }
+
+    pub fn is_keep_alive(&self) -> bool {
+        self.contains("keep-alive")
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+}
+
+impl TypedHeader for Connection {
+    const NAME: HeaderName = HeaderName::CONNECTION;
+
+    fn decode(values: &Values) -> Option<Self> {
+        values
+            .iter_slices()
+            .map(|v| is_token(v).then(|| String::from_utf8_lossy(v).to_ascii_lowercase()))
+            .collect::<Option<_>>()
+            .map(Self)
+    }
+
+    fn encode(&self) -> Vec<Bytes> {
+        self.0.iter().map(|o| o.clone().into()).collect()
+    }
+}
+
+/// Cache directives, see https://httpwg.org/specs/rfc9111.html#field.cache-control
+/// Names are stored in lowercase and values without quotes.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct CacheControl(Vec<(String, Option<String>)>);
+
+impl CacheControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a directive without a value, e.g. `no-store`.
+    #[must_use]
+    pub fn with(mut self, name: &str) -> Self {
+        self.0.push((name.to_ascii_lowercase(), None));
+        self
+    }
+
+    /// Adds a directive with a value, e.g. `max-age=60`.
+    #[must_use]
+    pub fn with_value(mut self, name: &str, value: impl ToString) -> Self {
+        self.0
+            .push((name.to_ascii_lowercase(), Some(value.to_string())));
+        self
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.iter().any(|(n, _)| n.eq_ignore_ascii_case(name))
+    }
+
+    /// The value of the first directive with the name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_deref()))
+    }
+
+    pub fn max_age(&self) -> Option<Duration> {
+        self.seconds("max-age")
+    }
+
+    pub fn s_maxage(&self) -> Option<Duration> {
+        self.seconds("s-maxage")
+    }
+
+    pub fn is_no_cache(&self) -> bool {
+        self.contains("no-cache")
+    }
+
+    pub fn is_no_store(&self) -> bool {
+        self.contains("no-store")
+    }
+
+    pub fn is_public(&self) -> bool {
+        self.contains("public")
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.contains("private")
+    }
+
+    pub fn is_immutable(&self) -> bool {
+        self.contains("immutable")
+    }
+
+    pub fn must_revalidate(&self) -> bool {
+        self.contains("must-revalidate")
+    }
+
+    fn seconds(&self, name: &str) -> Option<Duration> {
+        let value = self.get(name)?;
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        // values too large to represent are treated as the largest one, see
+        // https://httpwg.org/specs/rfc9111.html#delta-seconds
+        let seconds = value.parse().unwrap_or(u64::MAX);
+        Some(Duration::from_secs(seconds))
+    }
+}
+
+impl TypedHeader for CacheControl {
+    const NAME: HeaderName = HeaderName::CACHE_CONTROL;
+
+    fn decode(values: &Values) -> Option<Self> {
+        let mut directives = Vec::with_capacity(values.count());
+        for directive in values.iter_slices() {
+            let (name, value) = match directive.iter().position(|&b| b == b'=') {
+                Some(eq) => (&directive[..eq], Some(&directive[eq + 1..])),
+                None => (directive, None),
+            };
+
+            if !is_token(name) {
+                return None;
+            }
+
+            let value = match value {
+                Some(value) => Some(String::from_utf8(unquote(value)?).ok()?),
+                None => None,
+            };
+            directives.push((String::from_utf8_lossy(name).to_ascii_lowercase(), value));
+        }
+
+        Some(Self(directives))
+    }
+
+    fn encode(&self) -> Vec<Bytes> {
+        self.0
+            .iter()
+            .map(|(name, value)| {
+                let mut buffer = name.clone().into_bytes();
+                if let Some(value) = value {
+                    buffer.push(b'=');
+                    write_quoted_if_needed(&mut buffer, value.as_bytes());
+                }
+                buffer.into()
+            })
+            .collect()
+    }
+}
+
+// Accepts a token or a quoted string with backslash escapes.
+fn unquote(value: &[u8]) -> Option<Vec<u8>> {
+    let Some(quoted) = value.strip_prefix(b"\"") else {
+        return is_token(value).then(|| value.to_vec());
+    };
+
+    let mut unquoted = Vec::with_capacity(quoted.len());
+    let mut bytes = quoted.iter().copied();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'"' => return bytes.next().is_none().then_some(unquoted),
+            b'\\' => unquoted.push(bytes.next()?),
+            b => unquoted.push(b),
+        }
+    }
+
+    None
+}
+
+fn write_quoted_if_needed(buffer: &mut Vec<u8>, value: &[u8]) {
+    if is_token(value) {
+        buffer.extend_from_slice(value);
+        return;
+    }
+
+    buffer.push(b'"');
+    for &b in value {
+        if b == b'"' || b == b'\\' || QUOTED_TEXT_MAP[b as usize] == 0 {
+            buffer.push(b'\\');
+        }
+        buffer.push(b);
+    }
+    buffer.push(b'"');
+}
+
+/// Methods supported by a resource. Methods which `Method` does not know are
+/// skipped.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Allow(pub Vec<Method>);
+
+impl TypedHeader for Allow {
+    const NAME: HeaderName = HeaderName::ALLOW;
+
+    fn decode(values: &Values) -> Option<Self> {
+        // an empty value means no methods are allowed
+        if values.is_single() && values.first_slice().is_empty() {
+            return Some(Self(Vec::new()));
+        }
+
+        let mut methods = Vec::with_capacity(values.count());
+        for value in values.iter_slices() {
+            if !is_token(value) {
+                return None;
+            }
+
+            let mut bytes = Bytes::copy_from_slice(value);
+            match Method::from_bytes(&mut bytes) {
+                Some(method) if bytes.is_empty() => methods.push(method),
+                _ => {}
+            }
+        }
+
+        Some(Self(methods))
+    }
+
+    fn encode(&self) -> Vec<Bytes> {
+        if self.0.is_empty() {
+            return vec![Bytes::new()];
+        }
+
+        self.0
+            .iter()
+            .map(|m| Bytes::from_static(m.as_bytes()))
+            .collect()
+    }
+}
+
+/// Only absolute URIs and absolute paths are supported, other relative
+/// references are not valid request-targets.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Location(pub Uri);
+
+impl TypedHeader for Location {
+    const NAME: HeaderName = HeaderName::LOCATION;
+
+    // commas are allowed in URIs, but they split the field into values
+    fn decode(values: &Values) -> Option<Self> {
+        let uri = values.iter_slices().collect::<Vec<_>>().join(&b',');
+        Uri::parse(&uri).ok().map(Self)
+    }
+
+    fn encode(&self) -> Vec<Bytes> {
+        vec![self.0.to_string().into()]
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ETag(pub EntityTag);
+
+impl TypedHeader for ETag {
+    const NAME: HeaderName = HeaderName::ETAG;
+
+    fn decode(values: &Values) -> Option<Self> {
+        if !values.is_single() {
+            return None;
+        }
+
+        EntityTag::from_bytes(values.first_slice()).map(Self)
+    }
+
+    fn encode(&self) -> Vec<Bytes> {
+        vec![self.0.to_buffer().into()]
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct IfMatch(pub EntityTagList);
+
+impl TypedHeader for IfMatch {
+    const NAME: HeaderName = HeaderName::IF_MATCH;
+
+    fn decode(values: &Values) -> Option<Self> {
+        EntityTagList::from_values(values).map(Self)
+    }
+
+    fn encode(&self) -> Vec<Bytes> {
+        encode_tags(&self.0)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct IfNoneMatch(pub EntityTagList);
+
+impl TypedHeader for IfNoneMatch {
+    const NAME: HeaderName = HeaderName::IF_NONE_MATCH;
+
+    fn decode(values: &Values) -> Option<Self> {
+        EntityTagList::from_values(values).map(Self)
+    }
+
+    fn encode(&self) -> Vec<Bytes> {
+        encode_tags(&self.0)
+    }
+}
+
+fn encode_tags(tags: &EntityTagList) -> Vec<Bytes> {
+    match tags {
+        EntityTagList::Any => vec![Bytes::from_static(b"*")],
+        EntityTagList::Tags(tags) => tags.iter().map(|t| t.to_buffer().into()).collect(),
+    }
+}
+
+/// Request fields which the content depends on, `*` stands for anything
+/// other than the request-target.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Vary {
+    Any,
+    Fields(Vec<HeaderName>),
+}
+
+impl Vary {
+    pub fn contains(&self, name: &[u8]) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Fields(names) => names.iter().any(|n| n.eq_ignore_ascii_case(name)),
+        }
+    }
+}
+
+impl TypedHeader for Vary {
+    const NAME: HeaderName = HeaderName::VARY;
+
+    fn decode(values: &Values) -> Option<Self> {
+        if values.iter_slices().any(|v| v == b"*") {
+            return Some(Self::Any);
+        }
+
+        values
+            .iter_slices()
+            .map(|v| HeaderName::try_from(v).ok())
+            .collect::<Option<_>>()
+            .map(Self::Fields)
+    }
+
+    fn encode(&self) -> Vec<Bytes> {
+        match self {
+            Self::Any => vec![Bytes::from_static(b"*")],
+            Self::Fields(names) => names.iter().map(|n| n.clone().into_bytes()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Fields;
+
+    // the field value as it is written
+    fn encoded<H: TypedHeader>(header: H) -> String {
+        let mut fields = Fields::new();
+        fields.typed_insert(header);
+        String::from_utf8(fields.get(&H::NAME).unwrap().to_buffer()).unwrap()
+    }
+
+    #[test]
+    fn content() {
+        let mut fields = Fields::copy_from_str([
+            ("Content-Length", &["42", "42"]),
+            ("Content-Type", &["text/html; charset=\"utf-8\""]),
+        ]);
+
+        assert_eq!(fields.typed_get(), Some(ContentLength(42)));
+        let ContentType(mime) = fields.typed_get().unwrap();
+        assert_eq!(mime.essence_str(), "text/html");
+        assert_eq!(mime.get_param(mime::CHARSET), Some(mime::UTF_8));
+
+        fields.typed_insert(ContentLength(7));
+        assert_eq!(
+            fields.get_slices(b"content-length"),
+            Some((&b"7"[..], vec![]))
+        );
+        fields.typed_insert(ContentType(mime::APPLICATION_JSON));
+        assert_eq!(
+            fields.get_single(b"content-type"),
+            Some(&b"application/json"[..])
+        );
+
+        for invalid in ["", "-1", "+1", "4 2", "18446744073709551616"] {
+            let fields = Fields::copy_from_str([("Content-Length", &[invalid])]);
+            assert_eq!(fields.typed_get::<ContentLength>(), None, "{invalid}");
+        }
+        let fields = Fields::copy_from_str([("Content-Length", &["1", "2"])]);
+        assert_eq!(fields.typed_get::<ContentLength>(), None);
+    }
+
+    #[test]
+    fn dates() {
+        let fields = Fields::copy_from_str([
+            ("Date", &["Sun, 06 Nov 1994 08:49:37 GMT"]),
+            ("Expires", &["0"]),
+        ]);
+
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(fields.typed_get(), Some(Date(time)));
+        assert_eq!(fields.typed_get::<Expires>(), None);
+        assert_eq!(fields.typed_get::<LastModified>(), None);
+
+        let mut fields = Fields::new();
+        fields.typed_insert(LastModified(time));
+        assert_eq!(fields.typed_get(), Some(LastModified(time)));
+        assert_eq!(
+            fields.get_single(b"last-modified"),
+            Some(&b"Sun, 06 Nov 1994 08:49:37 GMT"[..])
+        );
+    }
+
+    #[test]
+    fn host_and_location() {
+        let mut fields = Fields::copy_from_str([
+            ("Host", &["Example.com:8080"]),
+            ("Location", &["/a,b?c=1,2"]),
+        ]);
+
+        let Host(authority) = fields.typed_get().unwrap();
+        assert_eq!(authority.host(), "example.com");
+        assert_eq!(authority.port(), Some(8080));
+
+        let Location(uri) = fields.typed_get().unwrap();
+        assert_eq!(uri.path(), "/a,b");
+        assert_eq!(uri.query(), Some("c=1,2"));
+
+        fields.typed_insert(Location("http://example.com/x".parse().unwrap()));
+        assert_eq!(
+            fields.get_single(b"location"),
+            Some(&b"http://example.com/x"[..])
+        );
+
+        let fields = Fields::copy_from_str([("Host", &["a.com", "b.com"])]);
+        assert_eq!(fields.typed_get::<Host>(), None);
+    }
+
+    #[test]
+    fn connection() {
+        let fields = Fields::copy_from_str([("Connection", &["Keep-Alive", "Upgrade"])]);
+        let connection: Connection = fields.typed_get().unwrap();
+        assert!(connection.is_keep_alive() && !connection.is_close());
+        assert!(connection.contains("upgrade"));
+        assert_eq!(
+            connection.iter().collect::<Vec<_>>(),
+            ["keep-alive", "upgrade"]
+        );
+
+        let mut fields = Fields::new();
+        fields.typed_insert(Connection::close());
+        assert!(fields.typed_get::<Connection>().unwrap().is_close());
+    }
+
+    #[test]
+    fn cache_control() {
+        let fields = Fields::copy_from_str([(
+            "Cache-Control",
+            &["Max-Age=60", "must-revalidate", "private=\"Set-Cookie, X\""],
+        )]);
+
+        let cache_control: CacheControl = fields.typed_get().unwrap();
+        assert_eq!(cache_control.max_age(), Some(Duration::from_secs(60)));
+        assert_eq!(cache_control.s_maxage(), None);
+        assert!(cache_control.must_revalidate() && !cache_control.is_no_store());
+        assert_eq!(cache_control.get("private"), Some("Set-Cookie, X"));
+
+        let cache_control = CacheControl::new()
+            .with("public")
+            .with_value("max-age", 3600)
+            .with_value("community", "a \"b\"");
+        assert_eq!(
+            encoded(cache_control.clone()),
+            "public, max-age=3600, community=\"a \\\"b\\\"\""
+        );
+
+        let mut fields = Fields::new();
+        fields.typed_insert(cache_control.clone());
+        assert_eq!(fields.typed_get(), Some(cache_control));
+
+        let fields = Fields::copy_from_str([("Cache-Control", &["max-age=\"60"])]);
+        assert_eq!(fields.typed_get::<CacheControl>(), None);
+    }
+
+    #[test]
+    fn allow() {
+        let fields = Fields::copy_from_str([("Allow", &["GET", "HEAD", "PROPFIND"])]);
+        assert_eq!(
+            fields.typed_get(),
+            Some(Allow(vec![Method::Get, Method::Head]))
+        );
+
+        let fields = Fields::copy_from_str([("Allow", &[""])]);
+        assert_eq!(fields.typed_get(), Some(Allow(vec![])));
+
+        assert_eq!(encoded(Allow(vec![Method::Get, Method::Post])), "GET, POST");
+        assert_eq!(encoded(Allow(vec![])), "");
+    }
+
+    #[test]
+    fn entity_tags() {
+        let mut fields = Fields::copy_from_str([
+            ("ETag", &["W/\"a\""]),
+            ("If-None-Match", &["\"a\"", "\"b\""]),
+            ("If-Match", &["*"]),
+        ]);
+
+        let etag = EntityTag::weak("a").unwrap();
+        assert_eq!(fields.typed_get(), Some(ETag(etag.clone())));
+        assert_eq!(fields.typed_get(), Some(IfMatch(EntityTagList::Any)));
+
+        let IfNoneMatch(tags) = fields.typed_get().unwrap();
+        assert!(tags.any_weak_eq(&etag));
+        assert_eq!(encoded(IfNoneMatch(tags)), "\"a\", \"b\"");
+
+        fields.typed_insert(ETag(EntityTag::strong("c").unwrap()));
+        assert_eq!(fields.get_single(b"etag"), Some(&b"\"c\""[..]));
+    }
+
+    #[test]
+    fn vary() {
+        let fields = Fields::copy_from_str([("Vary", &["accept-encoding", "Origin"])]);
+        let vary: Vary = fields.typed_get().unwrap();
+        assert!(vary.contains(b"Accept-Encoding") && !vary.contains(b"Accept"));
+        assert_eq!(encoded(vary), "accept-encoding, Origin");
+
+        let fields = Fields::copy_from_str([("Vary", &["Origin", "*"])]);
+        assert_eq!(fields.typed_get(), Some(Vary::Any));
+    }
+}
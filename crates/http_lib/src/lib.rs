@@ -1,16 +1,28 @@
 pub mod chars;
+pub mod chunked;
+pub mod coding;
+pub mod conditional;
+pub mod etag;
 pub mod field;
+pub mod form;
+pub mod header;
 pub mod method;
+pub mod multipart;
+pub mod range;
 pub mod request;
 pub mod response;
 pub mod version;
 pub mod transcode;
+pub mod uri;
 
-pub use field::Fields;
+pub use field::{Fields, HeaderName};
+pub use form::FormData;
+pub use header::TypedHeader;
 pub use version::Version;
 pub use method::Method;
 pub use request::Request;
 pub use response::Response;
+pub use uri::Uri;
 
 mod macros;
 mod advance;
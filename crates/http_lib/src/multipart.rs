@@ -0,0 +1,503 @@
+use std::fmt;
+
+use bytes::{Buf as _, Bytes, BytesMut};
+
+use crate::chars::CRLF;
+use crate::transcode::percent_decode;
+use crate::{field, Fields, HeaderName};
+
+// A delimiter line longer than this is not padded, but malformed.
+const MAX_PADDING_LEN: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParsingError {
+    NotMultipart,
+    InvalidBoundary,
+    InvalidDelimiter,
+    HeadTooLong,
+    Header(field::ParsingError),
+    PartTooLong,
+    TooManyParts,
+}
+
+impl ParsingError {
+    pub const fn as_str(self) -> &'static str {
+        use ParsingError::*;
+        match self {
+            NotMultipart => "body is not multipart/form-data",
+            InvalidBoundary => "missing or invalid boundary",
+            InvalidDelimiter => "malformed delimiter line",
+            HeadTooLong => "headers of a part exceed the size limit",
+            Header(_) => "malformed header of a part",
+            PartTooLong => "part exceeds the size limit",
+            TooManyParts => "too many parts",
+        }
+    }
+}
+
+impl fmt::Display for ParsingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::error::Error for ParsingError {}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Of the headers of a single part.
+    pub max_head_len: usize,
+    /// Of the body of a single part.
+    pub max_part_len: usize,
+    pub max_parts: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_head_len: 8 * 1024,
+            max_part_len: usize::MAX,
+            max_parts: 128,
+        }
+    }
+}
+
+/// The headers of a part, along with the field name and file name from its
+/// `Content-Disposition`.
+#[derive(Clone)]
+pub struct Part {
+    pub headers: Fields,
+    name: Option<String>,
+    filename: Option<String>,
+}
+
+impl Part {
+    fn new(headers: Fields) -> Self {
+        let (name, filename) = headers
+            .get_single(&HeaderName::CONTENT_DISPOSITION)
+            .map(parse_disposition)
+            .unwrap_or_default();
+
+        Self {
+            headers,
+            name,
+            filename,
+        }
+    }
+
+    /// The name of the form field.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The name of the uploaded file as sent by the client. It may contain
+    /// anything, including slashes, so it should not be used as a path as is.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn content_type(&self) -> Option<&[u8]> {
+        self.headers.get_single(&HeaderName::CONTENT_TYPE)
+    }
+}
+
+pub enum Event {
+    /// The headers of the next part.
+    Part(Part),
+    /// A piece of the body of the current part.
+    Data(Bytes),
+    /// The current part has been received in whole.
+    PartEnd,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Preamble,
+    // right after a boundary, before the rest of its line
+    Delimiter,
+    Head,
+    Body { len: usize },
+    Finished,
+}
+
+/// An incremental parser of `multipart/form-data` bodies, which hands out the
+/// bodies of the parts as they arrive, see
+/// https://www.rfc-editor.org/rfc/rfc7578 and
+/// https://www.rfc-editor.org/rfc/rfc2046#section-5.1.1
+pub struct Parser {
+    // `\r\n--` followed by the boundary
+    delimiter: Vec<u8>,
+    state: State,
+    parts: usize,
+    limits: Limits,
+}
+
+impl Parser {
+    pub fn new(boundary: &[u8], limits: Limits) -> Self {
+        let mut delimiter = Vec::with_capacity(boundary.len() + 4);
+        delimiter.extend_from_slice(CRLF);
+        delimiter.extend_from_slice(b"--");
+        delimiter.extend_from_slice(boundary);
+
+        Self {
+            delimiter,
+            state: State::Preamble,
+            parts: 0,
+            limits,
+        }
+    }
+
+    /// Takes the boundary from the `Content-Type` of the body.
+    pub fn from_content_type(content_type: &[u8], limits: Limits) -> Result<Self, ParsingError> {
+        Ok(Self::new(&boundary(content_type)?, limits))
+    }
+
+    /// Whether the close delimiter has been received. Anything following it is
+    /// left in the buffer.
+    pub fn is_finished(&self) -> bool {
+        self.state == State::Finished
+    }
+
+    /// Consumes `buffer` up to the next event. Returns `None` if more data is
+    /// needed, or once the body is finished.
+    pub fn next_event(&mut self, buffer: &mut BytesMut) -> Result<Option<Event>, ParsingError> {
+        loop {
+            match self.state {
+                State::Preamble => {
+                    // the first boundary does not need to follow a line break
+                    let dash_boundary = &self.delimiter[CRLF.len()..];
+                    if buffer.starts_with(dash_boundary) {
+                        buffer.advance(dash_boundary.len());
+                        self.state = State::Delimiter;
+                        continue;
+                    }
+
+                    match find(buffer, &self.delimiter) {
+                        Some(pos) => {
+                            buffer.advance(pos + self.delimiter.len());
+                            self.state = State::Delimiter;
+                        }
+                        None => {
+                            let keep = buffer.len().min(self.delimiter.len() - 1);
+                            buffer.advance(buffer.len() - keep);
+                            return Ok(None);
+                        }
+                    }
+                }
+                State::Delimiter => {
+                    if buffer.len() < 2 {
+                        return Ok(None);
+                    }
+
+                    if buffer.starts_with(b"--") {
+                        buffer.advance(2);
+                        self.state = State::Finished;
+                        return Ok(None);
+                    }
+
+                    let Some(line_len) = find(buffer, CRLF) else {
+                        return if buffer.len() > MAX_PADDING_LEN {
+                            Err(ParsingError::InvalidDelimiter)
+                        } else {
+                            Ok(None)
+                        };
+                    };
+
+                    if !buffer[..line_len].iter().all(|&b| b == b' ' || b == b'\t') {
+                        return Err(ParsingError::InvalidDelimiter);
+                    }
+
+                    self.parts += 1;
+                    if self.parts > self.limits.max_parts {
+                        return Err(ParsingError::TooManyParts);
+                    }
+
+                    buffer.advance(line_len + CRLF.len());
+                    self.state = State::Head;
+                }
+                State::Head => {
+                    let head_len = if buffer.starts_with(CRLF) {
+                        Some(CRLF.len())
+                    } else {
+                        find(buffer, b"\r\n\r\n").map(|pos| pos + 4)
+                    };
+
+                    let Some(head_len) = head_len else {
+                        return if buffer.len() > self.limits.max_head_len {
+                            Err(ParsingError::HeadTooLong)
+                        } else {
+                            Ok(None)
+                        };
+                    };
+
+                    if head_len > self.limits.max_head_len {
+                        return Err(ParsingError::HeadTooLong);
+                    }
+
+                    let mut head = buffer.split_to(head_len).freeze();
+                    let headers = Fields::from_bytes(&mut head).map_err(ParsingError::Header)?;
+                    self.state = State::Body { len: 0 };
+                    return Ok(Some(Event::Part(Part::new(headers))));
+                }
+                State::Body { len } => {
+                    let data_len = match find(buffer, &self.delimiter) {
+                        Some(0) => {
+                            buffer.advance(self.delimiter.len());
+                            self.state = State::Delimiter;
+                            return Ok(Some(Event::PartEnd));
+                        }
+                        Some(pos) => pos,
+                        // the end of the buffer might be the start of a delimiter
+                        None => buffer.len().saturating_sub(self.delimiter.len() - 1),
+                    };
+
+                    if data_len == 0 {
+                        return Ok(None);
+                    }
+
+                    let len = len.saturating_add(data_len);
+                    if len > self.limits.max_part_len {
+                        return Err(ParsingError::PartTooLong);
+                    }
+
+                    self.state = State::Body { len };
+                    return Ok(Some(Event::Data(buffer.split_to(data_len).freeze())));
+                }
+                State::Finished => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Returns the boundary of a `multipart/form-data` content type, e.g.
+/// `multipart/form-data; boundary="abc"`.
+pub fn boundary(content_type: &[u8]) -> Result<Vec<u8>, ParsingError> {
+    let essence = content_type
+        .split(|&b| b == b';')
+        .next()
+        .unwrap_or_default();
+    if !essence
+        .trim_ascii()
+        .eq_ignore_ascii_case(b"multipart/form-data")
+    {
+        return Err(ParsingError::NotMultipart);
+    }
+
+    let boundary = parameters(content_type)
+        .into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(b"boundary"))
+        .map(|(_, value)| value)
+        .ok_or(ParsingError::InvalidBoundary)?;
+
+    let is_valid = (1..=70).contains(&boundary.len())
+        && boundary.iter().all(|&b| b.is_ascii_graphic() || b == b' ')
+        && !boundary.ends_with(b" ");
+    if is_valid {
+        Ok(boundary)
+    } else {
+        Err(ParsingError::InvalidBoundary)
+    }
+}
+
+// Returns the `name` and `filename` parameters, preferring `filename*` when
+// given, e.g. `form-data; name="file"; filename*=UTF-8''%C5%82.txt`.
+fn parse_disposition(value: &[u8]) -> (Option<String>, Option<String>) {
+    let mut name = None;
+    let mut filename = None;
+    let mut ext_filename = None;
+    for (param, value) in parameters(value) {
+        if param.eq_ignore_ascii_case(b"name") {
+            name = Some(String::from_utf8_lossy(&value).into_owned());
+        } else if param.eq_ignore_ascii_case(b"filename") {
+            filename = Some(String::from_utf8_lossy(&value).into_owned());
+        } else if param.eq_ignore_ascii_case(b"filename*") {
+            ext_filename = decode_ext_value(&value);
+        }
+    }
+
+    (name, ext_filename.or(filename))
+}
+
+// Decodes `UTF-8'<LANGUAGE>'<PERCENT-ENCODED>`, see
+// https://www.rfc-editor.org/rfc/rfc8187#section-3.2
+fn decode_ext_value(value: &[u8]) -> Option<String> {
+    let mut parts = value.splitn(3, |&b| b == b'\'');
+    let charset = parts.next()?;
+    let _language = parts.next()?;
+    let encoded = parts.next()?;
+    if !charset.eq_ignore_ascii_case(b"UTF-8") {
+        return None;
+    }
+
+    String::from_utf8(percent_decode(encoded).ok()?).ok()
+}
+
+// Splits the parameters following the first `;` of a value, e.g.
+// `form-data; name="a;b"; x=1`. Backslashes are only treated as escapes before
+// quotes, as browsers do not escape them in file names.
+fn parameters(value: &[u8]) -> Vec<(&[u8], Vec<u8>)> {
+    let mut params = Vec::new();
+    let Some(start) = value.iter().position(|&b| b == b';') else {
+        return params;
+    };
+
+    let mut rest = &value[start + 1..];
+    loop {
+        rest = rest.trim_ascii_start();
+        let Some(eq) = rest.iter().position(|&b| b == b'=') else {
+            return params;
+        };
+
+        let name = rest[..eq].trim_ascii();
+        rest = rest[eq + 1..].trim_ascii_start();
+
+        let mut param = Vec::new();
+        let end = if rest.first() == Some(&b'"') {
+            let mut i = 1;
+            while i < rest.len() && rest[i] != b'"' {
+                if rest[i] == b'\\' && rest.get(i + 1) == Some(&b'"') {
+                    i += 1;
+                }
+                param.push(rest[i]);
+                i += 1;
+            }
+            i
+        } else {
+            let end = rest.iter().position(|&b| b == b';').unwrap_or(rest.len());
+            param.extend_from_slice(rest[..end].trim_ascii());
+            end
+        };
+
+        params.push((name, param));
+        match rest[end.min(rest.len())..].iter().position(|&b| b == b';') {
+            Some(semicolon) => rest = &rest[end + semicolon + 1..],
+            None => return params,
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONTENT_TYPE: &[u8] = b"multipart/form-data; boundary=\"----x\"";
+
+    const BODY: &str = "\
+        preamble\r\n\
+        ------x\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        Hello\r\n\
+        ------x \r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a; \\\"b\\\".txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        line\r\n\
+        ----x\r\n\
+        ------x--\r\n\
+        epilogue";
+
+    // Feeds the body in pieces of `step` bytes, merging the data of every part.
+    fn parse(
+        body: &[u8],
+        step: usize,
+        limits: Limits,
+    ) -> Result<Vec<(Part, Vec<u8>)>, ParsingError> {
+        let mut parser = Parser::from_content_type(CONTENT_TYPE, limits)?;
+        let mut buffer = BytesMut::new();
+        let mut parts: Vec<(Part, Vec<u8>)> = Vec::new();
+        for chunk in body.chunks(step) {
+            buffer.extend_from_slice(chunk);
+            while let Some(event) = parser.next_event(&mut buffer)? {
+                match event {
+                    Event::Part(part) => parts.push((part, Vec::new())),
+                    Event::Data(data) => parts.last_mut().unwrap().1.extend_from_slice(&data),
+                    Event::PartEnd => {}
+                }
+            }
+        }
+
+        assert!(parser.is_finished());
+        assert_eq!(&buffer[..], b"\r\nepilogue");
+        Ok(parts)
+    }
+
+    #[test]
+    fn parts() {
+        for step in [1, 3, 7, BODY.len()] {
+            let parts = parse(BODY.as_bytes(), step, Limits::default()).unwrap();
+            assert_eq!(parts.len(), 2);
+
+            let (title, data) = &parts[0];
+            assert_eq!(title.name(), Some("title"));
+            assert_eq!(title.filename(), None);
+            assert_eq!(data, b"Hello");
+
+            let (file, data) = &parts[1];
+            assert_eq!(file.name(), Some("file"));
+            assert_eq!(file.filename(), Some("a; \"b\".txt"));
+            assert_eq!(file.content_type(), Some(&b"text/plain"[..]));
+            assert_eq!(data, b"line\r\n----x");
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let limits = |max_head_len, max_part_len, max_parts| Limits {
+            max_head_len,
+            max_part_len,
+            max_parts,
+        };
+
+        let body = BODY.as_bytes();
+        assert!(parse(body, 5, limits(100, 11, 2)).is_ok());
+        assert_eq!(
+            parse(body, 5, limits(100, 10, 2)).err(),
+            Some(ParsingError::PartTooLong)
+        );
+        assert_eq!(
+            parse(body, 5, limits(100, 11, 1)).err(),
+            Some(ParsingError::TooManyParts)
+        );
+        assert_eq!(
+            parse(body, 5, limits(60, 11, 2)).err(),
+            Some(ParsingError::HeadTooLong)
+        );
+    }
+
+    #[test]
+    fn content_type() {
+        assert_eq!(
+            boundary(b"Multipart/Form-Data; charset=utf-8; boundary=abc").unwrap(),
+            b"abc"
+        );
+        assert_eq!(
+            boundary(b"text/plain; boundary=abc"),
+            Err(ParsingError::NotMultipart)
+        );
+        assert_eq!(
+            boundary(b"multipart/form-data"),
+            Err(ParsingError::InvalidBoundary)
+        );
+        assert_eq!(
+            boundary(b"multipart/form-data; boundary=\"\""),
+            Err(ParsingError::InvalidBoundary)
+        );
+    }
+
+    #[test]
+    fn disposition() {
+        assert_eq!(
+            parse_disposition(b"form-data; name=file; filename=\"C:\\x\\a.txt\""),
+            (Some("file".to_string()), Some("C:\\x\\a.txt".to_string()))
+        );
+        assert_eq!(
+            parse_disposition(b"form-data; filename=\"a.txt\"; filename*=UTF-8''%C5%82.txt"),
+            (None, Some("ł.txt".to_string()))
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::chars::CRLF;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParsingError {
+    UnsupportedUnit,
+    Malformed,
+}
+
+impl ParsingError {
+    pub const fn as_str(self) -> &'static str {
+        use ParsingError::*;
+        match self {
+            UnsupportedUnit => "unsupported range unit",
+            Malformed => "malformed range",
+        }
+    }
+}
+
+impl fmt::Display for ParsingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::error::Error for ParsingError {}
+
+/// A single range from the `Range` header, see
+/// https://httpwg.org/specs/rfc9110.html#byte.ranges
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ByteRange {
+    /// `first-last`, both inclusive.
+    FromTo(u64, u64),
+    /// `first-`
+    From(u64),
+    /// `-suffix`, the last `suffix` bytes.
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// Returns the inclusive range of bytes selected from a representation of
+    /// length `len`, or `None` if the range is unsatisfiable.
+    pub fn resolve(self, len: u64) -> Option<RangeInclusive<u64>> {
+        match self {
+            Self::FromTo(first, _) | Self::From(first) if first >= len => None,
+            Self::FromTo(first, last) => Some(first..=last.min(len - 1)),
+            Self::From(first) => Some(first..=len - 1),
+            Self::Suffix(0) => None,
+            Self::Suffix(_) if len == 0 => None,
+            Self::Suffix(suffix) => Some(len - suffix.min(len)..=len - 1),
+        }
+    }
+
+    fn from_bytes(spec: &[u8]) -> Result<Self, ParsingError> {
+        let Some(dash) = spec.iter().position(|&b| b == b'-') else {
+            return Err(ParsingError::Malformed);
+        };
+
+        let (first, last) = (&spec[..dash], &spec[dash + 1..]);
+        match (parse_u64(first), parse_u64(last)) {
+            (Some(first), Some(last)) if first <= last => Ok(Self::FromTo(first, last)),
+            (Some(first), None) if last.is_empty() => Ok(Self::From(first)),
+            (None, Some(suffix)) if first.is_empty() => Ok(Self::Suffix(suffix)),
+            _ => Err(ParsingError::Malformed),
+        }
+    }
+}
+
+fn parse_u64(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+fn trim(mut bytes: &[u8]) -> &[u8] {
+    while let [b' ' | b'\t', rest @ ..] = bytes {
+        bytes = rest;
+    }
+
+    while let [rest @ .., b' ' | b'\t'] = bytes {
+        bytes = rest;
+    }
+
+    bytes
+}
+
+/// Parses the value of the `Range` header. Note that `Fields` splits the
+/// value on commas, so the values need to be joined back together first.
+pub fn parse(value: &[u8]) -> Result<Vec<ByteRange>, ParsingError> {
+    let value = trim(value);
+    let Some(eq) = value.iter().position(|&b| b == b'=') else {
+        return Err(ParsingError::Malformed);
+    };
+
+    if !trim(&value[..eq]).eq_ignore_ascii_case(b"bytes") {
+        return Err(ParsingError::UnsupportedUnit);
+    }
+
+    let ranges = value[eq + 1..]
+        .split(|&b| b == b',')
+        .map(trim)
+        .filter(|spec| !spec.is_empty())
+        .map(ByteRange::from_bytes)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if ranges.is_empty() {
+        Err(ParsingError::Malformed)
+    } else {
+        Ok(ranges)
+    }
+}
+
+/// Resolves all satisfiable ranges, merging the ones that overlap or are
+/// adjacent.
+pub fn resolve_all(ranges: &[ByteRange], len: u64) -> Vec<RangeInclusive<u64>> {
+    let mut resolved: Vec<_> = ranges.iter().filter_map(|r| r.resolve(len)).collect();
+    resolved.sort_by_key(|r| *r.start());
+
+    let mut merged: Vec<RangeInclusive<u64>> = Vec::with_capacity(resolved.len());
+    for range in resolved {
+        match merged.last_mut() {
+            Some(last) if *range.start() <= last.end().saturating_add(1) => {
+                *last = *last.start()..=*range.end().max(last.end());
+            }
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+/// Formats the value of `Content-Range` for a satisfied range.
+pub fn content_range(range: &RangeInclusive<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{len}", range.start(), range.end())
+}
+
+/// Formats the value of `Content-Range` sent with `416 Range Not Satisfiable`.
+pub fn unsatisfied_content_range(len: u64) -> String {
+    format!("bytes */{len}")
+}
+
+/// Writes the delimiter and headers preceding a part of a
+/// `multipart/byteranges` body.
+pub fn write_part_head(
+    buffer: &mut Vec<u8>,
+    boundary: &str,
+    content_type: &[u8],
+    range: &RangeInclusive<u64>,
+    len: u64,
+    first: bool,
+) {
+    if !first {
+        buffer.extend_from_slice(CRLF);
+    }
+
+    buffer.extend_from_slice(b"--");
+    buffer.extend_from_slice(boundary.as_bytes());
+    buffer.extend_from_slice(CRLF);
+    buffer.extend_from_slice(b"Content-Type: ");
+    buffer.extend_from_slice(content_type);
+    buffer.extend_from_slice(CRLF);
+    buffer.extend_from_slice(b"Content-Range: ");
+    buffer.extend_from_slice(content_range(range, len).as_bytes());
+    buffer.extend_from_slice(CRLF);
+    buffer.extend_from_slice(CRLF);
+}
+
+/// Writes the delimiter closing a `multipart/byteranges` body.
+pub fn write_close_delimiter(buffer: &mut Vec<u8>, boundary: &str) {
+    buffer.extend_from_slice(CRLF);
+    buffer.extend_from_slice(b"--");
+    buffer.extend_from_slice(boundary.as_bytes());
+    buffer.extend_from_slice(b"--");
+    buffer.extend_from_slice(CRLF);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_ranges() {
+        use ByteRange::*;
+        assert_eq!(parse(b"bytes=0-499"), Ok(vec![FromTo(0, 499)]));
+        assert_eq!(
+            parse(b"bytes=0-0, -1,500-"),
+            Ok(vec![FromTo(0, 0), Suffix(1), From(500)])
+        );
+        assert_eq!(
+            parse(b"Bytes = 1-2, ,3-4"),
+            Ok(vec![FromTo(1, 2), FromTo(3, 4)])
+        );
+        assert_eq!(parse(b"items=0-1"), Err(ParsingError::UnsupportedUnit));
+        assert_eq!(parse(b"bytes=5-1"), Err(ParsingError::Malformed));
+        assert_eq!(parse(b"bytes=-"), Err(ParsingError::Malformed));
+        assert_eq!(parse(b"bytes="), Err(ParsingError::Malformed));
+    }
+
+    #[test]
+    fn resolve() {
+        use ByteRange::*;
+        assert_eq!(FromTo(0, 499).resolve(100), Some(0..=99));
+        assert_eq!(FromTo(100, 101).resolve(100), None);
+        assert_eq!(From(10).resolve(100), Some(10..=99));
+        assert_eq!(Suffix(10).resolve(100), Some(90..=99));
+        assert_eq!(Suffix(1000).resolve(100), Some(0..=99));
+        assert_eq!(Suffix(0).resolve(100), None);
+        assert_eq!(Suffix(1).resolve(0), None);
+    }
+
+    #[test]
+    fn merge() {
+        use ByteRange::*;
+        let ranges = [
+            FromTo(50, 59),
+            FromTo(0, 9),
+            FromTo(10, 19),
+            Suffix(5),
+            From(200),
+        ];
+        assert_eq!(resolve_all(&ranges, 100), vec![0..=19, 50..=59, 95..=99]);
+    }
+
+    #[test]
+    fn multipart() {
+        let mut buffer = Vec::new();
+        write_part_head(&mut buffer, "XYZ", b"text/plain", &(0..=1), 10, true);
+        buffer.extend_from_slice(b"ab");
+        write_part_head(&mut buffer, "XYZ", b"text/plain", &(8..=9), 10, false);
+        buffer.extend_from_slice(b"ij");
+        write_close_delimiter(&mut buffer, "XYZ");
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "--XYZ\r\n\
+            Content-Type: text/plain\r\n\
+            Content-Range: bytes 0-1/10\r\n\
+            \r\n\
+            ab\r\n\
+            --XYZ\r\n\
+            Content-Type: text/plain\r\n\
+            Content-Range: bytes 8-9/10\r\n\
+            \r\n\
+            ij\r\n\
+            --XYZ--\r\n"
+        );
+    }
+}
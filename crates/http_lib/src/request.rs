@@ -4,10 +4,13 @@ use bytes::Bytes;
 
 use crate::{
     chars::{CRLF, URI_MAP},
-    field, version, Fields, Method, Version,
+    chunked, field, form, header::ContentLength, version, Fields, FormData, HeaderName, Method, Uri, Version,
 };
 use crate::Advance;
 
+pub mod parser;
+pub use parser::{Parser, Status};
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum ParsingError {
     VersionMalformed,
@@ -17,6 +20,12 @@ pub enum ParsingError {
     Header(field::ParsingError),
     BodyLongerThanStream,
     Trailer(field::ParsingError),
+    HeadTooLong,
+    BodyTooLong,
+    InvalidContentLength,
+    TransferCodingWithContentLength,
+    UnsupportedTransferCoding,
+    Chunk(chunked::ParsingError),
 }
 
 impl ParsingError {
@@ -49,6 +58,14 @@ impl ParsingError {
             Trailer(field::InvalidCommentCharacter) => {
                 "trailer comment contains an invalid character"
             }
+            HeadTooLong => "start line and headers exceed the size limit",
+            BodyTooLong => "body exceeds the size limit",
+            InvalidContentLength => "invalid Content-Length",
+            TransferCodingWithContentLength => {
+                "both Transfer-Encoding and Content-Length are set"
+            }
+            UnsupportedTransferCoding => "unsupported transfer coding",
+            Chunk(err) => err.as_str(),
         }
     }
 }
@@ -59,6 +76,16 @@ impl From<version::Malformed> for ParsingError {
     }
 }
 
+impl From<chunked::ParsingError> for ParsingError {
+    fn from(err: chunked::ParsingError) -> Self {
+        match err {
+            chunked::ParsingError::BodyTooLong => Self::BodyTooLong,
+            chunked::ParsingError::Trailer(err) => Self::Trailer(err),
+            err => Self::Chunk(err),
+        }
+    }
+}
+
 impl fmt::Display for ParsingError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.as_str())
@@ -69,7 +96,7 @@ impl std::error::Error for ParsingError {}
 
 struct StartLine {
     method: Method,
-    path: Bytes,
+    uri: Uri,
     version: Version,
 }
 
@@ -83,10 +110,9 @@ impl StartLine {
             return Err(ParsingError::MalformedStartLine);
         }
 
-        let path = bytes.split_while(|&c| URI_MAP[c as usize] != 0);
-        if path.is_empty() {
-            return Err(ParsingError::InvalidResource);
-        }
+        let target = bytes.split_while(|&c| URI_MAP[c as usize] != 0);
+        let uri = Uri::from_request_target(method, &target)
+            .map_err(|_| ParsingError::InvalidResource)?;
 
         if !bytes.advance_byte(b' ') {
             return Err(ParsingError::MalformedStartLine);
@@ -100,15 +126,16 @@ impl StartLine {
 
         Ok(Self {
             method,
-            path,
+            uri,
             version,
         })
     }
 }
 
+#[derive(Clone)]
 pub struct Request {
     pub method: Method,
-    pub path: Bytes,
+    pub uri: Uri,
     pub version: Version,
     pub headers: Fields,
     pub body: Bytes,
@@ -116,16 +143,16 @@ pub struct Request {
 }
 
 impl Request {
-    pub fn builder(host: Bytes, method: Method, path: Bytes) -> Builder {
-        Builder::new(host, method, path)
+    pub fn builder(host: Bytes, method: Method, uri: Uri) -> Builder {
+        Builder::new(host, method, uri)
     }
 
-    pub fn new(host: Bytes, method: Method, path: Bytes) -> Self {
+    pub fn new(host: Bytes, method: Method, uri: Uri) -> Self {
         let mut headers = Fields::new();
-        headers.add_header_value("Host".into(), host);
+        headers.add_header_value(HeaderName::HOST, host);
         Self {
             method,
-            path,
+            uri,
             version: Version(1, 1),
             headers,
             body: Bytes::new(),
@@ -133,7 +160,7 @@ impl Request {
         }
     }
 
-    pub fn add_header_value(&mut self, name: Bytes, value: Bytes) {
+    pub fn add_header_value(&mut self, name: HeaderName, value: Bytes) {
         self.headers.add_header_value(name, value);   
     }
 
@@ -142,26 +169,70 @@ impl Request {
     }
 
     pub fn body_of_type(&mut self, body: Bytes, content_type: Bytes) {
-        self.add_header_value("Content-Length".into(), body.len().to_string().into());
-        self.add_header_value("Content-Type".into(), content_type);
+        self.add_header_value(HeaderName::CONTENT_LENGTH, body.len().to_string().into());
+        self.add_header_value(HeaderName::CONTENT_TYPE, content_type);
         self.body = body;
     }
 
+    fn from_parts(start_line: StartLine, headers: Fields, body: Bytes, trailers: Fields) -> Self {
+        let StartLine {
+            method,
+            uri,
+            version,
+        } = start_line;
+
+        Self {
+            method,
+            uri,
+            version,
+            headers,
+            body,
+            trailers,
+        }
+    }
+
+    // Switches the body to the chunked transfer coding, which allows sending
+    // trailers.
+    pub fn set_chunked(&mut self) {
+        self.headers.remove(&HeaderName::CONTENT_LENGTH);
+        if !self.is_chunked() {
+            self.add_header_value(HeaderName::TRANSFER_ENCODING, "chunked".into());
+        }
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        chunked::is_chunked(&self.headers)
+    }
+
+    /// The pairs of the query string, empty if there is none.
+    pub fn query(&self) -> Result<FormData, form::ParsingError> {
+        FormData::parse(self.uri.query().unwrap_or_default().as_bytes())
+    }
+
+    /// The pairs of a body sent as `application/x-www-form-urlencoded`.
+    pub fn form(&self) -> Result<FormData, form::ParsingError> {
+        let content_type = self.headers.get_single(&HeaderName::CONTENT_TYPE).unwrap_or_default();
+        let essence = content_type.split(|&b| b == b';').next().unwrap_or_default();
+        if !essence.trim_ascii().eq_ignore_ascii_case(form::CONTENT_TYPE.as_bytes()) {
+            return Err(form::ParsingError::UnexpectedContentType);
+        }
+
+        FormData::parse(&self.body)
+    }
+
     pub fn from_bytes(bytes: &mut Bytes) -> Result<Self, ParsingError> {
         let StartLine {
             method,
-            path,
+            uri,
             version,
         } = StartLine::from_bytes(bytes)?;
         let headers = Fields::from_bytes(bytes).map_err(ParsingError::Header)?;
-        let content_length = headers.get("Content-Length".as_bytes()).map_or(0, |c| {
-            std::str::from_utf8(c.get_refs().0.as_slice())
-                .unwrap_or("")
-                .parse()
-                .unwrap_or(0)
-        });
-
-        if content_length < bytes.len() {
+        let content_length = headers
+            .typed_get::<ContentLength>()
+            .and_then(|ContentLength(len)| usize::try_from(len).ok())
+            .unwrap_or(0);
+
+        if content_length > bytes.len() {
             return Err(ParsingError::BodyLongerThanStream);
         }
 
@@ -169,7 +240,7 @@ impl Request {
 
         Ok(Self {
             method,
-            path,
+            uri,
             version,
             headers,
             body,
@@ -180,14 +251,17 @@ impl Request {
     pub fn write_to_buffer(&self, buffer: &mut Vec<u8>) {
         buffer.extend_from_slice(self.method.as_bytes());
         buffer.push(b' ');
-        buffer.extend_from_slice(&self.path);
+        self.uri.write_to_buffer(buffer);
         buffer.push(b' ');
         self.version.write_to_buffer(buffer);
         buffer.extend_from_slice(CRLF);
 
         self.headers.write_to_buffer(buffer);
-        buffer.extend_from_slice(&self.body);
-        self.trailers.write_to_buffer(buffer);
+        if self.is_chunked() {
+            chunked::write_body(buffer, &self.body, &self.trailers);
+        } else {
+            buffer.extend_from_slice(&self.body);
+        }
     }
 
     pub fn to_buffer(&self) -> Vec<u8> {
@@ -202,13 +276,13 @@ pub struct Builder {
 }
 
 impl Builder {
-    pub fn new(host: Bytes, method: Method, path: Bytes) -> Self {
+    pub fn new(host: Bytes, method: Method, uri: Uri) -> Self {
         Self {
-            request: Box::new(Request::new(host, method, path))
+            request: Box::new(Request::new(host, method, uri))
         }
     }
 
-    pub fn add_header_value(mut self, name: Bytes, value: Bytes) -> Self {
+    pub fn add_header_value(mut self, name: HeaderName, value: Bytes) -> Self {
         self.request.add_header_value(name, value);
         self
     }
@@ -269,7 +343,7 @@ mod test {
 
     fn assert_start_line(req: &Request, method: Method, path: &str, version: Version) {
         assert_eq!(req.method, method);
-        assert_eq!(std::str::from_utf8(&req.path).unwrap(), path);
+        assert_eq!(req.uri.to_string(), path);
         assert_eq!(req.version, version);
     }
 
@@ -281,13 +355,15 @@ mod test {
         assert_headers(&req.headers, &HEAD_HEADERS);
         assert!(req.body.is_empty());
         assert!(req.trailers.is_empty());
+        assert!(req.query().unwrap().is_empty());
+        assert_eq!(req.form(), Err(form::ParsingError::UnexpectedContentType));
     }
 
     #[test]
     fn head_to_string() {
         let req = Request {
             method: Method::Head,
-            path: "/".into(),
+            uri: "/".parse().unwrap(),
             version: Version(1, 1),
             headers: Fields::copy_from_str(HEAD_HEADERS),
             body: Bytes::new(),
@@ -304,13 +380,16 @@ mod test {
         assert_headers(&req.headers, &POST_HEADERS);
         assert_eq!(&*req.body, b"Hello world!");
         assert!(req.trailers.is_empty());
+
+        let form = req.form().unwrap();
+        assert_eq!(form.get("Hello world!"), Some(""));
     }
 
     #[test]
     fn post_to_string() {
         let req = Request {
             method: Method::Post,
-            path: "/".into(),
+            uri: "/".parse().unwrap(),
             version: Version(1, 1),
             headers: Fields::copy_from_str(POST_HEADERS),
             body: "Hello world!".into(),
@@ -0,0 +1,410 @@
+use bytes::{Buf as _, Bytes, BytesMut};
+
+use super::{ParsingError, Request, StartLine};
+use crate::header::{ContentLength, TypedHeader};
+use crate::{chars::CRLF, chunked, Fields, HeaderName};
+
+const HEAD_TERMINATOR: &[u8] = b"\r\n\r\n";
+
+pub const DEFAULT_MAX_HEAD_LEN: usize = 16 * 1024;
+pub const DEFAULT_MAX_BODY_LEN: usize = 1024 * 1024;
+
+pub enum Status {
+    /// The whole chunk was consumed, but the request is not complete yet.
+    Partial,
+    /// The request is complete. Only the first `consumed` bytes of the chunk
+    /// belong to it, the rest should be fed to the parser again.
+    Complete {
+        request: Box<Request>,
+        consumed: usize,
+    },
+}
+
+enum State {
+    Head {
+        scanned: usize,
+    },
+    // the request is completed with its body and trailers
+    Body {
+        request: Box<Request>,
+        len: BodyLen,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum BodyLen {
+    Fixed(usize),
+    Chunked,
+}
+
+/// A push-style request parser, which can be fed data as it arrives.
+pub struct Parser {
+    buffer: BytesMut,
+    state: State,
+    decoder: Option<chunked::Decoder>,
+    max_head_len: usize,
+    max_body_len: usize,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::with_limits(DEFAULT_MAX_HEAD_LEN, DEFAULT_MAX_BODY_LEN)
+    }
+
+    pub fn with_limits(max_head_len: usize, max_body_len: usize) -> Self {
+        Self {
+            buffer: BytesMut::new(),
+            state: State::Head { scanned: 0 },
+            decoder: None,
+            max_head_len,
+            max_body_len,
+        }
+    }
+
+    /// Returns `true` if no part of the next request has been received yet.
+    pub fn is_idle(&self) -> bool {
+        matches!(self.state, State::Head { .. }) && self.buffer.is_empty()
+    }
+
+    /// Discards any partially parsed request.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.state = State::Head { scanned: 0 };
+        self.decoder = None;
+    }
+
+    /// Parses the next chunk of data. After an error the parser must be reset
+    /// before it is used again.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Status, ParsingError> {
+        self.feed_with_limit(chunk, |_| None)
+    }
+
+    /// Like `feed`, but once the head of a request has been parsed,
+    /// `max_body_len` is asked for the largest body accepted for it, e.g. to
+    /// allow uploads on some paths only. The request has no body yet, `None`
+    /// keeps the limit the parser was created with.
+    pub fn feed_with_limit(
+        &mut self,
+        chunk: &[u8],
+        max_body_len: impl FnOnce(&Request) -> Option<usize>,
+    ) -> Result<Status, ParsingError> {
+        self.buffer.extend_from_slice(chunk);
+
+        if let State::Head { scanned } = self.state {
+            if scanned == 0 {
+                // ignore empty lines preceding the request, see
+                // https://httpwg.org/specs/rfc9112.html#message.parsing
+                while self.buffer.starts_with(CRLF) {
+                    self.buffer.advance(CRLF.len());
+                }
+            }
+
+            let Some(head_len) = find_head_end(&self.buffer, scanned) else {
+                if self.buffer.len() > self.max_head_len {
+                    return Err(ParsingError::HeadTooLong);
+                }
+
+                let scanned = self.buffer.len().saturating_sub(HEAD_TERMINATOR.len() - 1);
+                self.state = State::Head { scanned };
+                return Ok(Status::Partial);
+            };
+
+            if head_len > self.max_head_len {
+                return Err(ParsingError::HeadTooLong);
+            }
+
+            let mut head = self.buffer.split_to(head_len).freeze();
+            let start_line = StartLine::from_bytes(&mut head)?;
+            let headers = Fields::from_bytes(&mut head).map_err(ParsingError::Header)?;
+            let len = body_len(&headers)?;
+            let request = Request::from_parts(start_line, headers, Bytes::new(), Fields::new());
+            let max_body_len = max_body_len(&request).unwrap_or(self.max_body_len);
+            if matches!(len, BodyLen::Fixed(len) if len > max_body_len) {
+                return Err(ParsingError::BodyTooLong);
+            }
+
+            if let BodyLen::Chunked = len {
+                self.decoder = Some(chunked::Decoder::new(max_body_len));
+            }
+
+            self.state = State::Body {
+                request: Box::new(request),
+                len,
+            };
+        }
+
+        let State::Body { len, .. } = self.state else {
+            unreachable!();
+        };
+
+        let (body, trailers) = match len {
+            BodyLen::Fixed(len) => {
+                if self.buffer.len() < len {
+                    return Ok(Status::Partial);
+                }
+
+                (self.buffer.split_to(len).freeze(), Fields::new())
+            }
+            BodyLen::Chunked => {
+                let decoder = self
+                    .decoder
+                    .as_mut()
+                    .expect("decoder is set for chunked bodies");
+                let Some(decoded) = decoder.decode(&mut self.buffer)? else {
+                    return Ok(Status::Partial);
+                };
+
+                self.decoder = None;
+                (decoded.body, decoded.trailers)
+            }
+        };
+
+        let consumed = chunk.len() - self.buffer.len();
+        let state = std::mem::replace(&mut self.state, State::Head { scanned: 0 });
+        self.buffer.clear();
+
+        let State::Body { mut request, .. } = state else {
+            unreachable!();
+        };
+
+        request.body = body;
+        request.trailers = trailers;
+        Ok(Status::Complete { request, consumed })
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Returns the length of the start line and headers, including the empty line
+// terminating them.
+fn find_head_end(buffer: &[u8], scanned: usize) -> Option<usize> {
+    buffer[scanned..]
+        .windows(HEAD_TERMINATOR.len())
+        .position(|w| w == HEAD_TERMINATOR)
+        .map(|pos| scanned + pos + HEAD_TERMINATOR.len())
+}
+
+fn body_len(headers: &Fields) -> Result<BodyLen, ParsingError> {
+    // a request with both could be framed differently by a proxy in front,
+    // which is a way to smuggle requests past it, see
+    // https://httpwg.org/specs/rfc9112.html#message.body.length
+    if headers.contains_name(&HeaderName::TRANSFER_ENCODING) {
+        if headers.contains_name(&HeaderName::CONTENT_LENGTH) {
+            return Err(ParsingError::TransferCodingWithContentLength);
+        }
+
+        return if chunked::is_chunked(headers) {
+            Ok(BodyLen::Chunked)
+        } else {
+            Err(ParsingError::UnsupportedTransferCoding)
+        };
+    }
+
+    let Some(values) = headers.get(&HeaderName::CONTENT_LENGTH) else {
+        return Ok(BodyLen::Fixed(0));
+    };
+
+    ContentLength::decode(values)
+        .and_then(|ContentLength(len)| usize::try_from(len).ok())
+        .map(BodyLen::Fixed)
+        .ok_or(ParsingError::InvalidContentLength)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PIPELINED: &str = "\
+        GET /first HTTP/1.1\r\n\
+        Host: example.com\r\n\
+        \r\n\
+        POST /second HTTP/1.1\r\n\
+        Host: example.com\r\n\
+        Content-Length: 12\r\n\
+        \r\n\
+        Hello world!";
+
+    fn feed_all(parser: &mut Parser, mut bytes: &[u8]) -> Vec<Request> {
+        let mut requests = Vec::new();
+        while !bytes.is_empty() {
+            match parser.feed(bytes).unwrap() {
+                Status::Partial => break,
+                Status::Complete { request, consumed } => {
+                    requests.push(*request);
+                    bytes = &bytes[consumed..];
+                }
+            }
+        }
+        requests
+    }
+
+    fn feed_all_err(parser: &mut Parser, mut bytes: &[u8]) -> Option<ParsingError> {
+        while !bytes.is_empty() {
+            match parser.feed(bytes) {
+                Ok(Status::Complete { consumed, .. }) => bytes = &bytes[consumed..],
+                Ok(Status::Partial) => break,
+                Err(err) => return Some(err),
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn pipelined() {
+        let mut parser = Parser::new();
+        let requests = feed_all(&mut parser, PIPELINED.as_bytes());
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].uri.path(), "/first");
+        assert!(requests[0].body.is_empty());
+        assert_eq!(requests[1].uri.path(), "/second");
+        assert_eq!(&*requests[1].body, b"Hello world!");
+        assert!(parser.is_idle());
+    }
+
+    #[test]
+    fn byte_by_byte() {
+        let mut parser = Parser::new();
+        let mut requests = Vec::new();
+        for b in PIPELINED.as_bytes().chunks(1) {
+            requests.extend(feed_all(&mut parser, b));
+        }
+        assert_eq!(requests.len(), 2);
+        assert_eq!(&*requests[1].body, b"Hello world!");
+    }
+
+    const CHUNKED: &str = "\
+        POST / HTTP/1.1\r\n\
+        Host: example.com\r\n\
+        Transfer-Encoding: chunked\r\n\
+        \r\n\
+        6\r\n\
+        Hello \r\n\
+        6\r\n\
+        world!\r\n\
+        0\r\n\
+        Checksum: 42\r\n\
+        \r\n\
+        GET / HTTP/1.1\r\n\
+        Host: example.com\r\n\
+        \r\n";
+
+    #[test]
+    fn chunked() {
+        let mut parser = Parser::new();
+        let requests = feed_all(&mut parser, CHUNKED.as_bytes());
+        assert_eq!(requests.len(), 2);
+        assert_eq!(&*requests[0].body, b"Hello world!");
+        assert_eq!(
+            requests[0].trailers.get_single(b"Checksum"),
+            Some(&b"42"[..])
+        );
+        assert_eq!(requests[1].method, crate::Method::Get);
+    }
+
+    #[test]
+    fn chunked_limits() {
+        let mut parser = Parser::with_limits(1024, 8);
+        let res = feed_all_err(&mut parser, CHUNKED.as_bytes());
+        assert!(matches!(res, Some(ParsingError::BodyTooLong)));
+    }
+
+    #[test]
+    fn leading_empty_lines() {
+        let mut parser = Parser::new();
+        let requests = feed_all(&mut parser, b"\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(requests.len(), 1);
+    }
+
+    #[test]
+    fn limits() {
+        let mut parser = Parser::with_limits(16, 4);
+        let res = parser.feed(b"GET / HTTP/1.1\r\nHost: example.com\r\n");
+        assert!(matches!(res, Err(ParsingError::HeadTooLong)));
+
+        let mut parser = Parser::with_limits(1024, 4);
+        let res = parser.feed(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n");
+        assert!(matches!(res, Err(ParsingError::BodyTooLong)));
+    }
+
+    #[test]
+    fn limit_per_request() {
+        // only posts to /upload may have a body longer than 4 bytes
+        let limit = |head: &Request| {
+            assert!(head.body.is_empty());
+            (head.method == crate::Method::Post && head.uri.path() == "/upload").then_some(12)
+        };
+
+        let mut parser = Parser::with_limits(1024, 4);
+        let Ok(Status::Complete { consumed, .. }) =
+            parser.feed_with_limit(PIPELINED.as_bytes(), limit)
+        else {
+            panic!("expected a request");
+        };
+        let second = &PIPELINED[consumed..];
+        let res = parser.feed_with_limit(second.as_bytes(), limit);
+        assert!(matches!(res, Err(ParsingError::BodyTooLong)));
+
+        let upload = second.replace("/second", "/upload");
+        parser.reset();
+        let Ok(Status::Complete { request, .. }) = parser.feed_with_limit(upload.as_bytes(), limit)
+        else {
+            panic!("expected a request");
+        };
+        assert_eq!(&*request.body, b"Hello world!");
+
+        let chunked = CHUNKED.replace("POST /", "POST /upload");
+        let mut parser = Parser::with_limits(1024, 4);
+        let res = parser.feed_with_limit(chunked.as_bytes(), limit);
+        assert!(matches!(res, Ok(Status::Complete { .. })));
+
+        let mut parser = Parser::with_limits(1024, 4);
+        let res = parser.feed_with_limit(CHUNKED.as_bytes(), limit);
+        assert!(matches!(res, Err(ParsingError::BodyTooLong)));
+
+        let mut parser = Parser::with_limits(1024, 4);
+        let res = parser.feed_with_limit(chunked.as_bytes(), |_| Some(8));
+        assert!(matches!(res, Err(ParsingError::BodyTooLong)));
+    }
+
+    #[test]
+    fn invalid_content_length() {
+        let mut parser = Parser::new();
+        let res = parser.feed(b"POST / HTTP/1.1\r\nContent-Length: 1, 2\r\n\r\n");
+        assert!(matches!(res, Err(ParsingError::InvalidContentLength)));
+    }
+
+    #[test]
+    fn transfer_coding_with_content_length() {
+        for head in [
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n",
+            "POST / HTTP/1.1\r\ncontent-length: 0\r\ntransfer-encoding: chunked\r\n\r\n",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\nContent-Length: 5\r\n\r\n",
+        ] {
+            let mut parser = Parser::new();
+            let res = parser.feed(head.as_bytes());
+            assert!(
+                matches!(res, Err(ParsingError::TransferCodingWithContentLength)),
+                "{head:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn lowercase_names() {
+        let mut parser = Parser::new();
+        let requests = feed_all(
+            &mut parser,
+            b"POST / HTTP/1.1\r\nhost: a\r\ncontent-length: 5\r\n\r\nHello",
+        );
+        assert_eq!(requests.len(), 1);
+        assert_eq!(&requests[0].body[..], b"Hello");
+        assert_eq!(
+            requests[0].headers.get_single(&HeaderName::HOST),
+            Some(&b"a"[..])
+        );
+    }
+}
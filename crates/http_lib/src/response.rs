@@ -1,11 +1,15 @@
 use std::fmt;
+use std::io::{self, Write};
 
 use bytes::Bytes;
 
-use crate::{chars::CRLF, field, version, Fields, Version};
+use crate::header::ContentLength;
 use crate::Advance as _;
+use crate::{chars::CRLF, chunked, field, version, Fields, HeaderName, Version};
 
+pub mod body;
 pub mod code;
+pub use body::Body;
 pub use code::Code;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -63,7 +67,7 @@ pub struct Response {
     pub version: Version,
     pub code: Code,
     pub headers: Fields,
-    pub body: Bytes,
+    pub body: Body,
     pub trailers: Fields,
 }
 
@@ -74,30 +78,51 @@ impl Response {
 
     pub fn new(code: Code) -> Self {
         let date = httpdate::fmt_http_date(std::time::SystemTime::now());
-        let headers = Fields::copy_from_str([("Date", &[&date])]);
+        let mut headers = Fields::new();
+        headers.add_header_value(HeaderName::DATE, date.into());
         Self {
             version: Version(1, 1),
             code,
             headers,
-            body: Bytes::new(),
+            body: Body::empty(),
             trailers: Fields::new(),
         }
     }
 
-    pub fn add_header_value(&mut self, name: Bytes, value: Bytes) {
+    pub fn add_header_value(&mut self, name: HeaderName, value: Bytes) {
         self.headers.add_header_value(name, value);
     }
 
     pub fn body(&mut self, body: String) {
-        self.body_of_type(body.into(), "text/plain".into());
+        self.body_of_type(body, "text/plain".into());
     }
 
-    pub fn body_of_type(&mut self, body: Bytes, content_type: Bytes) {
-        self.add_header_value("Content-Length".into(), body.len().to_string().into());
-        self.add_header_value("Content-Type".into(), content_type);
+    // Sets the body, falling back to the chunked transfer coding if its length
+    // is not known up front.
+    pub fn body_of_type(&mut self, body: impl Into<Body>, content_type: Bytes) {
+        let body = body.into();
+        if let Some(len) = body.len() {
+            self.add_header_value(HeaderName::CONTENT_LENGTH, len.to_string().into());
+        } else {
+            self.set_chunked();
+        }
+        self.add_header_value(HeaderName::CONTENT_TYPE, content_type);
         self.body = body;
     }
 
+    // Switches the body to the chunked transfer coding, which allows sending
+    // trailers.
+    pub fn set_chunked(&mut self) {
+        self.headers.remove(&HeaderName::CONTENT_LENGTH);
+        if !self.is_chunked() {
+            self.add_header_value(HeaderName::TRANSFER_ENCODING, "chunked".into());
+        }
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        chunked::is_chunked(&self.headers)
+    }
+
     pub fn from_bytes(bytes: &mut Bytes) -> Result<Self, ParsingError> {
         let version = Version::from_bytes(bytes).map_err(|_| ParsingError::VersionMalformed)?;
 
@@ -112,14 +137,12 @@ impl Response {
         }
 
         let headers = Fields::from_bytes(bytes).map_err(ParsingError::Header)?;
-        let content_length = headers.get("Content-Length".as_bytes()).map_or(0, |c| {
-            std::str::from_utf8(c.get_refs().0.as_slice())
-                .unwrap_or("")
-                .parse()
-                .unwrap_or(0)
-        });
-
-        if content_length < bytes.len() {
+        let content_length = headers
+            .typed_get::<ContentLength>()
+            .and_then(|ContentLength(len)| usize::try_from(len).ok())
+            .unwrap_or(0);
+
+        if content_length > bytes.len() {
             return Err(ParsingError::BodyLongerThanStream);
         }
 
@@ -129,18 +152,17 @@ impl Response {
             version,
             code,
             headers,
-            body,
+            body: body.into(),
             trailers: Fields::new(),
         })
     }
 
-    pub fn write_to_buffer(&self, buffer: &mut Vec<u8>) {
+    pub fn write_head_to_buffer(&self, buffer: &mut Vec<u8>) {
         let Self {
             version,
             code,
             headers,
-            body,
-            trailers,
+            ..
         } = self;
         version.write_to_buffer(buffer);
         buffer.push(b' ');
@@ -148,8 +170,54 @@ impl Response {
         buffer.extend_from_slice(CRLF);
 
         headers.write_to_buffer(buffer);
-        buffer.extend_from_slice(body);
-        trailers.write_to_buffer(buffer);
+    }
+
+    // Serializes the response, leaving out bodies which are not held in memory,
+    // `write_to` sends bodies of any kind.
+    pub fn write_to_buffer(&self, buffer: &mut Vec<u8>) {
+        self.write_head_to_buffer(buffer);
+
+        let body = self.body.as_bytes().map_or(&[][..], |b| b);
+        if self.is_chunked() {
+            chunked::write_body(buffer, body, &self.trailers);
+        } else {
+            buffer.extend_from_slice(body);
+        }
+    }
+
+    // Writes the whole response, using `buffer` as scratch space.
+    pub fn write_to<W: Write>(&mut self, writer: &mut W, buffer: &mut Vec<u8>) -> io::Result<()> {
+        buffer.clear();
+        if self
+            .body
+            .as_bytes()
+            .is_some_and(|b| b.len() <= body::CHUNK_SIZE)
+        {
+            self.write_to_buffer(buffer);
+            return writer.write_all(buffer);
+        }
+
+        self.write_head_to_buffer(buffer);
+        writer.write_all(buffer)?;
+        self.write_body_to(writer, buffer)
+    }
+
+    // Writes the body, along with the trailers if it is chunked, once the head
+    // has been sent.
+    pub fn write_body_to<W: Write>(
+        &mut self,
+        writer: &mut W,
+        buffer: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        let chunked = self.is_chunked();
+        self.body.write_to(writer, buffer, chunked)?;
+        if chunked {
+            buffer.clear();
+            chunked::write_last_chunk(buffer, &self.trailers);
+            writer.write_all(buffer)?;
+        }
+
+        Ok(())
     }
 
     pub fn to_buffer(&self) -> Vec<u8> {
@@ -170,7 +238,7 @@ impl Builder {
         }
     }
 
-    pub fn add_header_value(mut self, name: Bytes, value: Bytes) -> Self {
+    pub fn add_header_value(mut self, name: HeaderName, value: Bytes) -> Self {
         self.response.add_header_value(name, value);
         self
     }
@@ -180,11 +248,16 @@ impl Builder {
         self
     }
 
-    pub fn body_of_type(mut self, body: Bytes, content_type: Bytes) -> Self {
+    pub fn body_of_type(mut self, body: impl Into<Body>, content_type: Bytes) -> Self {
         self.response.body_of_type(body, content_type);
         self
     }
 
+    pub fn chunked(mut self) -> Self {
+        self.response.set_chunked();
+        self
+    }
+
     pub fn as_mut_ref(&mut self) -> &mut Response {
         self.response.as_mut()
     }
@@ -231,15 +304,67 @@ mod test {
         assert_headers(&res.headers, &HEADERS);
     }
 
+    #[test]
+    fn from_bytes_content_length() {
+        let mut bytes =
+            Bytes::from_static(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello world!");
+        let res = Response::from_bytes(&mut bytes).unwrap();
+        assert_eq!(res.body.as_bytes().map(|b| &b[..]), Some(&b"Hello"[..]));
+        assert_eq!(&bytes[..], b" world!");
+
+        let mut bytes = Bytes::from_static(b"HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\nHello");
+        assert_eq!(
+            Response::from_bytes(&mut bytes).err(),
+            Some(ParsingError::BodyLongerThanStream)
+        );
+    }
+
     #[test]
     fn to_buffer() {
         let res = Response {
             version: Version(1, 1),
             code: Code::MovedPermanently,
             headers: Fields::copy_from_str(HEADERS),
-            body: Bytes::new(),
+            body: Body::empty(),
             trailers: Fields::new(),
         };
         assert_eq!(String::from_utf8(res.to_buffer()).unwrap(), STRINGIFIED);
     }
+
+    #[test]
+    fn write_parts() {
+        let mut res = Response::builder(Code::Ok)
+            .body_of_type(
+                Body::Parts(vec!["Hello ".into(), "world!".into()]),
+                "text/plain".into(),
+            )
+            .finish();
+        let mut out = Vec::new();
+        res.write_to(&mut out, &mut Vec::new()).unwrap();
+        assert!(
+            out.ends_with(b"Content-Length: 12\r\nContent-Type: text/plain\r\n\r\nHello world!")
+        );
+    }
+
+    #[test]
+    fn chunked_to_buffer() {
+        let mut res = Response {
+            version: Version(1, 1),
+            code: Code::Ok,
+            headers: Fields::copy_from_str([("Content-Length", &["12"])]),
+            body: "Hello world!".into(),
+            trailers: Fields::copy_from_str([("Checksum", &["42"])]),
+        };
+        res.set_chunked();
+        assert_eq!(
+            String::from_utf8(res.to_buffer()).unwrap(),
+            "HTTP/1.1 200 OK\r\n\
+            Transfer-Encoding: chunked\r\n\
+            \r\n\
+            C\r\nHello world!\r\n\
+            0\r\n\
+            Checksum: 42\r\n\
+            \r\n"
+        );
+    }
 }
@@ -0,0 +1,205 @@
+use std::fs::File;
+use std::io::{self, Read, Seek as _, SeekFrom, Write};
+
+use bytes::Bytes;
+
+use crate::chars::CRLF;
+
+/// The amount of data read from a file or a reader at a time.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+// Enough space for a `u64` in hex followed by CRLF.
+const CHUNK_PREFIX_LEN: usize = 16 + CRLF.len();
+
+pub enum Body {
+    Bytes(Bytes),
+    File {
+        file: File,
+        offset: u64,
+        len: u64,
+    },
+    Reader(Box<dyn Read + Send>),
+    /// Multiple bodies sent one after another.
+    Parts(Vec<Body>),
+}
+
+impl Body {
+    pub const fn empty() -> Self {
+        Self::Bytes(Bytes::new())
+    }
+
+    pub fn file(file: File, offset: u64, len: u64) -> Self {
+        Self::File { file, offset, len }
+    }
+
+    pub fn reader<R: Read + Send + 'static>(reader: R) -> Self {
+        Self::Reader(Box::new(reader))
+    }
+
+    /// Returns the length of the body, if it is known up front.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Self::Bytes(bytes) => Some(bytes.len() as u64),
+            Self::File { len, .. } => Some(*len),
+            Self::Reader(_) => None,
+            Self::Parts(parts) => parts.iter().map(Self::len).sum(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    pub fn as_bytes(&self) -> Option<&Bytes> {
+        match self {
+            Self::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Writes the body in chunks of at most `CHUNK_SIZE` bytes, using `buffer`
+    /// as scratch space. If `chunked` is set, every chunk is framed according
+    /// to the chunked transfer coding, but the last chunk is not written.
+    pub fn write_to<W: Write>(
+        &mut self,
+        writer: &mut W,
+        buffer: &mut Vec<u8>,
+        chunked: bool,
+    ) -> io::Result<()> {
+        match self {
+            Self::Bytes(bytes) => {
+                for data in bytes.chunks(CHUNK_SIZE) {
+                    if chunked {
+                        buffer.clear();
+                        crate::chunked::write_chunk(buffer, data);
+                        writer.write_all(buffer)?;
+                    } else {
+                        writer.write_all(data)?;
+                    }
+                }
+
+                Ok(())
+            }
+            Self::File { file, offset, len } => {
+                file.seek(SeekFrom::Start(*offset))?;
+                let copied = copy_chunks(&mut file.take(*len), writer, buffer, chunked)?;
+                if copied < *len {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "file is shorter than expected",
+                    ));
+                }
+
+                Ok(())
+            }
+            Self::Reader(reader) => copy_chunks(reader, writer, buffer, chunked).map(|_| ()),
+            Self::Parts(parts) => parts
+                .iter_mut()
+                .try_for_each(|part| part.write_to(writer, buffer, chunked)),
+        }
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Self {
+        Self::Bytes(bytes)
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(vec: Vec<u8>) -> Self {
+        Self::Bytes(vec.into())
+    }
+}
+
+impl From<String> for Body {
+    fn from(string: String) -> Self {
+        Self::Bytes(string.into())
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(string: &'static str) -> Self {
+        Self::Bytes(string.into())
+    }
+}
+
+// Reads data directly after space reserved for the chunk size, so that each
+// chunk can be sent with a single write.
+fn copy_chunks<R: Read + ?Sized, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    buffer: &mut Vec<u8>,
+    chunked: bool,
+) -> io::Result<u64> {
+    let mut copied = 0;
+    loop {
+        buffer.resize(CHUNK_PREFIX_LEN + CHUNK_SIZE, 0);
+        let read = match reader.read(&mut buffer[CHUNK_PREFIX_LEN..]) {
+            Ok(0) => return Ok(copied),
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+
+        copied += read as u64;
+        buffer.truncate(CHUNK_PREFIX_LEN + read);
+        if chunked {
+            let size = format!("{read:X}");
+            let start = CHUNK_PREFIX_LEN - size.len() - CRLF.len();
+            buffer[start..start + size.len()].copy_from_slice(size.as_bytes());
+            buffer[start + size.len()..CHUNK_PREFIX_LEN].copy_from_slice(CRLF);
+            buffer.extend_from_slice(CRLF);
+            writer.write_all(&buffer[start..])?;
+        } else {
+            writer.write_all(&buffer[CHUNK_PREFIX_LEN..])?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reader_chunked() {
+        let data = vec![b'a'; CHUNK_SIZE + 1];
+        let mut body = Body::reader(io::Cursor::new(data.clone()));
+        let mut out = Vec::new();
+        body.write_to(&mut out, &mut Vec::new(), true).unwrap();
+
+        let mut expected = Vec::new();
+        crate::chunked::write_chunk(&mut expected, &data[..CHUNK_SIZE]);
+        crate::chunked::write_chunk(&mut expected, &data[CHUNK_SIZE..]);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn bytes() {
+        let mut body = Body::from("Hello world!");
+        let mut out = Vec::new();
+        body.write_to(&mut out, &mut Vec::new(), false).unwrap();
+        assert_eq!(out, b"Hello world!");
+        assert_eq!(body.len(), Some(12));
+    }
+
+    #[test]
+    fn parts() {
+        let mut body = Body::Parts(vec![
+            "Hello ".into(),
+            Body::reader(io::Cursor::new(b"world")),
+            "!".into(),
+        ]);
+        assert_eq!(body.len(), None);
+
+        let mut out = Vec::new();
+        body.write_to(&mut out, &mut Vec::new(), false).unwrap();
+        assert_eq!(out, b"Hello world!");
+    }
+}
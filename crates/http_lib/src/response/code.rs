@@ -256,7 +256,7 @@ impl Code {
 
         let idx = (code - 100) as usize;
         if idx < Self::LOOKUP.len() && Self::LOOKUP[idx] {
-            Some(unsafe { std::mem::transmute(code) })
+            Some(unsafe { std::mem::transmute::<u16, Self>(code) })
         } else {
             None
         }
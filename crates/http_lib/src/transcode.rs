@@ -1,4 +1,6 @@
-use std::fmt;
+use std::fmt::{self, Write as _};
+
+use crate::byte_map;
 
 #[derive(Debug)]
 pub struct TranscodeError;
@@ -11,11 +13,100 @@ impl fmt::Display for TranscodeError {
 
 impl std::error::Error for TranscodeError {}
 
+/// The component of a URI a value is encoded for. Each leaves a different set
+/// of characters as they are, see https://www.rfc-editor.org/rfc/rfc3986#section-3
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EncodeSet {
+    /// A whole path, `/` is left as it is.
+    Path,
+    /// A single segment of a path, `/` is encoded.
+    PathSegment,
+    /// A name or a value in a query, `&`, `=` and `+` are encoded.
+    QueryValue,
+    /// A user name or a password, `:` and `@` are encoded.
+    Userinfo,
+    Fragment,
+}
+
+// Unreserved characters and sub-delimiters, except for `:` which would make a
+// relative path look like a scheme.
+const PATH_SEGMENT_MAP: [u8; 256] = byte_map!(
+    for c; match c {
+        c if c.is_ascii_alphanumeric() => c,
+        b'-' | b'.' | b'_' | b'~' |
+        b'!' | b'$' | b'&' | b'\'' | b'(' | b')' |
+        b'*' | b'+' | b',' | b';' | b'=' | b'@' => c,
+        _ => 0,
+    }
+);
+
+const PATH_MAP: [u8; 256] = byte_map!(
+    for c; match c {
+        b'/' => c,
+        c => PATH_SEGMENT_MAP[c as usize],
+    }
+);
+
+const QUERY_VALUE_MAP: [u8; 256] = byte_map!(
+    for c; match c {
+        c if c.is_ascii_alphanumeric() => c,
+        b'-' | b'.' | b'_' | b'~' |
+        b'!' | b'$' | b'\'' | b'(' | b')' | b'*' |
+        b',' | b';' | b':' | b'@' | b'/' | b'?' => c,
+        _ => 0,
+    }
+);
+
+const USERINFO_MAP: [u8; 256] = byte_map!(
+    for c; match c {
+        c if c.is_ascii_alphanumeric() => c,
+        b'-' | b'.' | b'_' | b'~' |
+        b'!' | b'$' | b'&' | b'\'' | b'(' | b')' |
+        b'*' | b'+' | b',' | b';' | b'=' => c,
+        _ => 0,
+    }
+);
+
+const FRAGMENT_MAP: [u8; 256] = byte_map!(
+    for c; match c {
+        b':' | b'/' | b'?' => c,
+        c => PATH_SEGMENT_MAP[c as usize],
+    }
+);
+
+impl EncodeSet {
+    const fn map(self) -> &'static [u8; 256] {
+        match self {
+            Self::Path => &PATH_MAP,
+            Self::PathSegment => &PATH_SEGMENT_MAP,
+            Self::QueryValue => &QUERY_VALUE_MAP,
+            Self::Userinfo => &USERINFO_MAP,
+            Self::Fragment => &FRAGMENT_MAP,
+        }
+    }
+}
+
 #[inline]
 pub fn ascii_digit_to_u8(byte: u8) -> u8 {
     byte & 0xcf
 }
 
+/// Encodes every byte which is not allowed in the component as `%XX`,
+/// including `%` itself.
+pub fn percent_encode(bytes: &[u8], set: EncodeSet) -> String {
+    let map = set.map();
+    let mut out = String::with_capacity(bytes.len());
+    for &b in bytes {
+        if map[b as usize] != 0 {
+            out.push(char::from(b));
+        } else {
+            let _ = write!(out, "%{b:02X}");
+        }
+    }
+
+    out
+}
+
 pub fn percent_decode(mut bytes: &[u8]) -> Result<Vec<u8>, TranscodeError> {
     let mut out = Vec::with_capacity(bytes.len());
     let mut valid = 0;
@@ -44,3 +135,47 @@ pub fn percent_decode(mut bytes: &[u8]) -> Result<Vec<u8>, TranscodeError> {
     out.extend_from_slice(bytes);
     Ok(out)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_sets() {
+        let raw = "a b#1/ł:x@y?q=1&r+s%";
+        let encoded = |set| percent_encode(raw.as_bytes(), set);
+        assert_eq!(
+            encoded(EncodeSet::Path),
+            "a%20b%231/%C5%82%3Ax@y%3Fq=1&r+s%25"
+        );
+        assert_eq!(
+            encoded(EncodeSet::PathSegment),
+            "a%20b%231%2F%C5%82%3Ax@y%3Fq=1&r+s%25"
+        );
+        assert_eq!(
+            encoded(EncodeSet::QueryValue),
+            "a%20b%231/%C5%82:x@y?q%3D1%26r%2Bs%25"
+        );
+        assert_eq!(
+            encoded(EncodeSet::Userinfo),
+            "a%20b%231%2F%C5%82%3Ax%40y%3Fq=1&r+s%25"
+        );
+        assert_eq!(
+            encoded(EncodeSet::Fragment),
+            "a%20b%231/%C5%82:x@y?q=1&r+s%25"
+        );
+
+        for set in [
+            EncodeSet::Path,
+            EncodeSet::PathSegment,
+            EncodeSet::QueryValue,
+            EncodeSet::Userinfo,
+            EncodeSet::Fragment,
+        ] {
+            assert_eq!(
+                percent_decode(encoded(set).as_bytes()).unwrap(),
+                raw.as_bytes()
+            );
+        }
+    }
+}
@@ -0,0 +1,537 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chars::URI_MAP;
+use crate::Method;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ParsingError {
+    Empty,
+    InvalidCharacter,
+    InvalidPercentEncoding,
+    InvalidScheme,
+    InvalidAuthority,
+    InvalidPort,
+    UnexpectedForm,
+}
+
+impl ParsingError {
+    pub const fn as_str(self) -> &'static str {
+        use ParsingError::*;
+        match self {
+            Empty => "empty URI",
+            InvalidCharacter => "URI contains an invalid character",
+            InvalidPercentEncoding => "invalid percent-encoding",
+            InvalidScheme => "invalid scheme",
+            InvalidAuthority => "invalid authority",
+            InvalidPort => "invalid port",
+            UnexpectedForm => "request-target form is not allowed for the method",
+        }
+    }
+}
+
+impl fmt::Display for ParsingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::error::Error for ParsingError {}
+
+/// The forms of a request-target, see
+/// https://httpwg.org/specs/rfc9112.html#request.target
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Form {
+    /// `/where?q=now`, used by most requests.
+    Origin,
+    /// `http://www.example.org/pub/WWW/`, used with proxies.
+    Absolute,
+    /// `www.example.com:80`, only used by `CONNECT`.
+    Authority,
+    /// `*`, only used by `OPTIONS`.
+    Asterisk,
+}
+
+/// The host and port of a URI. The host is in lowercase, user information is
+/// not accepted.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Authority {
+    host: String,
+    port: Option<u16>,
+}
+
+impl Authority {
+    fn parse(s: &str) -> Result<Self, ParsingError> {
+        if s.contains('@') {
+            return Err(ParsingError::InvalidAuthority);
+        }
+
+        let (host, port) = if s.starts_with('[') {
+            let end = s.find(']').ok_or(ParsingError::InvalidAuthority)? + 1;
+            let is_valid = s[1..end - 1]
+                .bytes()
+                .all(|b| b.is_ascii_hexdigit() || b == b':' || b == b'.');
+            if !is_valid {
+                return Err(ParsingError::InvalidAuthority);
+            }
+
+            s.split_at(end)
+        } else {
+            let end = s.find(':').unwrap_or(s.len());
+            let (host, port) = s.split_at(end);
+            if !host.bytes().all(is_reg_name_byte) {
+                return Err(ParsingError::InvalidAuthority);
+            }
+
+            (host, port)
+        };
+
+        if host.is_empty() {
+            return Err(ParsingError::InvalidAuthority);
+        }
+
+        let port = match port {
+            "" | ":" => None,
+            port => {
+                let port = port
+                    .strip_prefix(':')
+                    .ok_or(ParsingError::InvalidAuthority)?;
+                if !port.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(ParsingError::InvalidPort);
+                }
+                Some(port.parse().map_err(|_| ParsingError::InvalidPort)?)
+            }
+        };
+
+        Ok(Self {
+            host: normalize_percent_encoding(host)?.to_ascii_lowercase(),
+            port,
+        })
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+
+impl fmt::Display for Authority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{port}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Authority {
+    type Err = ParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// A parsed request-target. Components are normalized as described in
+/// https://www.rfc-editor.org/rfc/rfc3986#section-6.2.2, i.e. the scheme and
+/// host are in lowercase, percent-encoded unreserved characters are decoded,
+/// the remaining percent-encodings use uppercase digits and dot-segments are
+/// removed from the path. Default ports of `http` and `https` are dropped.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Uri {
+    form: Form,
+    scheme: Option<String>,
+    authority: Option<Authority>,
+    path: String,
+    query: Option<String>,
+    fragment: Option<String>,
+}
+
+impl Uri {
+    /// Parses a request-target in the form allowed for the method.
+    pub fn from_request_target(method: Method, target: &[u8]) -> Result<Self, ParsingError> {
+        if method == Method::Connect {
+            return Self::parse_authority_form(target);
+        }
+
+        let uri = Self::parse(target)?;
+        if uri.form == Form::Asterisk && method != Method::Options {
+            return Err(ParsingError::UnexpectedForm);
+        }
+
+        Ok(uri)
+    }
+
+    /// Parses a target in the origin, absolute or asterisk form. The authority
+    /// form is ambiguous with absolute URIs, see `parse_authority_form`.
+    pub fn parse(target: &[u8]) -> Result<Self, ParsingError> {
+        let target = to_str(target)?;
+        if target == "*" {
+            return Ok(Self {
+                form: Form::Asterisk,
+                scheme: None,
+                authority: None,
+                path: "*".to_string(),
+                query: None,
+                fragment: None,
+            });
+        }
+
+        let (rest, fragment) = match target.split_once('#') {
+            Some((rest, fragment)) => (rest, Some(fragment)),
+            None => (target, None),
+        };
+
+        let (rest, query) = match rest.split_once('?') {
+            Some((rest, query)) => (rest, Some(query)),
+            None => (rest, None),
+        };
+
+        let (form, scheme, authority, path) = if rest.starts_with('/') {
+            (
+                Form::Origin,
+                None,
+                None,
+                remove_dot_segments(&normalize_percent_encoding(rest)?),
+            )
+        } else {
+            let (scheme, rest) = rest.split_once(':').ok_or(ParsingError::InvalidScheme)?;
+            let scheme = parse_scheme(scheme)?;
+            let rest = rest
+                .strip_prefix("//")
+                .ok_or(ParsingError::InvalidAuthority)?;
+            let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+
+            let mut authority = Authority::parse(authority)?;
+            if authority.port == default_port(&scheme) {
+                authority.port = None;
+            }
+
+            let path = if path.is_empty() {
+                "/".to_string()
+            } else {
+                remove_dot_segments(&normalize_percent_encoding(path)?)
+            };
+
+            (Form::Absolute, Some(scheme), Some(authority), path)
+        };
+
+        Ok(Self {
+            form,
+            scheme,
+            authority,
+            path,
+            query: query.map(normalize_percent_encoding).transpose()?,
+            fragment: fragment.map(normalize_percent_encoding).transpose()?,
+        })
+    }
+
+    /// Parses a target in the authority form, e.g. `www.example.com:443`. The
+    /// port is required.
+    pub fn parse_authority_form(target: &[u8]) -> Result<Self, ParsingError> {
+        let authority = Authority::parse(to_str(target)?)?;
+        if authority.port.is_none() {
+            return Err(ParsingError::InvalidPort);
+        }
+
+        Ok(Self {
+            form: Form::Authority,
+            scheme: None,
+            authority: Some(authority),
+            path: String::new(),
+            query: None,
+            fragment: None,
+        })
+    }
+
+    pub fn form(&self) -> Form {
+        self.form
+    }
+
+    pub fn scheme(&self) -> Option<&str> {
+        self.scheme.as_deref()
+    }
+
+    pub fn authority(&self) -> Option<&Authority> {
+        self.authority.as_ref()
+    }
+
+    /// The path, which starts with a slash unless the form is authority or
+    /// asterisk. Reserved characters stay percent-encoded.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    pub fn fragment(&self) -> Option<&str> {
+        self.fragment.as_deref()
+    }
+
+    /// Replaces the path of an origin-form or absolute-form URI, which is
+    /// normalized the same way as when parsing.
+    pub fn set_path(&mut self, path: &str) -> Result<(), ParsingError> {
+        if !matches!(self.form, Form::Origin | Form::Absolute) || !path.starts_with('/') {
+            return Err(ParsingError::UnexpectedForm);
+        }
+
+        self.path = remove_dot_segments(&normalize_percent_encoding(to_str(path.as_bytes())?)?);
+        Ok(())
+    }
+
+    pub fn write_to_buffer(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(self.to_string().as_bytes());
+    }
+}
+
+impl FromStr for Uri {
+    type Err = ParsingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s.as_bytes())
+    }
+}
+
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(scheme) = &self.scheme {
+            write!(f, "{scheme}://")?;
+        }
+
+        if let Some(authority) = &self.authority {
+            write!(f, "{authority}")?;
+        }
+
+        f.write_str(&self.path)?;
+        if let Some(query) = &self.query {
+            write!(f, "?{query}")?;
+        }
+
+        if let Some(fragment) = &self.fragment {
+            write!(f, "#{fragment}")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn to_str(target: &[u8]) -> Result<&str, ParsingError> {
+    if target.is_empty() {
+        return Err(ParsingError::Empty);
+    }
+
+    if !target.iter().all(|&b| URI_MAP[b as usize] != 0) {
+        return Err(ParsingError::InvalidCharacter);
+    }
+
+    // the characters allowed by `URI_MAP` are ASCII
+    std::str::from_utf8(target).map_err(|_| ParsingError::InvalidCharacter)
+}
+
+// scheme = ALPHA *( ALPHA / DIGIT / "+" / "-" / "." )
+fn parse_scheme(scheme: &str) -> Result<String, ParsingError> {
+    let mut bytes = scheme.bytes();
+    let is_valid = bytes.next().is_some_and(|b| b.is_ascii_alphabetic())
+        && bytes.all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'-' | b'.'));
+
+    if is_valid {
+        Ok(scheme.to_ascii_lowercase())
+    } else {
+        Err(ParsingError::InvalidScheme)
+    }
+}
+
+fn default_port(scheme: &str) -> Option<u16> {
+    match scheme {
+        "http" => Some(80),
+        "https" => Some(443),
+        _ => None,
+    }
+}
+
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~')
+}
+
+// reg-name = *( unreserved / pct-encoded / sub-delims )
+fn is_reg_name_byte(b: u8) -> bool {
+    is_unreserved(b)
+        || matches!(
+            b,
+            b'%' | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'='
+        )
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+// Decodes percent-encoded unreserved characters and uses uppercase digits for
+// the rest, e.g. `%7euser%2fa` becomes `~user%2Fa`.
+fn normalize_percent_encoding(s: &str) -> Result<String, ParsingError> {
+    let bytes = s.as_bytes();
+    let mut out = String::with_capacity(s.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            out.push(char::from(bytes[i]));
+            i += 1;
+            continue;
+        }
+
+        let digits = bytes
+            .get(i + 1..i + 3)
+            .ok_or(ParsingError::InvalidPercentEncoding)?;
+        let (Some(high), Some(low)) = (hex_value(digits[0]), hex_value(digits[1])) else {
+            return Err(ParsingError::InvalidPercentEncoding);
+        };
+
+        let decoded = high << 4 | low;
+        if is_unreserved(decoded) {
+            out.push(char::from(decoded));
+        } else {
+            out.push('%');
+            out.push(char::from(digits[0].to_ascii_uppercase()));
+            out.push(char::from(digits[1].to_ascii_uppercase()));
+        }
+
+        i += 3;
+    }
+
+    Ok(out)
+}
+
+// Resolves `.` and `..` segments of an absolute path, see
+// https://www.rfc-editor.org/rfc/rfc3986#section-5.2.4
+fn remove_dot_segments(path: &str) -> String {
+    let mut output: Vec<&str> = Vec::new();
+    let mut ends_with_dot = false;
+    for segment in path.split('/').skip(1) {
+        ends_with_dot = matches!(segment, "." | "..");
+        match segment {
+            "." => {}
+            ".." => {
+                output.pop();
+            }
+            segment => output.push(segment),
+        }
+    }
+
+    let mut normalized = String::with_capacity(path.len());
+    for segment in &output {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+
+    if normalized.is_empty() || ends_with_dot {
+        normalized.push('/');
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn origin_form() {
+        let uri = Uri::parse(b"/index.html?v=3#top").unwrap();
+        assert_eq!(uri.form(), Form::Origin);
+        assert_eq!(uri.path(), "/index.html");
+        assert_eq!(uri.query(), Some("v=3"));
+        assert_eq!(uri.fragment(), Some("top"));
+        assert_eq!(uri.scheme(), None);
+        assert_eq!(uri.to_string(), "/index.html?v=3#top");
+    }
+
+    #[test]
+    fn absolute_form() {
+        let uri = Uri::parse(b"HTTP://Example.COM:80?q").unwrap();
+        assert_eq!(uri.form(), Form::Absolute);
+        assert_eq!(uri.scheme(), Some("http"));
+        assert_eq!(uri.authority().map(Authority::host), Some("example.com"));
+        assert_eq!(uri.authority().and_then(Authority::port), None);
+        assert_eq!(uri.path(), "/");
+        assert_eq!(uri.to_string(), "http://example.com/?q");
+
+        let uri = Uri::parse(b"https://[::1]:8443/a/../b").unwrap();
+        assert_eq!(uri.authority().unwrap().to_string(), "[::1]:8443");
+        assert_eq!(uri.path(), "/b");
+
+        assert_eq!(
+            Uri::parse(b"http://user@example.com/"),
+            Err(ParsingError::InvalidAuthority)
+        );
+        assert_eq!(
+            Uri::parse(b"1http://example.com/"),
+            Err(ParsingError::InvalidScheme)
+        );
+        assert_eq!(
+            Uri::parse(b"http://example.com:http/"),
+            Err(ParsingError::InvalidPort)
+        );
+    }
+
+    #[test]
+    fn authority_and_asterisk_forms() {
+        let uri = Uri::from_request_target(Method::Connect, b"Example.com:443").unwrap();
+        assert_eq!(uri.form(), Form::Authority);
+        assert_eq!(uri.to_string(), "example.com:443");
+        assert_eq!(
+            Uri::from_request_target(Method::Connect, b"example.com"),
+            Err(ParsingError::InvalidPort)
+        );
+
+        let uri = Uri::from_request_target(Method::Options, b"*").unwrap();
+        assert_eq!(uri.form(), Form::Asterisk);
+        assert_eq!(
+            Uri::from_request_target(Method::Get, b"*"),
+            Err(ParsingError::UnexpectedForm)
+        );
+    }
+
+    #[test]
+    fn normalization() {
+        let cases = [
+            ("/a/b/c/./../../g", "/a/g"),
+            ("/mid/content=5/../6", "/mid/6"),
+            ("/../../a", "/a"),
+            ("/a/b/..", "/a/"),
+            ("/a/.", "/a/"),
+            ("/a//b/", "/a//b/"),
+            ("/%7euser/%2e%2e/x", "/x"),
+            ("/a%2fb%c3%b3", "/a%2Fb%C3%B3"),
+        ];
+
+        for (target, path) in cases {
+            assert_eq!(
+                Uri::parse(target.as_bytes()).unwrap().path(),
+                path,
+                "{target}"
+            );
+        }
+
+        assert_eq!(
+            Uri::parse(b"/a%2"),
+            Err(ParsingError::InvalidPercentEncoding)
+        );
+        assert_eq!(
+            Uri::parse(b"/a%zz"),
+            Err(ParsingError::InvalidPercentEncoding)
+        );
+        assert_eq!(Uri::parse(b"/a b"), Err(ParsingError::InvalidCharacter));
+        assert_eq!(Uri::parse(b""), Err(ParsingError::Empty));
+    }
+}
@@ -0,0 +1,441 @@
+use std::borrow::Cow;
+use std::fmt::{self, Write as _};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::{general_purpose::STANDARD, Engine as _};
+use http_lib::request::ParsingError;
+use http_lib::{HeaderName, Request};
+use log::warn;
+use serde::Serialize;
+
+use crate::observer::{Exchange, Observer, Rejection};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Common Log Format, as written by Apache and nginx.
+    Common,
+    /// Common Log Format followed by the referer and the user agent.
+    Combined,
+    /// A JSON object per line, with the duration and the request ID as well.
+    Json,
+}
+
+impl LogFormat {
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Common => "common",
+            Self::Combined => "combined",
+            Self::Json => "json",
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "common" => Ok(Self::Common),
+            "combined" => Ok(Self::Combined),
+            "json" => Ok(Self::Json),
+            _ => Err("expected common, combined or json"),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+enum Target {
+    Stdout,
+    File { path: String, file: File },
+}
+
+fn open_append(path: &str) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Writes a line for every response to a file or the standard output.
+pub struct AccessLog {
+    format: LogFormat,
+    target: Mutex<Target>,
+}
+
+impl AccessLog {
+    /// Appends to the file, or writes to the standard output if the path is
+    /// `-`.
+    pub fn open(path: &str, format: LogFormat) -> io::Result<Self> {
+        let target = if path == "-" {
+            Target::Stdout
+        } else {
+            Target::File {
+                path: path.to_string(),
+                file: open_append(path)?,
+            }
+        };
+
+        Ok(Self {
+            format,
+            target: Mutex::new(target),
+        })
+    }
+
+    /// Opens the file again, e.g. after it has been moved away by logrotate.
+    pub fn reopen(&self) -> io::Result<()> {
+        let mut target = self.target.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Target::File { path, file } = &mut *target {
+            *file = open_append(path)?;
+        }
+
+        Ok(())
+    }
+
+    fn format_line(&self, exchange: &Exchange<'_>) -> String {
+        let Exchange {
+            req,
+            res,
+            peer_addr,
+            received,
+            duration,
+            bytes_sent,
+            body_bytes_sent,
+            ..
+        } = *exchange;
+
+        let client = peer_addr.map(|addr| addr.ip().to_string());
+        let user = basic_auth_user(req);
+        let referer = req.headers.get_single(&HeaderName::REFERER);
+        let user_agent = req.headers.get_single(&HeaderName::USER_AGENT);
+        let code = res.code as u16;
+
+        if self.format == LogFormat::Json {
+            let request_id = res
+                .headers
+                .get_single(&HeaderName::X_REQUEST_ID)
+                .or_else(|| req.headers.get_single(&HeaderName::X_REQUEST_ID));
+
+            let entry = JsonEntry {
+                time: rfc3339(received),
+                client: client.as_deref(),
+                user: user.as_deref(),
+                method: Some(req.method.as_str()),
+                path: Some(Cow::Owned(req.uri.to_string())),
+                version: Some(&format_version(req)),
+                status: code,
+                bytes_sent: Some(bytes_sent),
+                referer: referer.map(String::from_utf8_lossy),
+                user_agent: user_agent.map(String::from_utf8_lossy),
+                duration_ms: Some(duration.as_secs_f64() * 1000.0),
+                request_id: request_id.map(String::from_utf8_lossy),
+                error: None,
+            };
+
+            let mut line = serde_json::to_string(&entry).expect("entries are always serializable");
+            line.push('\n');
+            return line;
+        }
+
+        let mut request_line = Vec::with_capacity(64);
+        request_line.extend_from_slice(req.method.as_str().as_bytes());
+        request_line.push(b' ');
+        req.uri.write_to_buffer(&mut request_line);
+        request_line.push(b' ');
+        request_line.extend_from_slice(format_version(req).as_bytes());
+
+        let mut line = format!(
+            "{} - {} [{}] \"{}\" {code} {}",
+            client.as_deref().unwrap_or("-"),
+            user.as_deref()
+                .map_or_else(|| "-".to_string(), |u| escape(u.as_bytes())),
+            clf_time(received),
+            escape(&request_line),
+            if body_bytes_sent == 0 {
+                "-".to_string()
+            } else {
+                body_bytes_sent.to_string()
+            },
+        );
+
+        if self.format == LogFormat::Combined {
+            let quoted = |value: Option<&[u8]>| value.map_or_else(|| "-".to_string(), escape);
+            let _ = write!(line, " \"{}\" \"{}\"", quoted(referer), quoted(user_agent));
+        }
+
+        line.push('\n');
+        line
+    }
+
+    // Requests answered before reaching the handler are logged without the
+    // request line, which may not have been read or parsed.
+    fn format_rejection(&self, rejection: &Rejection) -> String {
+        let time = SystemTime::now();
+        let client = rejection.peer_addr.map(|addr| addr.ip().to_string());
+        let code = rejection.code as u16;
+
+        let mut line = match self.format {
+            LogFormat::Json => {
+                let entry = JsonEntry {
+                    time: rfc3339(time),
+                    client: client.as_deref(),
+                    user: None,
+                    method: None,
+                    path: None,
+                    version: None,
+                    status: code,
+                    bytes_sent: None,
+                    referer: None,
+                    user_agent: None,
+                    duration_ms: None,
+                    request_id: None,
+                    error: rejection.error.map(ParsingError::as_str),
+                };
+
+                serde_json::to_string(&entry).expect("entries are always serializable")
+            }
+            LogFormat::Common => format!(
+                "{} - - [{}] \"-\" {code} -",
+                client.as_deref().unwrap_or("-"),
+                clf_time(time)
+            ),
+            LogFormat::Combined => format!(
+                "{} - - [{}] \"-\" {code} - \"-\" \"-\"",
+                client.as_deref().unwrap_or("-"),
+                clf_time(time)
+            ),
+        };
+
+        line.push('\n');
+        line
+    }
+
+    fn write_line(&self, line: &str) {
+        let mut target = self.target.lock().unwrap_or_else(PoisonError::into_inner);
+        let result = match &mut *target {
+            Target::Stdout => io::stdout().lock().write_all(line.as_bytes()),
+            Target::File { file, .. } => file.write_all(line.as_bytes()),
+        };
+
+        if let Err(err) = result {
+            warn!("Failed to write to the access log {err}");
+        }
+    }
+}
+
+impl Observer for AccessLog {
+    fn response_sent(&self, exchange: &Exchange<'_>) {
+        self.write_line(&self.format_line(exchange));
+    }
+
+    fn request_rejected(&self, rejection: &Rejection) {
+        self.write_line(&self.format_rejection(rejection));
+    }
+}
+
+// Rejected requests have only the time, the client, the status and the error.
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    time: String,
+    client: Option<&'a str>,
+    user: Option<&'a str>,
+    method: Option<&'a str>,
+    path: Option<Cow<'a, str>>,
+    version: Option<&'a str>,
+    status: u16,
+    bytes_sent: Option<u64>,
+    referer: Option<Cow<'a, str>>,
+    user_agent: Option<Cow<'a, str>>,
+    duration_ms: Option<f64>,
+    request_id: Option<Cow<'a, str>>,
+    // why a rejected request could not be parsed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+fn format_version(req: &Request) -> String {
+    let http_lib::Version(major, minor) = req.version;
+    format!("HTTP/{major}.{minor}")
+}
+
+// The user name sent with Basic authentication, whether it was accepted or not.
+fn basic_auth_user(req: &Request) -> Option<String> {
+    let value = req.headers.get_single(&HeaderName::AUTHORIZATION)?;
+    let (scheme, credentials) = value.split_at(value.iter().position(|&b| b == b' ')?);
+    if !scheme.eq_ignore_ascii_case(b"Basic") {
+        return None;
+    }
+
+    let decoded = STANDARD.decode(credentials.trim_ascii()).ok()?;
+    let user = decoded.split(|&b| b == b':').next()?;
+    Some(String::from_utf8_lossy(user).into_owned())
+}
+
+// Escapes quotes, backslashes and bytes which are not printable ASCII, so that
+// the log stays one line per request and the fields can be split reliably.
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    for &b in bytes {
+        match b {
+            b'"' | b'\\' => {
+                escaped.push('\\');
+                escaped.push(char::from(b));
+            }
+            0x20..=0x7e => escaped.push(char::from(b)),
+            _ => {
+                let _ = write!(escaped, "\\x{b:02x}");
+            }
+        }
+    }
+
+    escaped
+}
+
+// The UTC date and time as (year, month, day, hours, minutes, seconds), see
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil(time: SystemTime) -> (i64, usize, u64, u64, u64, u64) {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    let z = days.cast_signed() + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (
+        year,
+        month.unsigned_abs().try_into().unwrap_or(1),
+        day.unsigned_abs(),
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+    )
+}
+
+// e.g. `10/Oct/2000:13:55:36 +0000`
+fn clf_time(time: SystemTime) -> String {
+    let (year, month, day, hours, minutes, seconds) = civil(time);
+    let month = MONTHS[month - 1];
+    format!("{day:02}/{month}/{year}:{hours:02}:{minutes:02}:{seconds:02} +0000")
+}
+
+// e.g. `2000-10-10T13:55:36.123Z`
+fn rfc3339(time: SystemTime) -> String {
+    let (year, month, day, hours, minutes, seconds) = civil(time);
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_millis());
+    format!("{year}-{month:02}-{day:02}T{hours:02}:{minutes:02}:{seconds:02}.{millis:03}Z")
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use http_lib::response::Code;
+    use http_lib::{Method, Response};
+
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(civil(UNIX_EPOCH), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(civil(at(971_186_136)), (2000, 10, 10, 13, 55, 36));
+        assert_eq!(civil(at(951_782_400)), (2000, 2, 29, 0, 0, 0));
+        assert_eq!(civil(at(946_684_799)), (1999, 12, 31, 23, 59, 59));
+        assert_eq!(civil(at(1_709_251_199)), (2024, 2, 29, 23, 59, 59));
+        assert_eq!(civil(at(4_107_542_400)), (2100, 3, 1, 0, 0, 0));
+        // times before the epoch are clamped to it
+        assert_eq!(
+            civil(UNIX_EPOCH - Duration::from_secs(1)),
+            (1970, 1, 1, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(clf_time(at(971_186_136)), "10/Oct/2000:13:55:36 +0000");
+        assert_eq!(clf_time(at(946_684_799)), "31/Dec/1999:23:59:59 +0000");
+        assert_eq!(clf_time(UNIX_EPOCH), "01/Jan/1970:00:00:00 +0000");
+
+        let time = UNIX_EPOCH + Duration::new(971_186_136, 123_456_789);
+        assert_eq!(rfc3339(time), "2000-10-10T13:55:36.123Z");
+        assert_eq!(rfc3339(at(1_709_251_199)), "2024-02-29T23:59:59.000Z");
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            escape(b"GET /index.html HTTP/1.1"),
+            "GET /index.html HTTP/1.1"
+        );
+        assert_eq!(escape(br#"say "hi" \o/"#), r#"say \"hi\" \\o/"#);
+        assert_eq!(
+            escape(b"\r\n\t\x00\x1b[31m\x7f\xff"),
+            r"\x0d\x0a\x09\x00\x1b[31m\x7f\xff"
+        );
+        assert_eq!(escape("café".as_bytes()), r"caf\xc3\xa9");
+        // a forged entry stays within the quoted field
+        assert_eq!(
+            escape(b"x\" 200 1\n127.0.0.1 - - \"GET /"),
+            r#"x\" 200 1\x0a127.0.0.1 - - \"GET /"#
+        );
+    }
+
+    #[test]
+    fn lines() {
+        let log = AccessLog::open("-", LogFormat::Combined).unwrap();
+        let mut req = Request::new(
+            "localhost".into(),
+            Method::Get,
+            "/a%20b?q=1".parse().unwrap(),
+        );
+        req.add_header_value(HeaderName::USER_AGENT, "evil\"\nagent".into());
+        req.add_header_value(HeaderName::AUTHORIZATION, "Basic dXNlcjpwYXNz".into());
+        let res = Response::new(Code::Ok);
+        let exchange = Exchange {
+            req: &req,
+            res: &res,
+            peer_addr: Some("192.0.2.1:4000".parse().unwrap()),
+            received: at(971_186_136),
+            duration: Duration::from_millis(5),
+            bytes_received: 80,
+            bytes_sent: 150,
+            body_bytes_sent: 100,
+        };
+
+        assert_eq!(
+            log.format_line(&exchange),
+            "192.0.2.1 - user [10/Oct/2000:13:55:36 +0000] \"GET /a%20b?q=1 HTTP/1.1\" \
+             200 100 \"-\" \"evil\\\"\\x0aagent\"\n"
+        );
+
+        let rejection = Rejection {
+            code: Code::BadRequest,
+            error: None,
+            peer_addr: None,
+        };
+        let line = log.format_rejection(&rejection);
+        assert!(line.starts_with("- - - ["));
+        assert!(line.ends_with("] \"-\" 400 - \"-\" \"-\"\n"));
+    }
+}
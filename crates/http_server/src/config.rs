@@ -1,15 +1,43 @@
 use pico_args::{Arguments as PicoArgs, Error as PicoError};
 use std::{
-    fmt,
+    collections::HashSet,
+    fmt, io,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::{Component, Path},
+    str::FromStr,
+    time::Duration,
 };
 
+use bytes::Bytes;
+use http_lib::HeaderName;
+use serde::Deserialize;
+
+use crate::access_log::LogFormat;
 use crate::apply_if_some;
+use crate::server::Limits;
+
+mod env;
+mod file;
 
 #[derive(Debug)]
 pub enum ParsingError {
     VerbosityOutOfBounds,
     Pico(PicoError),
+    Io(String, io::Error),
+    File(String, toml::de::Error),
+    // A value which has the right type but is not accepted, e.g. a compression
+    // level above the maximum. The key names the flag, variable or TOML key.
+    Invalid { key: String, message: String },
+    Unknown(String),
+}
+
+impl ParsingError {
+    fn invalid(key: impl Into<String>, message: impl fmt::Display) -> Self {
+        Self::Invalid {
+            key: key.into(),
+            message: message.to_string(),
+        }
+    }
 }
 
 impl From<PicoError> for ParsingError {
@@ -23,12 +51,19 @@ impl fmt::Display for ParsingError {
         match self {
             Self::Pico(err) => fmt::Display::fmt(err, f),
             Self::VerbosityOutOfBounds => write!(f, "verbosity specified more than four times"),
+            Self::Io(path, err) => write!(f, "failed to read {path}: {err}"),
+            Self::File(path, err) => write!(f, "invalid configuration in {path}: {err}"),
+            Self::Invalid { key, message } => write!(f, "invalid value for {key}: {message}"),
+            Self::Unknown(key) => write!(f, "unknown setting {key}"),
         }
     }
 }
 
 impl std::error::Error for ParsingError {}
 
+// Brotli's highest quality, gzip and deflate stop at 9.
+pub const MAX_COMPRESSION_LEVEL: u32 = 11;
+
 fn log_filter_from_int(verbosity: i32) -> log::LevelFilter {
     use log::LevelFilter::*;
     match verbosity.clamp(0, 5) {
@@ -42,9 +77,13 @@ fn log_filter_from_int(verbosity: i32) -> log::LevelFilter {
     }
 }
 
-fn parse_verbosity(args: &mut PicoArgs) -> Result<log::LevelFilter, ParsingError> {
-    let mut verbosity = 1;
-    for _ in 0..4 {
+fn parse_verbosity(args: &mut PicoArgs) -> Result<Option<log::LevelFilter>, ParsingError> {
+    if !args.contains(["-v", "--verbose"]) {
+        return Ok(None);
+    }
+
+    let mut verbosity = 2;
+    for _ in 0..3 {
         if args.contains(["-v", "--verbose"]) {
             verbosity += 1;
         } else {
@@ -55,7 +94,213 @@ fn parse_verbosity(args: &mut PicoArgs) -> Result<log::LevelFilter, ParsingError
     if args.contains(["-v", "--verbose"]) {
         Err(ParsingError::VerbosityOutOfBounds)
     } else {
-        Ok(log_filter_from_int(verbosity))
+        Ok(Some(log_filter_from_int(verbosity)))
+    }
+}
+
+fn check_compression_level(level: Option<u32>, key: &str) -> Result<Option<u32>, ParsingError> {
+    if level.is_some_and(|level| level > MAX_COMPRESSION_LEVEL) {
+        let message = format!("must be between 0 and {MAX_COMPRESSION_LEVEL}");
+        Err(ParsingError::invalid(key, message))
+    } else {
+        Ok(level)
+    }
+}
+
+fn check_metrics_path(path: Option<String>, key: &str) -> Result<Option<String>, ParsingError> {
+    match path {
+        Some(path) if !path.starts_with('/') || path.contains('?') => Err(ParsingError::invalid(
+            key,
+            "expected a path starting with /",
+        )),
+        path => Ok(path),
+    }
+}
+
+// The upload directory is given relative to the root, and may not leave it.
+fn check_upload_dir(dir: Option<String>, key: &str) -> Result<Option<String>, ParsingError> {
+    match dir {
+        Some(dir)
+            if dir.is_empty()
+                || !Path::new(&dir)
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_))) =>
+        {
+            Err(ParsingError::invalid(
+                key,
+                "expected a relative path within the root",
+            ))
+        }
+        dir => Ok(dir),
+    }
+}
+
+// Lists given on the command line replace the ones from other layers, but only
+// if the flag was used at all.
+fn non_empty<T>(values: Vec<T>) -> Option<Vec<T>> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values)
+    }
+}
+
+// A certificate used for a specific server name, given as
+// `<NAME>=<CERT>,<KEY>`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SniCertificate {
+    pub name: String,
+    pub cert: String,
+    pub key: String,
+}
+
+impl FromStr for SniCertificate {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const EXPECTED: &str = "expected <NAME>=<CERT>,<KEY>";
+        let (name, paths) = s.split_once('=').ok_or(EXPECTED)?;
+        let (cert, key) = paths.split_once(',').ok_or(EXPECTED)?;
+        if name.is_empty() || cert.is_empty() || key.is_empty() {
+            return Err(EXPECTED);
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            cert: cert.to_string(),
+            key: key.to_string(),
+        })
+    }
+}
+
+// A field added to every response, given as `<NAME>: <VALUE>`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResponseHeader {
+    pub name: String,
+    pub value: String,
+}
+
+impl ResponseHeader {
+    // Checks the name and the value, which must not contain control characters
+    // since a line break would end the field and start another one.
+    pub fn to_field(&self) -> Result<(HeaderName, Bytes), &'static str> {
+        let name =
+            HeaderName::try_from(self.name.as_bytes()).map_err(|_| "expected a field name")?;
+        if self
+            .value
+            .bytes()
+            .any(|b| b.is_ascii_control() && b != b'\t')
+        {
+            return Err("the value must not contain control characters");
+        }
+
+        Ok((name, self.value.clone().into()))
+    }
+}
+
+impl FromStr for ResponseHeader {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s.split_once(':').ok_or("expected <NAME>: <VALUE>")?;
+        let header = Self {
+            name: name.trim().to_string(),
+            value: value.trim().to_string(),
+        };
+        header.to_field()?;
+        Ok(header)
+    }
+}
+
+// A file sent instead of the default body of an error response, given as
+// `<CODE>=<PATH>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ErrorPage {
+    pub code: u16,
+    pub path: String,
+}
+
+impl FromStr for ErrorPage {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const EXPECTED: &str = "expected <CODE>=<PATH> with a 4xx or 5xx code";
+        let (code, path) = s.split_once('=').ok_or(EXPECTED)?;
+        let code = code.trim().parse().map_err(|_| EXPECTED)?;
+        if !(400..600).contains(&code) || path.is_empty() {
+            return Err(EXPECTED);
+        }
+
+        Ok(Self {
+            code,
+            path: path.to_string(),
+        })
+    }
+}
+
+// Checks a host name, which may start with `*.` to match all subdomains.
+fn check_host_name(name: &str) -> Result<(), &'static str> {
+    let name = name.strip_prefix("*.").unwrap_or(name);
+    let is_valid = !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.');
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err("expected a host name, optionally starting with *.")
+    }
+}
+
+// A site served for the requests with one of the names in the `Host` field,
+// given as `<NAME>[,<ALIAS>...]=<ROOT>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VirtualHost {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub root: String,
+    // falls back to the global setting
+    pub directory_listing: Option<bool>,
+    pub headers: Vec<ResponseHeader>,
+    pub error_pages: Vec<ErrorPage>,
+}
+
+impl VirtualHost {
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.name.as_str()).chain(self.aliases.iter().map(String::as_str))
+    }
+}
+
+impl FromStr for VirtualHost {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const EXPECTED: &str = "expected <NAME>[,<ALIAS>...]=<ROOT>";
+        let (names, root) = s.split_once('=').ok_or(EXPECTED)?;
+        let mut names = names
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase());
+        let name = names.next().ok_or(EXPECTED)?;
+        let aliases: Vec<_> = names.collect();
+        if root.is_empty() {
+            return Err(EXPECTED);
+        }
+
+        check_host_name(&name)?;
+        aliases
+            .iter()
+            .try_for_each(|alias| check_host_name(alias))?;
+        Ok(Self {
+            name,
+            aliases,
+            root: root.to_string(),
+            directory_listing: None,
+            headers: Vec::new(),
+            error_pages: Vec::new(),
+        })
     }
 }
 
@@ -63,26 +308,92 @@ fn is_localhost(addr: IpAddr) -> bool {
     addr == Ipv4Addr::LOCALHOST || addr == Ipv6Addr::LOCALHOST
 }
 
+// Settings from a single layer: the configuration file, the environment or
+// the command line. Unset values are taken from the layer below.
+#[derive(Clone, Default)]
 pub struct OptionalConfigValues {
     pub address: Option<IpAddr>,
     pub port: Option<u16>,
-    pub host: String,
-    pub verbosity: log::LevelFilter,
-    pub root: String,
+    pub host: Option<String>,
+    pub verbosity: Option<log::LevelFilter>,
+    pub root: Option<String>,
+    pub keep_alive_timeout: Option<u64>,
+    pub request_timeout: Option<u64>,
+    pub write_timeout: Option<u64>,
+    pub drain_timeout: Option<u64>,
+    pub max_requests: Option<usize>,
+    pub workers: Option<usize>,
+    pub max_connections: Option<usize>,
+    pub compression_level: Option<u32>,
+    pub compression_min_size: Option<u64>,
+    pub compression_exclude: Option<Vec<String>>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub tls_self_signed: Option<bool>,
+    pub tls_sni: Option<Vec<SniCertificate>>,
+    pub headers: Option<Vec<ResponseHeader>>,
+    pub basic_auth: Option<String>,
+    pub directory_listing: Option<bool>,
+    pub error_pages: Option<Vec<ErrorPage>>,
+    pub hosts: Option<Vec<VirtualHost>>,
+    pub default_host: Option<String>,
+    pub access_log: Option<String>,
+    pub access_log_format: Option<LogFormat>,
+    pub metrics_path: Option<String>,
+    pub metrics_port: Option<u16>,
+    pub upload_dir: Option<String>,
+    pub max_upload_size: Option<usize>,
 }
 
 impl OptionalConfigValues {
     pub fn from_pico_args(args: &mut PicoArgs) -> Result<Self, ParsingError> {
+        let compression_level = args.opt_value_from_str("--compression-level")?;
+        let metrics_path = args.opt_value_from_str("--metrics-path")?;
+        let upload_dir = args.opt_value_from_str("--upload-dir")?;
         Ok(OptionalConfigValues {
             address: args.opt_value_from_str(["-a", "--address"])?,
             port: args.opt_value_from_str(["-p", "--port"])?,
-            host: args
-                .opt_value_from_str("--host")
-                .map(Option::unwrap_or_default)?,
+            host: args.opt_value_from_str("--host")?,
             verbosity: parse_verbosity(args)?,
-            root: args.free_from_str().unwrap_or_default(),
+            keep_alive_timeout: args.opt_value_from_str("--keep-alive")?,
+            request_timeout: args.opt_value_from_str("--request-timeout")?,
+            write_timeout: args.opt_value_from_str("--write-timeout")?,
+            drain_timeout: args.opt_value_from_str("--drain-timeout")?,
+            max_requests: args.opt_value_from_str("--max-requests")?,
+            workers: args.opt_value_from_str(["-w", "--workers"])?,
+            max_connections: args.opt_value_from_str("--max-connections")?,
+            compression_level: check_compression_level(compression_level, "--compression-level")?,
+            compression_min_size: args.opt_value_from_str("--compression-min-size")?,
+            compression_exclude: non_empty(args.values_from_str("--no-compress")?),
+            tls_cert: args.opt_value_from_str("--tls-cert")?,
+            tls_key: args.opt_value_from_str("--tls-key")?,
+            tls_self_signed: args.contains("--tls-self-signed").then_some(true),
+            tls_sni: non_empty(args.values_from_str("--tls-sni")?),
+            headers: non_empty(args.values_from_str("--header")?),
+            basic_auth: args.opt_value_from_str("--basic-auth")?,
+            directory_listing: args.contains("--no-directory-listing").then_some(false),
+            error_pages: non_empty(args.values_from_str("--error-page")?),
+            hosts: non_empty(args.values_from_str("--vhost")?),
+            default_host: args.opt_value_from_str("--default-host")?,
+            access_log: args.opt_value_from_str("--access-log")?,
+            access_log_format: args.opt_value_from_str("--access-log-format")?,
+            metrics_path: check_metrics_path(metrics_path, "--metrics-path")?,
+            metrics_port: args.opt_value_from_str("--metrics-port")?,
+            upload_dir: check_upload_dir(upload_dir, "--upload-dir")?,
+            max_upload_size: args.opt_value_from_str("--max-upload-size")?,
+            root: args.opt_free_from_str()?,
         })
     }
+
+    /// Reads a TOML configuration file.
+    pub fn from_file(path: &str) -> Result<Self, ParsingError> {
+        file::load(path)
+    }
+
+    /// Reads the `HTTP_SERVER_*` environment variables.
+    pub fn from_env() -> Result<Self, ParsingError> {
+        env::load(std::env::vars_os())
+    }
 }
 
 pub struct Config {
@@ -91,43 +402,449 @@ pub struct Config {
     pub host: String,
     pub verbosity: log::LevelFilter,
     pub root: String,
+    pub keep_alive_timeout: Duration,
+    pub request_timeout: Duration,
+    pub write_timeout: Duration,
+    pub drain_timeout: Duration,
+    pub max_requests: usize,
+    pub workers: usize,
+    pub max_connections: usize,
+    pub compression_level: u32,
+    pub compression_min_size: u64,
+    pub compression_exclude: Vec<String>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub tls_self_signed: bool,
+    pub tls_sni: Vec<SniCertificate>,
+    pub headers: Vec<ResponseHeader>,
+    pub basic_auth: Option<String>,
+    pub directory_listing: bool,
+    pub error_pages: Vec<ErrorPage>,
+    pub hosts: Vec<VirtualHost>,
+    // the virtual host serving requests for unknown hosts, which are answered
+    // with `421 Misdirected Request` otherwise
+    pub default_host: Option<String>,
+    // a path, or `-` for the standard output
+    pub access_log: Option<String>,
+    pub access_log_format: LogFormat,
+    // serves the metrics on the main listener
+    pub metrics_path: Option<String>,
+    // serves the metrics at `/metrics` on a separate listener
+    pub metrics_port: Option<u16>,
+    // relative to the root of every site, decides the limit of request bodies
+    // so it is only read at startup
+    pub upload_dir: Option<String>,
+    // raises the limit of request bodies if uploads are enabled
+    pub max_upload_size: usize,
 }
 
 impl Config {
+    #[must_use]
+    pub fn limits(&self) -> Limits {
+        Limits {
+            keep_alive_timeout: self.keep_alive_timeout,
+            request_timeout: self.request_timeout,
+            write_timeout: self.write_timeout,
+            max_requests: self.max_requests,
+            workers: self.workers,
+            max_connections: self.max_connections,
+            drain_timeout: self.drain_timeout,
+            ..Limits::default()
+        }
+    }
+
+    #[must_use]
+    pub fn has_metrics(&self) -> bool {
+        self.metrics_path.is_some() || self.metrics_port.is_some()
+    }
+
+    #[must_use]
+    pub fn is_tls(&self) -> bool {
+        self.tls_cert.is_some() || self.tls_self_signed || !self.tls_sni.is_empty()
+    }
+
+    /// Layers the settings as defaults < configuration file < environment <
+    /// command line. The file is taken from `HTTP_SERVER_CONFIG` unless a path
+    /// is given.
+    pub fn load(path: Option<&str>, args: OptionalConfigValues) -> Result<Self, ParsingError> {
+        let path = path.map(str::to_string).or_else(env::config_path);
+        let file = path
+            .map(|path| OptionalConfigValues::from_file(&path))
+            .transpose()?;
+
+        Self::from_layers(
+            file.into_iter()
+                .chain([OptionalConfigValues::from_env()?, args]),
+        )
+    }
+
+    // Applies the layers over the defaults, lowest first, and checks the
+    // result.
+    fn from_layers(
+        layers: impl IntoIterator<Item = OptionalConfigValues>,
+    ) -> Result<Self, ParsingError> {
+        let mut config = Self::default();
+        for layer in layers {
+            config.apply_optional(layer);
+        }
+
+        if config.host.is_empty() && is_localhost(config.address) {
+            config.host = "localhost".to_string();
+        }
+
+        let mut names = HashSet::new();
+        if let Some(name) = config
+            .hosts
+            .iter()
+            .flat_map(VirtualHost::names)
+            .find(|&name| !names.insert(name))
+        {
+            let message = format!("{name} is given for more than one virtual host");
+            return Err(ParsingError::invalid("vhost", message));
+        }
+
+        if let Some(name) = &config.default_host {
+            let name = name.to_ascii_lowercase();
+            if !config
+                .hosts
+                .iter()
+                .any(|host| host.names().any(|n| n == name))
+            {
+                let message = format!("{name} is not one of the virtual hosts");
+                return Err(ParsingError::invalid("default-host", message));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Describes the settings which differ in another configuration, e.g.
+    /// `("root", "\".\" -> \"public\"")`.
+    #[must_use]
+    pub fn diff(&self, other: &Self) -> Vec<(&'static str, String)> {
+        let mut changes = Vec::new();
+        macro_rules! compare {
+            ($($field:ident),*) => {
+                $(
+                    if self.$field != other.$field {
+                        let change = format!("{:?} -> {:?}", self.$field, other.$field);
+                        changes.push((stringify!($field), change));
+                    }
+                )*
+            };
+        }
+
+        compare!(
+            address,
+            port,
+            host,
+            verbosity,
+            root,
+            keep_alive_timeout,
+            request_timeout,
+            write_timeout,
+            drain_timeout,
+            max_requests,
+            workers,
+            max_connections,
+            compression_level,
+            compression_min_size,
+            compression_exclude,
+            tls_cert,
+            tls_key,
+            tls_self_signed,
+            tls_sni,
+            headers,
+            directory_listing,
+            error_pages,
+            hosts,
+            default_host,
+            access_log,
+            access_log_format,
+            metrics_path,
+            metrics_port,
+            upload_dir,
+            max_upload_size
+        );
+
+        // the credentials are not logged
+        if self.basic_auth != other.basic_auth {
+            changes.push(("basic_auth", "changed".to_string()));
+        }
+
+        changes
+    }
+
     pub fn apply_optional(&mut self, partial: OptionalConfigValues) {
         apply_if_some!(self.address, partial.address);
         apply_if_some!(self.port, partial.port);
+        apply_if_some!(self.host, partial.host);
+        apply_if_some!(self.verbosity, partial.verbosity);
+        apply_if_some!(self.root, partial.root);
+        apply_if_some!(self.max_requests, partial.max_requests);
+        apply_if_some!(self.workers, partial.workers);
+        apply_if_some!(self.max_connections, partial.max_connections);
+        apply_if_some!(self.compression_level, partial.compression_level);
+        apply_if_some!(self.compression_min_size, partial.compression_min_size);
+        apply_if_some!(self.compression_exclude, partial.compression_exclude);
+        apply_if_some!(self.tls_self_signed, partial.tls_self_signed);
+        apply_if_some!(self.tls_sni, partial.tls_sni);
+        apply_if_some!(self.headers, partial.headers);
+        apply_if_some!(self.directory_listing, partial.directory_listing);
+        apply_if_some!(self.error_pages, partial.error_pages);
+        apply_if_some!(self.hosts, partial.hosts);
+        apply_if_some!(self.access_log_format, partial.access_log_format);
+        apply_if_some!(self.max_upload_size, partial.max_upload_size);
 
-        if !partial.host.is_empty() {
-            self.host = partial.host;
-        } else if is_localhost(self.address) {
-            self.host = "localhost".to_string();
+        if let Some(secs) = partial.keep_alive_timeout {
+            self.keep_alive_timeout = Duration::from_secs(secs);
         }
 
-        if !partial.root.is_empty() {
-            self.root = partial.root;
+        if let Some(secs) = partial.request_timeout {
+            self.request_timeout = Duration::from_secs(secs);
         }
 
-        self.verbosity = partial.verbosity;
-    }
-}
+        if let Some(secs) = partial.write_timeout {
+            self.write_timeout = Duration::from_secs(secs);
+        }
 
-impl From<OptionalConfigValues> for Config {
-    fn from(partial: OptionalConfigValues) -> Self {
-        let mut config = Self::default();
-        config.apply_optional(partial);
-        config
+        if let Some(secs) = partial.drain_timeout {
+            self.drain_timeout = Duration::from_secs(secs);
+        }
+
+        self.tls_cert = partial.tls_cert.or(self.tls_cert.take());
+        self.tls_key = partial.tls_key.or(self.tls_key.take());
+        self.basic_auth = partial.basic_auth.or(self.basic_auth.take());
+        self.default_host = partial.default_host.or(self.default_host.take());
+        self.access_log = partial.access_log.or(self.access_log.take());
+        self.metrics_path = partial.metrics_path.or(self.metrics_path.take());
+        self.metrics_port = partial.metrics_port.or(self.metrics_port);
+        self.upload_dir = partial.upload_dir.or(self.upload_dir.take());
     }
 }
 
 impl Default for Config {
     fn default() -> Self {
+        let limits = Limits::default();
         Self {
             address: Ipv4Addr::LOCALHOST.into(),
             port: 8000,
             host: String::new(),
             root: ".".to_string(),
             verbosity: log::LevelFilter::Error,
+            keep_alive_timeout: limits.keep_alive_timeout,
+            request_timeout: limits.request_timeout,
+            write_timeout: limits.write_timeout,
+            drain_timeout: limits.drain_timeout,
+            max_requests: limits.max_requests,
+            workers: limits.workers,
+            max_connections: limits.max_connections,
+            compression_level: 6,
+            compression_min_size: 1024,
+            compression_exclude: Vec::new(),
+            tls_cert: None,
+            tls_key: None,
+            tls_self_signed: false,
+            tls_sni: Vec::new(),
+            headers: Vec::new(),
+            basic_auth: None,
+            directory_listing: true,
+            error_pages: Vec::new(),
+            hosts: Vec::new(),
+            default_host: None,
+            access_log: None,
+            access_log_format: LogFormat::Combined,
+            metrics_path: None,
+            metrics_port: None,
+            upload_dir: None,
+            max_upload_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsString;
+
+    use super::*;
+
+    #[test]
+    fn layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("http-server.toml");
+        std::fs::write(
+            &path,
+            r#"
+                root = "public"
+                host = "file.example"
+
+                [listener]
+                port = 1000
+
+                [limits]
+                keep-alive = 7
+
+                [[headers]]
+                name = "Link"
+                value = "</a.css>"
+
+                [[headers]]
+                name = "Link"
+                value = "</b.js>"
+            "#,
+        )
+        .unwrap();
+        let file = file::load(path.to_str().unwrap());
+
+        let env = env::load(
+            [
+                ("HTTP_SERVER_ROOT", "env-root"),
+                ("HTTP_SERVER_PORT", "2000"),
+                ("HTTP_SERVER_WORKERS", "3"),
+            ]
+            .into_iter()
+            .map(|(key, value)| (OsString::from(key), OsString::from(value))),
+        );
+
+        let mut args = PicoArgs::from_vec(vec!["-p".into(), "3000".into()]);
+        let args = OptionalConfigValues::from_pico_args(&mut args);
+
+        let config = Config::from_layers([file.unwrap(), env.unwrap(), args.unwrap()]).unwrap();
+        let defaults = Config::default();
+        assert_eq!(config.port, 3000);
+        assert_eq!(config.root, "env-root");
+        assert_eq!(config.workers, 3);
+        assert_eq!(config.host, "file.example");
+        assert_eq!(config.keep_alive_timeout, Duration::from_secs(7));
+        assert_eq!(config.max_requests, defaults.max_requests);
+        assert_eq!(config.address, defaults.address);
+        let links: Vec<_> = config.headers.iter().map(|h| h.value.as_str()).collect();
+        assert_eq!(links, ["</a.css>", "</b.js>"]);
+    }
+
+    #[test]
+    fn defaults() {
+        let config = Config::from_layers([]).unwrap();
+        assert_eq!(config.port, 8000);
+        assert_eq!(config.root, ".");
+        // a loopback address is served as localhost unless a host is set
+        assert_eq!(config.host, "localhost");
+    }
+
+    #[test]
+    fn default_host() {
+        let layer = |default_host: &str| OptionalConfigValues {
+            hosts: Some(vec!["example.com,www.example.com=site".parse().unwrap()]),
+            default_host: Some(default_host.to_string()),
+            ..OptionalConfigValues::default()
+        };
+
+        assert!(Config::from_layers([layer("WWW.example.com")]).is_ok());
+        assert!(Config::from_layers([layer("example.org")]).is_err());
+    }
+
+    #[test]
+    fn duplicate_hosts() {
+        let layer = |hosts: &[&str]| OptionalConfigValues {
+            hosts: Some(hosts.iter().map(|host| host.parse().unwrap()).collect()),
+            ..OptionalConfigValues::default()
+        };
+
+        let hosts = ["example.com,*.example.com=a", "example.org=b"];
+        assert!(Config::from_layers([layer(&hosts)]).is_ok());
+        for hosts in [
+            ["example.com=a", "Example.com=b"],
+            ["example.com=a", "example.org,example.com=b"],
+            ["*.example.com=a", "example.org,*.example.com=b"],
+            ["example.com,example.com=a", "example.org=b"],
+        ] {
+            let err = Config::from_layers([layer(&hosts)]).err().unwrap();
+            assert!(matches!(err, ParsingError::Invalid { key, .. } if key == "vhost"));
+        }
+    }
+
+    #[test]
+    fn upload_dir() {
+        for dir in ["uploads", "files/uploads", "uploads/"] {
+            assert_eq!(
+                check_upload_dir(Some(dir.to_string()), "upload_dir").unwrap(),
+                Some(dir.to_string())
+            );
+        }
+
+        assert_eq!(check_upload_dir(None, "upload_dir").unwrap(), None);
+        for dir in [
+            "",
+            "/uploads",
+            "../uploads",
+            "uploads/..",
+            "./uploads",
+            "a/../b",
+        ] {
+            let err = check_upload_dir(Some(dir.to_string()), "upload_dir").unwrap_err();
+            assert!(matches!(err, ParsingError::Invalid { key, .. } if key == "upload_dir"));
+        }
+    }
+
+    #[test]
+    fn response_header() {
+        assert_eq!(
+            " X-Frame-Options :  DENY ".parse(),
+            Ok(ResponseHeader {
+                name: "X-Frame-Options".to_string(),
+                value: "DENY".to_string(),
+            })
+        );
+        assert_eq!(
+            "Link: <https://example.com/a.css>; rel=preload".parse(),
+            Ok(ResponseHeader {
+                name: "Link".to_string(),
+                value: "<https://example.com/a.css>; rel=preload".to_string(),
+            })
+        );
+
+        for value in [
+            "X-Frame-Options",
+            ": DENY",
+            "X Frame: DENY",
+            "X-Frame-Options: DENY\r\nSet-Cookie: id=1",
+            "X-Frame-Options: DENY\nSet-Cookie: id=1",
+            "X-Frame-Options: DE\0NY",
+            "X-Frame-Options: DE\x7fNY",
+        ] {
+            assert!(value.parse::<ResponseHeader>().is_err(), "{value:?}");
+        }
+
+        let header = ResponseHeader {
+            name: "X-Note".to_string(),
+            value: "tab\tand ünicode".to_string(),
+        };
+        assert_eq!(
+            header.to_field(),
+            Ok((HeaderName::from_static("X-Note"), "tab\tand ünicode".into()))
+        );
+    }
+
+    #[test]
+    fn sni_certificate() {
+        assert_eq!(
+            "example.com=certs/example.pem,keys/example.key".parse(),
+            Ok(SniCertificate {
+                name: "example.com".to_string(),
+                cert: "certs/example.pem".to_string(),
+                key: "keys/example.key".to_string(),
+            })
+        );
+
+        for value in [
+            "",
+            "example.com",
+            "example.com=cert.pem",
+            "=cert.pem,key.pem",
+            "example.com=,key.pem",
+            "example.com=cert.pem,",
+        ] {
+            assert!(value.parse::<SniCertificate>().is_err(), "{value}");
         }
     }
 }
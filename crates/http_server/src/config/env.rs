@@ -0,0 +1,199 @@
+use std::ffi::OsString;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use super::{
+    check_compression_level, check_metrics_path, check_upload_dir, OptionalConfigValues,
+    ParsingError,
+};
+
+const PREFIX: &str = "HTTP_SERVER_";
+
+// Path of the configuration file, used when `--config` is not given.
+const CONFIG: &str = "HTTP_SERVER_CONFIG";
+
+pub fn config_path() -> Option<String> {
+    std::env::var(CONFIG).ok().filter(|path| !path.is_empty())
+}
+
+fn parse<T>(key: &str, value: &str) -> Result<T, ParsingError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value.parse().map_err(|err| ParsingError::invalid(key, err))
+}
+
+// Lists are separated with semicolons, since commas are used within
+// `--tls-sni` values and header values.
+fn parse_list<T>(key: &str, value: &str) -> Result<Vec<T>, ParsingError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| parse(key, item))
+        .collect()
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, ParsingError> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" | "" => Ok(false),
+        _ => Err(ParsingError::invalid(key, "expected true or false")),
+    }
+}
+
+pub fn load(
+    vars: impl Iterator<Item = (OsString, OsString)>,
+) -> Result<OptionalConfigValues, ParsingError> {
+    let mut partial = OptionalConfigValues::default();
+    for (key, value) in vars {
+        let Some(key) = key.to_str().filter(|key| key.starts_with(PREFIX)) else {
+            continue;
+        };
+
+        let Some(value) = value.to_str() else {
+            return Err(ParsingError::invalid(key, "not valid UTF-8"));
+        };
+
+        match &key[PREFIX.len()..] {
+            "CONFIG" => {}
+            "ADDRESS" => partial.address = Some(parse(key, value)?),
+            "PORT" => partial.port = Some(parse(key, value)?),
+            "HOST" => partial.host = Some(value.to_string()),
+            "ROOT" => partial.root = Some(value.to_string()),
+            "LOG_LEVEL" => partial.verbosity = Some(parse(key, value)?),
+            "KEEP_ALIVE" => partial.keep_alive_timeout = Some(parse(key, value)?),
+            "REQUEST_TIMEOUT" => partial.request_timeout = Some(parse(key, value)?),
+            "WRITE_TIMEOUT" => partial.write_timeout = Some(parse(key, value)?),
+            "DRAIN_TIMEOUT" => partial.drain_timeout = Some(parse(key, value)?),
+            "MAX_REQUESTS" => partial.max_requests = Some(parse(key, value)?),
+            "WORKERS" => partial.workers = Some(parse(key, value)?),
+            "MAX_CONNECTIONS" => partial.max_connections = Some(parse(key, value)?),
+            "COMPRESSION_LEVEL" => {
+                partial.compression_level = check_compression_level(Some(parse(key, value)?), key)?;
+            }
+            "COMPRESSION_MIN_SIZE" => partial.compression_min_size = Some(parse(key, value)?),
+            "NO_COMPRESS" => partial.compression_exclude = Some(parse_list(key, value)?),
+            "TLS_CERT" => partial.tls_cert = Some(value.to_string()),
+            "TLS_KEY" => partial.tls_key = Some(value.to_string()),
+            "TLS_SELF_SIGNED" => partial.tls_self_signed = Some(parse_bool(key, value)?),
+            "TLS_SNI" => partial.tls_sni = Some(parse_list(key, value)?),
+            "HEADERS" => partial.headers = Some(parse_list(key, value)?),
+            "BASIC_AUTH" => partial.basic_auth = Some(value.to_string()),
+            "DIRECTORY_LISTING" => partial.directory_listing = Some(parse_bool(key, value)?),
+            "ERROR_PAGES" => partial.error_pages = Some(parse_list(key, value)?),
+            "VHOSTS" => partial.hosts = Some(parse_list(key, value)?),
+            "DEFAULT_HOST" => partial.default_host = Some(value.to_string()),
+            "ACCESS_LOG" => partial.access_log = Some(value.to_string()),
+            "ACCESS_LOG_FORMAT" => partial.access_log_format = Some(parse(key, value)?),
+            "METRICS_PATH" => {
+                partial.metrics_path = check_metrics_path(Some(value.to_string()), key)?;
+            }
+            "METRICS_PORT" => partial.metrics_port = Some(parse(key, value)?),
+            "UPLOAD_DIR" => partial.upload_dir = check_upload_dir(Some(value.to_string()), key)?,
+            "MAX_UPLOAD_SIZE" => partial.max_upload_size = Some(parse(key, value)?),
+            _ => return Err(ParsingError::Unknown(key.to_string())),
+        }
+    }
+
+    Ok(partial)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::ResponseHeader;
+
+    fn vars(vars: &[(&str, &str)]) -> impl Iterator<Item = (OsString, OsString)> {
+        vars.iter()
+            .map(|&(key, value)| (key.into(), value.into()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    // The values are not `Debug`, since they may hold credentials.
+    fn error(vars: impl Iterator<Item = (OsString, OsString)>) -> ParsingError {
+        match load(vars) {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn values() {
+        let partial = load(vars(&[
+            ("PATH", "/usr/bin"),
+            ("HTTP_SERVER_CONFIG", "server.toml"),
+            ("HTTP_SERVER_PORT", "8080"),
+            ("HTTP_SERVER_ROOT", "public"),
+            ("HTTP_SERVER_TLS_SELF_SIGNED", "Yes"),
+            ("HTTP_SERVER_DIRECTORY_LISTING", "0"),
+            (
+                "HTTP_SERVER_HEADERS",
+                "Link: </a.css>, </b.js>;; X-Frame-Options: DENY",
+            ),
+            ("HTTP_SERVER_NO_COMPRESS", " image/* ; video/*"),
+        ]))
+        .unwrap();
+
+        assert_eq!(partial.port, Some(8080));
+        assert_eq!(partial.root.as_deref(), Some("public"));
+        assert_eq!(partial.tls_self_signed, Some(true));
+        assert_eq!(partial.directory_listing, Some(false));
+        assert_eq!(
+            partial.headers.unwrap(),
+            [
+                ResponseHeader {
+                    name: "Link".to_string(),
+                    value: "</a.css>, </b.js>".to_string(),
+                },
+                ResponseHeader {
+                    name: "X-Frame-Options".to_string(),
+                    value: "DENY".to_string(),
+                },
+            ]
+        );
+        assert_eq!(partial.compression_exclude.unwrap(), ["image/*", "video/*"]);
+        assert_eq!(partial.host, None);
+        assert_eq!(partial.workers, None);
+    }
+
+    #[test]
+    fn errors() {
+        let err = error(vars(&[("HTTP_SERVER_PROT", "8080")]));
+        assert!(matches!(err, ParsingError::Unknown(key) if key == "HTTP_SERVER_PROT"));
+
+        let err = error(vars(&[("HTTP_SERVER_PORT", "http")]));
+        assert!(matches!(err, ParsingError::Invalid { key, .. } if key == "HTTP_SERVER_PORT"));
+
+        let err = error(vars(&[("HTTP_SERVER_TLS_SELF_SIGNED", "maybe")]));
+        assert!(matches!(err, ParsingError::Invalid { .. }));
+
+        let err = error(vars(&[("HTTP_SERVER_UPLOAD_DIR", "../uploads")]));
+        assert!(matches!(err, ParsingError::Invalid { .. }));
+
+        let err = error(vars(&[("HTTP_SERVER_COMPRESSION_LEVEL", "12")]));
+        assert!(matches!(err, ParsingError::Invalid { .. }));
+
+        let err = error(vars(&[(
+            "HTTP_SERVER_HEADERS",
+            "X-A: a\r\nSet-Cookie: id=1",
+        )]));
+        assert!(matches!(err, ParsingError::Invalid { key, .. } if key == "HTTP_SERVER_HEADERS"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn not_utf8() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let value = OsString::from_vec(b"/srv/\xff".to_vec());
+        let err = error([("HTTP_SERVER_ROOT".into(), value)].into_iter());
+        assert!(matches!(err, ParsingError::Invalid { key, .. } if key == "HTTP_SERVER_ROOT"));
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::{
+    check_compression_level, check_host_name, check_metrics_path, check_upload_dir, ErrorPage,
+    OptionalConfigValues, ParsingError, ResponseHeader, SniCertificate, VirtualHost,
+};
+
+// The layout of the configuration file, e.g.
+//
+// ```toml
+// root = "public"
+// log-level = "info"
+// access-log = "logs/access.log"
+// upload-dir = "uploads"
+//
+// [listener]
+// address = "0.0.0.0"
+// port = 8443
+// tls-cert = "cert.pem"
+// tls-key = "key.pem"
+//
+// [metrics]
+// port = 9100
+//
+// [[headers]]
+// name = "Link"
+// value = "</style.css>; rel=preload"
+//
+// [[headers]]
+// name = "Link"
+// value = "</script.js>; rel=preload"
+//
+// [[hosts]]
+// name = "example.com"
+// aliases = ["*.example.com"]
+// root = "sites/example"
+//
+// [hosts.error-pages]
+// 404 = "sites/example/404.html"
+// ```
+//
+// Relative paths are resolved against the directory of the file. Headers are
+// a list rather than a table, so that a field may be added more than once.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct File {
+    root: Option<String>,
+    host: Option<String>,
+    log_level: Option<String>,
+    basic_auth: Option<String>,
+    directory_listing: Option<bool>,
+    default_host: Option<String>,
+    access_log: Option<String>,
+    access_log_format: Option<String>,
+    upload_dir: Option<String>,
+    listener: Listener,
+    limits: Limits,
+    compression: Compression,
+    metrics: Metrics,
+    headers: Option<Vec<ResponseHeader>>,
+    error_pages: Option<BTreeMap<String, String>>,
+    hosts: Option<Vec<Host>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Host {
+    name: String,
+    #[serde(default)]
+    aliases: Vec<String>,
+    root: String,
+    directory_listing: Option<bool>,
+    #[serde(default)]
+    headers: Vec<ResponseHeader>,
+    #[serde(default)]
+    error_pages: BTreeMap<String, String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Listener {
+    address: Option<IpAddr>,
+    port: Option<u16>,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    tls_self_signed: Option<bool>,
+    tls_sni: Option<Vec<SniCertificate>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Limits {
+    keep_alive: Option<u64>,
+    request_timeout: Option<u64>,
+    write_timeout: Option<u64>,
+    drain_timeout: Option<u64>,
+    max_requests: Option<usize>,
+    workers: Option<usize>,
+    max_connections: Option<usize>,
+    max_upload_size: Option<usize>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Compression {
+    level: Option<u32>,
+    min_size: Option<u64>,
+    exclude: Option<Vec<String>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Metrics {
+    path: Option<String>,
+    port: Option<u16>,
+}
+
+pub fn load(path: &str) -> Result<OptionalConfigValues, ParsingError> {
+    let contents =
+        std::fs::read_to_string(path).map_err(|err| ParsingError::Io(path.to_string(), err))?;
+    let file: File =
+        toml::from_str(&contents).map_err(|err| ParsingError::File(path.to_string(), err))?;
+
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let resolve = |p: String| dir.join(p).to_string_lossy().into_owned();
+    let key = |key: &str| format!("{key} in {path}");
+
+    let verbosity = match file.log_level {
+        Some(level) => Some(level.parse().map_err(|_| {
+            ParsingError::invalid(
+                key("log-level"),
+                "expected one of off, error, warn, info, debug or trace",
+            )
+        })?),
+        None => None,
+    };
+
+    let access_log_format = file
+        .access_log_format
+        .map(|format| {
+            format
+                .parse()
+                .map_err(|err| ParsingError::invalid(key("access-log-format"), err))
+        })
+        .transpose()?;
+
+    let headers = file
+        .headers
+        .map(|headers| parse_headers(headers, key))
+        .transpose()?;
+
+    let error_pages = file
+        .error_pages
+        .map(|pages| parse_error_pages(pages, resolve, key))
+        .transpose()?;
+
+    let hosts = file
+        .hosts
+        .map(|hosts| {
+            hosts
+                .into_iter()
+                .map(|host| parse_host(host, resolve, key))
+                .collect::<Result<_, _>>()
+        })
+        .transpose()?;
+
+    let tls_sni = file.listener.tls_sni.map(|certs| {
+        certs
+            .into_iter()
+            .map(|SniCertificate { name, cert, key }| SniCertificate {
+                name,
+                cert: resolve(cert),
+                key: resolve(key),
+            })
+            .collect()
+    });
+
+    Ok(OptionalConfigValues {
+        address: file.listener.address,
+        port: file.listener.port,
+        host: file.host,
+        verbosity,
+        root: file.root.map(resolve),
+        keep_alive_timeout: file.limits.keep_alive,
+        request_timeout: file.limits.request_timeout,
+        write_timeout: file.limits.write_timeout,
+        drain_timeout: file.limits.drain_timeout,
+        max_requests: file.limits.max_requests,
+        workers: file.limits.workers,
+        max_connections: file.limits.max_connections,
+        compression_level: check_compression_level(
+            file.compression.level,
+            &key("compression.level"),
+        )?,
+        compression_min_size: file.compression.min_size,
+        compression_exclude: file.compression.exclude,
+        tls_cert: file.listener.tls_cert.map(resolve),
+        tls_key: file.listener.tls_key.map(resolve),
+        tls_self_signed: file.listener.tls_self_signed,
+        tls_sni,
+        headers,
+        basic_auth: file.basic_auth,
+        directory_listing: file.directory_listing,
+        error_pages,
+        hosts,
+        default_host: file.default_host,
+        access_log: file
+            .access_log
+            .map(|path| if path == "-" { path } else { resolve(path) }),
+        access_log_format,
+        metrics_path: check_metrics_path(file.metrics.path, &key("metrics.path"))?,
+        metrics_port: file.metrics.port,
+        upload_dir: check_upload_dir(file.upload_dir, &key("upload-dir"))?,
+        max_upload_size: file.limits.max_upload_size,
+    })
+}
+
+fn parse_host(
+    host: Host,
+    resolve: impl Fn(String) -> String,
+    key: impl Fn(&str) -> String,
+) -> Result<VirtualHost, ParsingError> {
+    let name = host.name.to_ascii_lowercase();
+    let key = |k: &str| key(&format!("{k} of host {name}"));
+    for n in std::iter::once(&name).chain(&host.aliases) {
+        check_host_name(n).map_err(|err| ParsingError::invalid(key("name"), err))?;
+    }
+
+    Ok(VirtualHost {
+        aliases: host
+            .aliases
+            .iter()
+            .map(|alias| alias.to_ascii_lowercase())
+            .collect(),
+        root: resolve(host.root),
+        directory_listing: host.directory_listing,
+        headers: parse_headers(host.headers, key)?,
+        error_pages: parse_error_pages(host.error_pages, &resolve, key)?,
+        name,
+    })
+}
+
+// Checks the names the same way as those of the `--header` values.
+fn parse_headers(
+    headers: Vec<ResponseHeader>,
+    key: impl Fn(&str) -> String,
+) -> Result<Vec<ResponseHeader>, ParsingError> {
+    headers
+        .into_iter()
+        .map(|ResponseHeader { name, value }| {
+            let header = ResponseHeader {
+                name: name.trim().to_string(),
+                value: value.trim().to_string(),
+            };
+            match header.to_field() {
+                Ok(_) => Ok(header),
+                Err(err) => Err(ParsingError::invalid(
+                    key(&format!("headers.{}", header.name)),
+                    err,
+                )),
+            }
+        })
+        .collect()
+}
+
+// The keys are status codes, e.g. `404 = "errors/not-found.html"`.
+fn parse_error_pages(
+    pages: BTreeMap<String, String>,
+    resolve: impl Fn(String) -> String,
+    key: impl Fn(&str) -> String,
+) -> Result<Vec<ErrorPage>, ParsingError> {
+    pages
+        .into_iter()
+        .map(|(code, path)| {
+            let page = format!("{code}={path}")
+                .parse::<ErrorPage>()
+                .map_err(|err| ParsingError::invalid(key(&format!("error-pages.{code}")), err))?;
+
+            Ok(ErrorPage {
+                path: resolve(page.path),
+                ..page
+            })
+        })
+        .collect()
+}
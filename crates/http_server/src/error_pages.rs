@@ -0,0 +1,100 @@
+use std::fs::File;
+
+use http_lib::response::{Body, Code};
+use http_lib::{HeaderName, Response};
+use log::warn;
+
+use crate::config::ErrorPage;
+
+/// Files sent instead of the default body of error responses, chosen by the
+/// status code.
+#[derive(Clone, Debug, Default)]
+pub struct ErrorPages {
+    pages: Vec<ErrorPage>,
+}
+
+impl ErrorPages {
+    #[must_use]
+    pub fn new(pages: &[ErrorPage]) -> Self {
+        Self {
+            pages: pages.to_vec(),
+        }
+    }
+
+    /// Replaces the body of an error response with the page configured for
+    /// its code. Other responses are left alone.
+    pub fn apply(&self, res: &mut Response) {
+        let code = res.code as u16;
+        if !(400..600).contains(&code) {
+            return;
+        }
+
+        let Some(page) = self.pages.iter().find(|page| page.code == code) else {
+            return;
+        };
+
+        let opened = File::open(&page.path).and_then(|file| Ok((file.metadata()?, file)));
+        let (metadata, file) = match opened {
+            Ok((metadata, file)) if metadata.is_file() => (metadata, file),
+            _ => {
+                warn!("Failed to open the error page {}", page.path);
+                return;
+            }
+        };
+
+        let mime_type = mime_guess::from_path(&page.path)
+            .first_or_octet_stream()
+            .to_string();
+        res.headers.remove(&HeaderName::CONTENT_LENGTH);
+        res.headers.remove(&HeaderName::CONTENT_TYPE);
+        res.body_of_type(Body::file(file, 0, metadata.len()), mime_type.into());
+    }
+
+    /// Returns a response with the code and the page configured for it.
+    #[must_use]
+    pub fn response(&self, code: Code) -> Response {
+        let mut res = Response::new(code);
+        self.apply(&mut res);
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn apply() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("404.html");
+        std::fs::write(&path, "<h1>Not here</h1>").unwrap();
+        let pages = ErrorPages::new(&[
+            ErrorPage {
+                code: 404,
+                path: path.to_str().unwrap().to_string(),
+            },
+            ErrorPage {
+                code: 500,
+                path: "/nonexistent/500.html".to_string(),
+            },
+        ]);
+
+        let res = pages.response(Code::NotFound);
+        assert_eq!(res.body.len(), Some(17));
+        assert_eq!(
+            res.headers.get_single(&HeaderName::CONTENT_TYPE).unwrap(),
+            b"text/html".as_slice()
+        );
+
+        // the default body is kept if the page cannot be opened
+        assert_eq!(
+            pages.response(Code::InternalServerError).body.len(),
+            Some(0)
+        );
+        assert_eq!(pages.response(Code::Forbidden).body.len(), Some(0));
+
+        let mut res = Response::builder(Code::Ok).body("ok".to_string()).finish();
+        pages.apply(&mut res);
+        assert_eq!(res.body.len(), Some(2));
+    }
+}
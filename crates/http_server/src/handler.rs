@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use http_lib::response::Code;
+use http_lib::{Request, Response};
+
+/// Produces a response for every request read from a connection. Handlers are
+/// shared between worker threads.
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, req: &Request) -> Response;
+
+    /// Returns the response for a request rejected before it reached the
+    /// handler, e.g. because it was malformed or too large.
+    fn error_response(&self, code: Code) -> Response {
+        Response::new(code)
+    }
+
+    /// Returns the largest body accepted for a request, before its body is
+    /// read. `None` keeps the server's `max_body_len`.
+    fn max_body_len(&self, _head: &Request) -> Option<usize> {
+        None
+    }
+
+    /// Returns the handler for all the requests of a connection, taken when
+    /// the connection is accepted. Handlers which may be replaced while the
+    /// server is running return the current one.
+    fn for_connection(self: Arc<Self>) -> Arc<dyn Handler>
+    where
+        Self: Sized,
+    {
+        self
+    }
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    fn handle(&self, req: &Request) -> Response {
+        self(req)
+    }
+}
@@ -0,0 +1,37 @@
+#![warn(clippy::all, clippy::pedantic)]
+#![allow(clippy::enum_glob_use)]
+#![allow(clippy::missing_errors_doc)]
+#![allow(clippy::similar_names)] // allow usage of `req` and `res`
+
+pub mod access_log;
+pub mod config;
+pub mod error_pages;
+pub mod handler;
+pub mod metrics;
+pub mod middleware;
+pub mod observer;
+pub mod reload;
+pub mod router;
+pub mod routing;
+pub mod server;
+pub mod tls;
+pub mod vhost;
+
+mod connection;
+mod macros;
+#[cfg(target_os = "linux")]
+mod send_file;
+mod stream_handler;
+mod upload;
+mod worker_pool;
+
+pub use error_pages::ErrorPages;
+pub use handler::Handler;
+pub use metrics::Metrics;
+pub use middleware::{Middleware, Stack};
+pub use observer::Observer;
+pub use reload::Reloadable;
+pub use router::Router;
+pub use routing::{Params, Routes};
+pub use server::{Limits, Server};
+pub use vhost::VirtualHosts;
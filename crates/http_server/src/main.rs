@@ -1,22 +1,16 @@
 #![warn(clippy::all, clippy::pedantic)]
-#![allow(clippy::enum_glob_use)]
-#![allow(clippy::similar_names)] // allow usage of `req` and `res`
-#![allow(dead_code)]
 
-use std::{io, net::TcpListener, process::exit};
+use std::{io, process::exit, sync::Arc};
 
-use handlebars::Handlebars;
-use log::warn;
+use log::{error, info, warn};
 use pico_args::Arguments as PicoArgs;
 
-mod config;
-mod macros;
-mod router;
-mod stream_handler;
-
-use config::{Config, OptionalConfigValues};
-use router::Router;
-use stream_handler::StreamHandler;
+use http_server::access_log::AccessLog;
+use http_server::config::{Config, OptionalConfigValues, ParsingError};
+use http_server::metrics::Endpoint;
+use http_server::middleware::{BasicAuth, Compressor, RequestId, RequestLog, SetHeaders};
+use http_server::server::{Shutdown, ShutdownHandle};
+use http_server::{tls, Metrics, Reloadable, Server, Stack, VirtualHosts};
 
 const VERSION: &str = "http-server, version 0.0.0";
 
@@ -28,12 +22,108 @@ OPTIONS:
     -a --address <ADDRESS>      Address to use
     -p --port <PORT>            Port to use
        --host <HOST>            Expected Host header value (if it is not an IP address)
+       --keep-alive <SECONDS>   Idle timeout of persistent connections; 0 disables them
+       --request-timeout <SECONDS>
+                                Time given to a client to send a whole request
+       --write-timeout <SECONDS>
+                                Time a client may stop reading a response; 0 waits forever
+       --max-requests <COUNT>   Maximum number of requests served per connection
+    -w --workers <COUNT>        Number of worker threads (defaults to the number of CPUs)
+       --max-connections <COUNT>
+                                Maximum number of connections open at the same time
+       --drain-timeout <SECONDS>
+                                Time given to open connections to finish on shutdown
+       --compression-level <LEVEL>
+                                Compression level from 1 to 11, gzip stops at 9;
+                                0 disables compression
+       --compression-min-size <BYTES>
+                                Smallest response body which gets compressed
+       --no-compress <MIME>     Never compress this type, e.g. image/svg+xml or text/*;
+                                can be repeated
+       --tls-cert <PATH>        PEM certificate chain; enables HTTPS
+       --tls-key <PATH>         PEM private key (PKCS#8, PKCS#1 or SEC1)
+       --tls-sni <NAME>=<CERT>,<KEY>
+                                Certificate used when the client asks for NAME;
+                                can be repeated
+       --tls-self-signed        Serve HTTPS with an ephemeral certificate for localhost
+       --header <NAME:VALUE>    Field added to every response; can be repeated
+       --basic-auth <USER:PASSWORD>
+                                Require HTTP Basic authentication
+       --access-log <PATH>      Write a line for every response to PATH, or to the
+                                standard output if PATH is -
+       --access-log-format <FORMAT>
+                                common, combined (default) or json
+       --metrics-path <PATH>    Serve Prometheus metrics at PATH, e.g. /metrics
+       --metrics-port <PORT>    Serve Prometheus metrics at /metrics on a separate port
+       --no-directory-listing   Answer requests for directories with 403 Forbidden
+       --upload-dir <DIR>       Accept multipart/form-data uploads into DIR under the
+                                root and its subdirectories
+       --max-upload-size <BYTES>
+                                Largest upload request accepted (16 MiB by default)
+       --error-page <CODE>=<PATH>
+                                File sent as the body of responses with CODE;
+                                can be repeated
+       --vhost <NAME>[,<ALIAS>...]=<ROOT>
+                                Serve ROOT for requests to NAME or its aliases, which
+                                may start with *. to match subdomains; can be repeated
+       --default-host <NAME>    Virtual host serving requests for unknown hosts,
+                                which are answered with 421 otherwise
+       --config <PATH>          Load settings from a TOML file
+       --check-config           Validate the configuration and exit
     -v --verbose                Increase the level of verbosity; can be repeated up to 4 times
        --version                Show version and exit
        --help                   Show this message and exit
+
+Settings are layered as defaults < configuration file < environment < options.
+The environment variables are HTTP_SERVER_ followed by the option name in upper
+case with dashes replaced by underscores, e.g. HTTP_SERVER_MAX_REQUESTS, except
+for HTTP_SERVER_CONFIG, HTTP_SERVER_ROOT, HTTP_SERVER_LOG_LEVEL (off, error,
+warn, info, debug or trace) and HTTP_SERVER_HEADERS. Lists are separated with
+semicolons.
+
+SIGHUP reloads the configuration. Changes to the address, port, limits, TLS,
+access log and metrics settings take effect after a restart.
+
+SIGUSR1 reopens the access log, e.g. after it has been rotated.
+
+SIGTERM and SIGINT stop the server once open connections finish, or the drain
+timeout expires. Sending either again stops it immediately.
+
+EXIT STATUS:
+    0       All connections finished before stopping
+    1       Invalid configuration or the server failed to start
+    2       Connections were still open when the drain timeout expired
+    128+N   Stopped immediately by a repeated signal N
 ";
 
-fn parse_arguments() -> Config {
+// Settings which are only read when the server starts.
+const RESTART_REQUIRED: [&str; 17] = [
+    "address",
+    "port",
+    "keep_alive_timeout",
+    "request_timeout",
+    "write_timeout",
+    "drain_timeout",
+    "max_requests",
+    "workers",
+    "max_connections",
+    "tls_cert",
+    "tls_key",
+    "tls_self_signed",
+    "tls_sni",
+    "access_log",
+    "access_log_format",
+    "metrics_path",
+    "metrics_port",
+];
+
+struct Arguments {
+    config_path: Option<String>,
+    check_config: bool,
+    partial: OptionalConfigValues,
+}
+
+fn parse_arguments() -> Arguments {
     let mut args = PicoArgs::from_env();
 
     if args.contains("--help") {
@@ -46,8 +136,19 @@ fn parse_arguments() -> Config {
         exit(0);
     }
 
-    let partial = match OptionalConfigValues::from_pico_args(&mut args) {
-        Ok(partial) => partial,
+    let parsed = args
+        .opt_value_from_str("--config")
+        .map_err(ParsingError::from)
+        .and_then(|config_path| {
+            Ok(Arguments {
+                config_path,
+                check_config: args.contains("--check-config"),
+                partial: OptionalConfigValues::from_pico_args(&mut args)?,
+            })
+        });
+
+    let arguments = match parsed {
+        Ok(arguments) => arguments,
         Err(err) => {
             eprintln!("{err}");
             exit(1);
@@ -63,7 +164,7 @@ fn parse_arguments() -> Config {
         exit(1);
     }
 
-    partial.into()
+    arguments
 }
 
 fn init_logger(config: &Config) {
@@ -73,38 +174,286 @@ fn init_logger(config: &Config) {
         .add_filter_allow_str("http_server")
         .build();
 
+    // everything passes the logger itself, so that the level can be changed
+    // on reload
     TermLogger::init(
-        config.verbosity,
+        log::LevelFilter::Trace,
         log_config,
         TerminalMode::Mixed,
         ColorChoice::Always,
     )
     .expect("unable to initialize simplelog");
+    log::set_max_level(config.verbosity);
 }
 
-fn init_handlebars_registry() -> Handlebars<'static> {
-    let mut handlebars = Handlebars::new();
+fn build_handler(config: &Config, metrics: Option<&Arc<Metrics>>) -> Stack<VirtualHosts> {
+    let metrics_endpoint = config
+        .metrics_path
+        .as_ref()
+        .zip(metrics)
+        .map(|(path, metrics)| Endpoint::new(path.as_str(), Arc::clone(metrics)));
+
+    Stack::new(VirtualHosts::new(config))
+        .with_if_some(config.access_log.is_none().then_some(RequestLog))
+        .with_if_some(config.access_log.as_ref().map(|_| RequestId::new()))
+        .with_if_some(
+            config
+                .basic_auth
+                .as_deref()
+                .map(|credentials| BasicAuth::new(credentials, "http-server")),
+        )
+        .with(SetHeaders::new(&config.headers))
+        .with(Compressor::new(config))
+        .with_if_some(metrics_endpoint)
+}
+
+// Runs at startup and after every reload, so sites added by a reload get one
+// too.
+fn create_upload_dirs(config: &Config) {
+    let Some(dir) = &config.upload_dir else {
+        return;
+    };
+
+    let roots = std::iter::once(&config.root).chain(config.hosts.iter().map(|host| &host.root));
+    for root in roots {
+        let path = format!("{}/{}", root.trim_end_matches('/'), dir.trim_matches('/'));
+        if let Err(err) = std::fs::create_dir_all(&path) {
+            warn!("Failed to create the upload directory {path}: {err}");
+        }
+    }
+}
+
+// Serves the metrics on their own listener, without TLS.
+fn serve_metrics(config: &Config, port: u16, metrics: &Arc<Metrics>) -> io::Result<()> {
+    let server = Server::builder(Endpoint::new("/metrics", Arc::clone(metrics)))
+        .bind((config.address, port))
+        .workers(1)
+        .build()?;
+
+    info!("Serving metrics on http://{}/metrics", server.local_addr()?);
+    std::thread::Builder::new()
+        .name("metrics".to_string())
+        .spawn(move || {
+            if let Err(err) = server.run() {
+                error!("The metrics listener has failed {err}");
+            }
+        })?;
+
+    Ok(())
+}
+
+// The state needed to act on signals.
+#[cfg(unix)]
+struct Running {
+    arguments: Arguments,
+    config: Config,
+    handler: Reloadable<Stack<VirtualHosts>>,
+    shutdown: ShutdownHandle,
+    access_log: Option<Arc<AccessLog>>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+// Reloads the configuration on SIGHUP and reopens the access log on SIGUSR1.
+// The first SIGTERM or SIGINT starts a graceful shutdown, the next one exits
+// right away.
+#[cfg(unix)]
+fn handle_signals(mut running: Running) {
+    use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1};
+    use signal_hook::iterator::Signals;
+    use std::thread;
+
+    let mut signals = match Signals::new([SIGHUP, SIGUSR1, SIGTERM, SIGINT]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            error!("Unable to handle signals {err}");
+            return;
+        }
+    };
+
+    thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || {
+            for signal in signals.forever() {
+                match signal {
+                    SIGHUP => {
+                        let Running {
+                            arguments,
+                            config,
+                            handler,
+                            metrics,
+                            ..
+                        } = &running;
+
+                        if let Some(new_config) =
+                            reload(arguments, config, handler, metrics.as_ref())
+                        {
+                            running.config = new_config;
+                        }
+                    }
+                    SIGUSR1 => {
+                        if let Some(access_log) = &running.access_log {
+                            match access_log.reopen() {
+                                Ok(()) => info!("Reopened the access log"),
+                                Err(err) => error!("Failed to reopen the access log {err}"),
+                            }
+                        }
+                    }
+                    _ if running.shutdown.is_requested() => {
+                        warn!("Stopping immediately");
+                        exit(128 + signal);
+                    }
+                    _ => {
+                        info!("Shutting down");
+                        running.shutdown.shutdown();
+                    }
+                }
+            }
+        })
+        .expect("unable to spawn the signal handling thread");
+}
+
+fn reload(
+    arguments: &Arguments,
+    current: &Config,
+    handler: &Reloadable<Stack<VirtualHosts>>,
+    metrics: Option<&Arc<Metrics>>,
+) -> Option<Config> {
+    info!("Reloading configuration");
+    let config = Config::load(arguments.config_path.as_deref(), arguments.partial.clone())
+        .map_err(|err| err.to_string())
+        .and_then(|config| match tls::server_config(&config) {
+            Ok(_) => Ok(config),
+            Err(err) => Err(err.to_string()),
+        });
+
+    let mut config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            error!("Keeping the current configuration, the new one is invalid: {err}");
+            return None;
+        }
+    };
 
-    handlebars
-        .register_template_string("dir", include_str!("dir.hbs"))
-        .unwrap();
+    let changes = current.diff(&config);
+    if changes.is_empty() {
+        info!("Configuration has not changed");
+        return None;
+    }
 
-    handlebars
+    for (key, change) in &changes {
+        if RESTART_REQUIRED.contains(key) {
+            warn!("Changed {key}: {change}, which takes effect after a restart");
+        } else {
+            info!("Changed {key}: {change}");
+        }
+    }
+
+    // the handler has to agree with the listener, e.g. on the port expected
+    // in the `Host` field
+    config.address = current.address;
+    config.port = current.port;
+    config.keep_alive_timeout = current.keep_alive_timeout;
+    config.request_timeout = current.request_timeout;
+    config.write_timeout = current.write_timeout;
+    config.drain_timeout = current.drain_timeout;
+    config.max_requests = current.max_requests;
+    config.workers = current.workers;
+    config.max_connections = current.max_connections;
+    config.tls_cert.clone_from(&current.tls_cert);
+    config.tls_key.clone_from(&current.tls_key);
+    config.tls_self_signed = current.tls_self_signed;
+    config.tls_sni.clone_from(&current.tls_sni);
+    config.access_log.clone_from(&current.access_log);
+    config.access_log_format = current.access_log_format;
+    config.metrics_path.clone_from(&current.metrics_path);
+    config.metrics_port = current.metrics_port;
+
+    log::set_max_level(config.verbosity);
+    create_upload_dirs(&config);
+    handler.replace(build_handler(&config, metrics));
+    Some(config)
 }
 
 fn main() -> io::Result<()> {
-    let config = parse_arguments();
+    let arguments = parse_arguments();
+    let config = match Config::load(arguments.config_path.as_deref(), arguments.partial.clone()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            exit(1);
+        }
+    };
+
+    let tls = match tls::server_config(&config) {
+        Ok(tls) => tls,
+        Err(err) => {
+            eprintln!("{err}");
+            exit(1);
+        }
+    };
+
+    if arguments.check_config {
+        println!("Configuration is valid");
+        return Ok(());
+    }
 
     init_logger(&config);
+    create_upload_dirs(&config);
 
-    let listener = TcpListener::bind((config.address, config.port))?;
-    let mut handler = StreamHandler::new(Router::new(init_handlebars_registry(), &config));
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut stream) => handler.dispatch(&mut stream),
-            Err(err) => warn!("Connection failed {err}"),
-        }
+    let access_log = match &config.access_log {
+        Some(path) => match AccessLog::open(path, config.access_log_format) {
+            Ok(access_log) => Some(Arc::new(access_log)),
+            Err(err) => {
+                error!("Failed to open the access log {path}: {err}");
+                exit(1);
+            }
+        },
+        None => None,
+    };
+
+    let metrics = config.has_metrics().then(|| Arc::new(Metrics::new()));
+    let handler = Reloadable::new(build_handler(&config, metrics.as_ref()));
+    let mut builder = Server::builder(handler.clone())
+        .bind((config.address, config.port))
+        .limits(config.limits())
+        .tls(tls);
+
+    if let Some(access_log) = &access_log {
+        builder = builder.observer(Arc::clone(access_log));
     }
 
-    Ok(())
+    if let Some(metrics) = &metrics {
+        builder = builder.observer(Arc::clone(metrics));
+    }
+
+    let server = builder.build()?;
+
+    info!(
+        "Listening on {}://{} with {} workers",
+        if server.is_tls() { "https" } else { "http" },
+        server.local_addr()?,
+        server.workers()
+    );
+
+    if let (Some(port), Some(metrics)) = (config.metrics_port, &metrics) {
+        serve_metrics(&config, port, metrics)?;
+    }
+
+    #[cfg(unix)]
+    handle_signals(Running {
+        arguments,
+        config,
+        handler,
+        shutdown: server.shutdown_handle(),
+        access_log,
+        metrics,
+    });
+
+    match server.run()? {
+        Shutdown::Drained => {
+            info!("Stopped");
+            Ok(())
+        }
+        Shutdown::TimedOut => exit(2),
+    }
 }
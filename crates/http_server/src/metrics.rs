@@ -0,0 +1,390 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use http_lib::request::ParsingError;
+use http_lib::response::Code;
+use http_lib::{HeaderName, Method, Request, Response};
+
+use crate::handler::Handler;
+use crate::middleware::{Middleware, Next};
+use crate::observer::{Exchange, Observer, Rejection};
+
+// Upper bounds of the latency histogram buckets in seconds, the same as the
+// defaults of the Prometheus client libraries.
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Default)]
+struct Counters {
+    // by method and status code
+    requests: BTreeMap<(&'static str, u16), u64>,
+    // by variant of `request::ParsingError`
+    parse_errors: BTreeMap<&'static str, u64>,
+    // by status code
+    rejections: BTreeMap<u16, u64>,
+}
+
+/// Collects statistics about the requests served, to be scraped by
+/// Prometheus. Register it with `Builder::observer` and serve it with an
+/// `Endpoint`.
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<Counters>,
+    // the last one counts requests slower than all the bounds
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    duration_sum_nanos: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    connections_active: AtomicUsize,
+    connections_total: AtomicU64,
+}
+
+impl Metrics {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Formats the metrics in the Prometheus text exposition format.
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut out = String::with_capacity(4096);
+        let counters = self.counters.lock().unwrap_or_else(PoisonError::into_inner);
+
+        header(
+            &mut out,
+            "http_requests_total",
+            "counter",
+            "Requests answered by the handler, by method and status code.",
+        );
+        for ((method, code), count) in &counters.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{method}\",code=\"{code}\"}} {count}"
+            );
+        }
+
+        header(
+            &mut out,
+            "http_parse_errors_total",
+            "counter",
+            "Requests which could not be parsed, by error.",
+        );
+        for (error, count) in &counters.parse_errors {
+            let _ = writeln!(out, "http_parse_errors_total{{error=\"{error}\"}} {count}");
+        }
+
+        header(
+            &mut out,
+            "http_rejected_requests_total",
+            "counter",
+            "Requests answered without reaching the handler, by status code.",
+        );
+        for (code, count) in &counters.rejections {
+            let _ = writeln!(
+                out,
+                "http_rejected_requests_total{{code=\"{code}\"}} {count}"
+            );
+        }
+
+        drop(counters);
+
+        header(
+            &mut out,
+            "http_request_duration_seconds",
+            "histogram",
+            "Time taken to handle requests and send the responses.",
+        );
+        let mut cumulative = 0;
+        for (bound, bucket) in DURATION_BUCKETS.iter().zip(&self.duration_buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{le=\"{bound}\"}} {cumulative}"
+            );
+        }
+
+        cumulative += self.duration_buckets[DURATION_BUCKETS.len()].load(Ordering::Relaxed);
+        #[allow(clippy::cast_precision_loss)]
+        let sum = self.duration_sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let _ = writeln!(
+            out,
+            "http_request_duration_seconds_bucket{{le=\"+Inf\"}} {cumulative}"
+        );
+        let _ = writeln!(out, "http_request_duration_seconds_sum {sum}");
+        let _ = writeln!(out, "http_request_duration_seconds_count {cumulative}");
+
+        let scalars = [
+            (
+                "http_received_bytes_total",
+                "counter",
+                "Bytes of requests read, including the heads.",
+                self.bytes_received.load(Ordering::Relaxed),
+            ),
+            (
+                "http_sent_bytes_total",
+                "counter",
+                "Bytes of responses written, including the heads.",
+                self.bytes_sent.load(Ordering::Relaxed),
+            ),
+            (
+                "http_connections_active",
+                "gauge",
+                "Connections being served.",
+                self.connections_active.load(Ordering::Relaxed) as u64,
+            ),
+            (
+                "http_connections_total",
+                "counter",
+                "Connections served.",
+                self.connections_total.load(Ordering::Relaxed),
+            ),
+        ];
+
+        for (name, kind, help, value) in scalars {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{name} {value}");
+        }
+
+        out
+    }
+
+    fn respond(&self, req: &Request) -> Response {
+        if !matches!(req.method, Method::Get | Method::Head) {
+            return Response::builder(Code::MethodNotAllowed)
+                .add_header_value(HeaderName::ALLOW, "GET, HEAD".into())
+                .finish();
+        }
+
+        Response::builder(Code::Ok)
+            .body_of_type(self.render(), CONTENT_TYPE.into())
+            .add_header_value(HeaderName::CACHE_CONTROL, "no-store".into())
+            .finish()
+    }
+}
+
+impl Observer for Metrics {
+    fn response_sent(&self, exchange: &Exchange<'_>) {
+        let method = exchange.req.method.as_str();
+        let code = exchange.res.code as u16;
+        *self
+            .counters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .requests
+            .entry((method, code))
+            .or_default() += 1;
+
+        let secs = exchange.duration.as_secs_f64();
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|&bound| secs <= bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+
+        let nanos = u64::try_from(exchange.duration.as_nanos()).unwrap_or(u64::MAX);
+        self.duration_sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(exchange.bytes_received, Ordering::Relaxed);
+        self.bytes_sent
+            .fetch_add(exchange.bytes_sent, Ordering::Relaxed);
+    }
+
+    fn connection_opened(&self, _peer_addr: Option<SocketAddr>) {
+        self.connections_active.fetch_add(1, Ordering::Relaxed);
+        self.connections_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn connection_closed(&self, _peer_addr: Option<SocketAddr>) {
+        self.connections_active.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn request_rejected(&self, rejection: &Rejection) {
+        let mut counters = self.counters.lock().unwrap_or_else(PoisonError::into_inner);
+        *counters
+            .rejections
+            .entry(rejection.code as u16)
+            .or_default() += 1;
+
+        if let Some(err) = rejection.error {
+            *counters.parse_errors.entry(error_label(err)).or_default() += 1;
+        }
+    }
+}
+
+/// Serves the metrics at a path, either in front of another handler as
+/// middleware or on its own, e.g. on a separate port.
+pub struct Endpoint {
+    path: String,
+    metrics: Arc<Metrics>,
+}
+
+impl Endpoint {
+    pub fn new(path: impl Into<String>, metrics: Arc<Metrics>) -> Self {
+        Self {
+            path: path.into(),
+            metrics,
+        }
+    }
+
+    fn matches(&self, req: &Request) -> bool {
+        req.uri.path() == self.path
+    }
+}
+
+impl Middleware for Endpoint {
+    fn handle(&self, req: &Request, next: Next<'_>) -> Response {
+        if self.matches(req) {
+            self.metrics.respond(req)
+        } else {
+            next.run(req)
+        }
+    }
+}
+
+impl Handler for Endpoint {
+    fn handle(&self, req: &Request) -> Response {
+        if self.matches(req) {
+            self.metrics.respond(req)
+        } else {
+            Response::builder(Code::NotFound)
+                .body("Not found".to_string())
+                .finish()
+        }
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+// Only the outer variant is used, so that the number of series stays small.
+fn error_label(err: ParsingError) -> &'static str {
+    use ParsingError::*;
+    match err {
+        VersionMalformed => "VersionMalformed",
+        MethodUnsupported => "MethodUnsupported",
+        MalformedStartLine => "MalformedStartLine",
+        InvalidResource => "InvalidResource",
+        Header(_) => "Header",
+        BodyLongerThanStream => "BodyLongerThanStream",
+        Trailer(_) => "Trailer",
+        HeadTooLong => "HeadTooLong",
+        BodyTooLong => "BodyTooLong",
+        InvalidContentLength => "InvalidContentLength",
+        TransferCodingWithContentLength => "TransferCodingWithContentLength",
+        UnsupportedTransferCoding => "UnsupportedTransferCoding",
+        Chunk(_) => "Chunk",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    fn respond(metrics: &Metrics, method: Method, code: Code, millis: u64) {
+        let req = Request::new("localhost".into(), method, "/".parse().unwrap());
+        let res = Response::new(code);
+        metrics.response_sent(&Exchange {
+            req: &req,
+            res: &res,
+            peer_addr: None,
+            received: SystemTime::now(),
+            duration: Duration::from_millis(millis),
+            bytes_received: 100,
+            bytes_sent: 200,
+            body_bytes_sent: 150,
+        });
+    }
+
+    fn reject(metrics: &Metrics, code: Code, error: Option<ParsingError>) {
+        metrics.request_rejected(&Rejection {
+            code,
+            error,
+            peer_addr: None,
+        });
+    }
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::new();
+        respond(&metrics, Method::Get, Code::Ok, 3);
+        respond(&metrics, Method::Get, Code::Ok, 300);
+        respond(&metrics, Method::Post, Code::NotFound, 20_000);
+        reject(
+            &metrics,
+            Code::RequestHeaderFieldsTooLarge,
+            Some(ParsingError::HeadTooLong),
+        );
+        reject(&metrics, Code::ServiceUnavailable, None);
+        metrics.connection_opened(None);
+        metrics.connection_opened(None);
+        metrics.connection_closed(None);
+
+        let rendered = metrics.render();
+        let lines: Vec<_> = rendered.lines().collect();
+        for expected in [
+            "# HELP http_requests_total Requests answered by the handler, by method and status code.",
+            "# TYPE http_requests_total counter",
+            r#"http_requests_total{method="GET",code="200"} 2"#,
+            r#"http_requests_total{method="POST",code="404"} 1"#,
+            r#"http_parse_errors_total{error="HeadTooLong"} 1"#,
+            r#"http_rejected_requests_total{code="431"} 1"#,
+            r#"http_rejected_requests_total{code="503"} 1"#,
+            "# TYPE http_request_duration_seconds histogram",
+            r#"http_request_duration_seconds_bucket{le="0.005"} 1"#,
+            r#"http_request_duration_seconds_bucket{le="0.25"} 1"#,
+            r#"http_request_duration_seconds_bucket{le="0.5"} 2"#,
+            r#"http_request_duration_seconds_bucket{le="10"} 2"#,
+            r#"http_request_duration_seconds_bucket{le="+Inf"} 3"#,
+            "http_request_duration_seconds_sum 20.303",
+            "http_request_duration_seconds_count 3",
+            "http_received_bytes_total 300",
+            "http_sent_bytes_total 600",
+            "# TYPE http_connections_active gauge",
+            "http_connections_active 1",
+            "http_connections_total 2",
+        ] {
+            assert!(lines.contains(&expected), "missing {expected} in\n{rendered}");
+        }
+
+        // every sample belongs to a described metric
+        let families: Vec<_> = lines
+            .iter()
+            .filter_map(|line| line.strip_prefix("# TYPE "))
+            .filter_map(|line| line.split(' ').next())
+            .collect();
+        for line in lines.iter().filter(|line| !line.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            assert!(families.iter().any(|family| name.starts_with(family)));
+        }
+    }
+
+    #[test]
+    fn endpoint() {
+        let metrics = Arc::new(Metrics::new());
+        let endpoint = Endpoint::new("/metrics", Arc::clone(&metrics));
+        let get = |method, path: &str| {
+            let req = Request::new("localhost".into(), method, path.parse().unwrap());
+            Handler::handle(&endpoint, &req)
+        };
+
+        let res = get(Method::Get, "/metrics");
+        assert_eq!(res.code, Code::Ok);
+        assert_eq!(
+            res.headers.get_single(&HeaderName::CONTENT_TYPE).unwrap(),
+            CONTENT_TYPE.as_bytes()
+        );
+        assert_eq!(get(Method::Post, "/metrics").code, Code::MethodNotAllowed);
+        assert_eq!(get(Method::Get, "/other").code, Code::NotFound);
+    }
+}
@@ -0,0 +1,177 @@
+use http_lib::response::Code;
+use http_lib::{Request, Response};
+
+use crate::handler::Handler;
+
+pub mod auth;
+pub mod compression;
+pub mod headers;
+pub mod host;
+pub mod request_id;
+pub mod request_log;
+
+pub use auth::BasicAuth;
+pub use compression::Compressor;
+pub use headers::SetHeaders;
+pub use host::HostValidation;
+pub use request_id::RequestId;
+pub use request_log::RequestLog;
+
+/// Wraps request handling, e.g. to reject requests before they reach the
+/// handler or to modify responses on their way out.
+pub trait Middleware: Send + Sync + 'static {
+    fn handle(&self, req: &Request, next: Next<'_>) -> Response;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(&Request, Next<'_>) -> Response + Send + Sync + 'static,
+{
+    fn handle(&self, req: &Request, next: Next<'_>) -> Response {
+        self(req, next)
+    }
+}
+
+/// The rest of the chain, which ends with the handler.
+pub struct Next<'a> {
+    middleware: &'a [Box<dyn Middleware>],
+    handler: &'a dyn Handler,
+}
+
+impl Next<'_> {
+    pub fn run(self, req: &Request) -> Response {
+        match self.middleware.split_first() {
+            Some((first, rest)) => first.handle(
+                req,
+                Next {
+                    middleware: rest,
+                    handler: self.handler,
+                },
+            ),
+            None => self.handler.handle(req),
+        }
+    }
+
+    /// Returns the handler's response for a request rejected by the
+    /// middleware, see [`Handler::error_response`].
+    #[must_use]
+    pub fn error_response(&self, code: Code) -> Response {
+        self.handler.error_response(code)
+    }
+}
+
+/// A handler wrapped in middleware. Middleware added first sees the request
+/// first and the response last.
+pub struct Stack<H> {
+    middleware: Vec<Box<dyn Middleware>>,
+    handler: H,
+}
+
+impl<H: Handler> Stack<H> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            middleware: Vec::new(),
+            handler,
+        }
+    }
+
+    #[must_use]
+    pub fn with(mut self, middleware: impl Middleware) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    #[must_use]
+    pub fn with_if_some(self, middleware: Option<impl Middleware>) -> Self {
+        match middleware {
+            Some(middleware) => self.with(middleware),
+            None => self,
+        }
+    }
+}
+
+impl<H: Handler> Handler for Stack<H> {
+    fn handle(&self, req: &Request) -> Response {
+        let next = Next {
+            middleware: &self.middleware,
+            handler: &self.handler,
+        };
+
+        next.run(req)
+    }
+
+    fn error_response(&self, code: Code) -> Response {
+        self.handler.error_response(code)
+    }
+
+    fn max_body_len(&self, head: &Request) -> Option<usize> {
+        self.handler.max_body_len(head)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use http_lib::Method;
+
+    use super::*;
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    // Records the request and the response passing through it.
+    fn tracing(name: &'static str, log: &Log) -> impl Middleware {
+        let log = Arc::clone(log);
+        move |req: &Request, next: Next<'_>| {
+            log.lock().unwrap().push(format!("{name} request"));
+            let res = next.run(req);
+            log.lock().unwrap().push(format!("{name} response"));
+            res
+        }
+    }
+
+    fn request() -> Request {
+        Request::new("localhost".into(), Method::Get, "/".parse().unwrap())
+    }
+
+    fn handler(log: &Log) -> impl Handler {
+        let log = Arc::clone(log);
+        move |_: &Request| {
+            log.lock().unwrap().push("handler".to_string());
+            Response::new(Code::Ok)
+        }
+    }
+
+    #[test]
+    fn order() {
+        let log = Log::default();
+        let stack = Stack::new(handler(&log))
+            .with(tracing("outer", &log))
+            .with_if_some(None::<fn(&Request, Next<'_>) -> Response>)
+            .with(tracing("inner", &log));
+
+        assert_eq!(stack.handle(&request()).code, Code::Ok);
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "outer request",
+                "inner request",
+                "handler",
+                "inner response",
+                "outer response",
+            ]
+        );
+    }
+
+    #[test]
+    fn short_circuit() {
+        let log = Log::default();
+        let stack = Stack::new(handler(&log))
+            .with(tracing("outer", &log))
+            .with(|_: &Request, _: Next<'_>| Response::new(Code::Unauthorized))
+            .with(tracing("inner", &log));
+
+        assert_eq!(stack.handle(&request()).code, Code::Unauthorized);
+        assert_eq!(*log.lock().unwrap(), ["outer request", "outer response"]);
+    }
+}
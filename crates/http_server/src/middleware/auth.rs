@@ -0,0 +1,62 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use http_lib::response::Code;
+use http_lib::{HeaderName, Request, Response};
+
+use super::{Middleware, Next};
+
+/// Requires the credentials to be sent with the `Basic` authentication
+/// scheme, see <https://www.rfc-editor.org/rfc/rfc7617>
+pub struct BasicAuth {
+    credentials: Vec<u8>,
+    challenge: String,
+}
+
+impl BasicAuth {
+    /// `credentials` are expected in the `user:password` form.
+    #[must_use]
+    pub fn new(credentials: &str, realm: &str) -> Self {
+        let realm = realm.replace(['\\', '"'], "");
+        Self {
+            credentials: credentials.as_bytes().to_vec(),
+            challenge: format!("Basic realm=\"{realm}\", charset=\"UTF-8\""),
+        }
+    }
+
+    fn is_authorized(&self, req: &Request) -> bool {
+        let Some(authorization) = req.headers.get_single(&HeaderName::AUTHORIZATION) else {
+            return false;
+        };
+
+        let Some(space) = authorization.iter().position(|&b| b == b' ') else {
+            return false;
+        };
+
+        let (scheme, encoded) = authorization.split_at(space);
+        if !scheme.eq_ignore_ascii_case(b"Basic") {
+            return false;
+        }
+
+        STANDARD
+            .decode(encoded.trim_ascii())
+            .is_ok_and(|decoded| constant_time_eq(&decoded, &self.credentials))
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn handle(&self, req: &Request, next: Next<'_>) -> Response {
+        if self.is_authorized(req) {
+            return next.run(req);
+        }
+
+        let mut res = next.error_response(Code::Unauthorized);
+        res.add_header_value(HeaderName::WWW_AUTHENTICATE, self.challenge.clone().into());
+        res
+    }
+}
+
+// Compares the credentials without leaking the position of the first
+// difference through timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
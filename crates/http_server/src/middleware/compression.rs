@@ -0,0 +1,492 @@
+use std::io::{self, Read, Seek as _, SeekFrom};
+use std::mem;
+
+use brotli::CompressorReader;
+use flate2::read::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use log::{debug, warn};
+
+use super::{Middleware, Next};
+use crate::config::Config;
+use http_lib::coding::{self, ContentCoding};
+use http_lib::etag::EntityTag;
+use http_lib::response::{Body, Code};
+use http_lib::{HeaderName, Request, Response};
+
+// Codings applied on the fly, in order of preference. Others, such as zstd, are
+// only served precompressed.
+#[derive(Clone, Copy)]
+enum Encoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    const SUPPORTED: [Self; 3] = [Self::Brotli, Self::Gzip, Self::Deflate];
+
+    fn coding(self) -> ContentCoding {
+        match self {
+            Self::Brotli => ContentCoding::Brotli,
+            Self::Gzip => ContentCoding::Gzip,
+            Self::Deflate => ContentCoding::Deflate,
+        }
+    }
+
+    fn from_coding(coding: ContentCoding) -> Option<Self> {
+        Self::SUPPORTED.into_iter().find(|e| e.coding() == coding)
+    }
+}
+
+// The levels above only apply to brotli.
+const MAX_FLATE_LEVEL: u32 = 9;
+
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const BROTLI_WINDOW_BITS: u32 = 22;
+
+const COMPRESSIBLE_TYPES: [&str; 8] = [
+    "application/javascript",
+    "application/json",
+    "application/manifest+json",
+    "application/wasm",
+    "application/xhtml+xml",
+    "application/xml",
+    "image/svg+xml",
+    "image/x-icon",
+];
+
+/// Compresses response bodies with the coding preferred by the client.
+pub struct Compressor {
+    level: u32,
+    min_size: u64,
+    excluded_types: Vec<String>,
+}
+
+impl Compressor {
+    #[must_use]
+    pub fn new(config: &Config) -> Self {
+        Self {
+            level: config.compression_level,
+            min_size: config.compression_min_size,
+            excluded_types: config
+                .compression_exclude
+                .iter()
+                .map(|t| t.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    fn compress(&self, req: &Request, res: &mut Response) {
+        if self.level == 0 {
+            return;
+        }
+
+        // caches update the stored response, which varies on `Accept-Encoding`
+        if res.code == Code::NotModified {
+            add_vary(res);
+            return;
+        }
+
+        if res.code != Code::Ok
+            || res.headers.contains_name(&HeaderName::CONTENT_ENCODING)
+            || !res
+                .headers
+                .get_single(&HeaderName::CONTENT_TYPE)
+                .is_some_and(|t| self.is_compressible(t))
+        {
+            return;
+        }
+
+        // the response depends on `Accept-Encoding` even if it ends up being
+        // sent unencoded
+        add_vary(res);
+
+        // `HEAD` is answered with the same fields as `GET`. Files are only read
+        // while sending, so their encoders do no work, and in-memory bodies are
+        // encoded to know their length.
+        if matches!(res.body, Body::Parts(_))
+            || res.body.len().is_some_and(|len| len < self.min_size)
+        {
+            return;
+        }
+
+        let supported = Encoding::SUPPORTED.map(Encoding::coding);
+        let coding = coding::negotiate(req.headers.get(&HeaderName::ACCEPT_ENCODING), &supported);
+        let Some(encoding) = Encoding::from_coding(coding) else {
+            return;
+        };
+
+        let body = mem::take(&mut res.body);
+        match self.encode(body, encoding) {
+            Ok(body) => res.body = body,
+            Err((body, err)) => {
+                warn!("Failed to compress the response: {err}");
+                res.body = body;
+                return;
+            }
+        }
+
+        debug!("Compressed the response with {coding}");
+        res.add_header_value(HeaderName::CONTENT_ENCODING, coding.as_str().into());
+        // the encoding is produced while sending, so ranges of it cannot be served
+        res.headers.remove(&HeaderName::ACCEPT_RANGES);
+        if let Some(len) = res.body.len() {
+            res.headers.remove(&HeaderName::CONTENT_LENGTH);
+            res.add_header_value(HeaderName::CONTENT_LENGTH, len.to_string().into());
+        } else {
+            res.set_chunked();
+        }
+
+        weaken_etag(res);
+    }
+
+    fn is_compressible(&self, content_type: &[u8]) -> bool {
+        let Ok(content_type) = std::str::from_utf8(content_type) else {
+            return false;
+        };
+
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        let is_excluded =
+            self.excluded_types
+                .iter()
+                .any(|excluded| match excluded.strip_suffix("/*") {
+                    Some(prefix) => essence.split('/').next() == Some(prefix),
+                    None => *excluded == essence,
+                });
+
+        !is_excluded
+            && (essence.starts_with("text/")
+                || essence.ends_with("+json")
+                || essence.ends_with("+xml")
+                || COMPRESSIBLE_TYPES.contains(&essence.as_str()))
+    }
+
+    // Returns the body back if it could not be encoded.
+    fn encode(&self, body: Body, encoding: Encoding) -> Result<Body, (Body, io::Error)> {
+        match body {
+            Body::Bytes(bytes) => {
+                let mut encoded = Vec::with_capacity(bytes.len() / 2);
+                match self
+                    .encoder(io::Cursor::new(bytes.clone()), encoding)
+                    .read_to_end(&mut encoded)
+                {
+                    Ok(_) => Ok(encoded.into()),
+                    Err(err) => Err((Body::Bytes(bytes), err)),
+                }
+            }
+            Body::File {
+                mut file,
+                offset,
+                len,
+            } => match file.seek(SeekFrom::Start(offset)) {
+                Ok(_) => Ok(Body::Reader(self.encoder(file.take(len), encoding))),
+                Err(err) => Err((Body::File { file, offset, len }, err)),
+            },
+            Body::Reader(reader) => Ok(Body::Reader(self.encoder(reader, encoding))),
+            Body::Parts(_) => Ok(body),
+        }
+    }
+
+    fn encoder<R: Read + Send + 'static>(
+        &self,
+        reader: R,
+        encoding: Encoding,
+    ) -> Box<dyn Read + Send> {
+        let flate_level = Compression::new(self.level.min(MAX_FLATE_LEVEL));
+        match encoding {
+            Encoding::Gzip => Box::new(GzEncoder::new(reader, flate_level)),
+            // the `deflate` coding is actually the zlib format
+            Encoding::Deflate => Box::new(ZlibEncoder::new(reader, flate_level)),
+            Encoding::Brotli => Box::new(CompressorReader::new(
+                reader,
+                BROTLI_BUFFER_SIZE,
+                self.level,
+                BROTLI_WINDOW_BITS,
+            )),
+        }
+    }
+}
+
+impl Middleware for Compressor {
+    fn handle(&self, req: &Request, next: Next<'_>) -> Response {
+        let mut res = next.run(req);
+        self.compress(req, &mut res);
+        res
+    }
+}
+
+fn add_vary(res: &mut Response) {
+    if !res
+        .headers
+        .contains_value(&HeaderName::VARY, b"Accept-Encoding")
+    {
+        res.add_header_value(HeaderName::VARY, "Accept-Encoding".into());
+    }
+}
+
+// A compressed representation is not byte-for-byte identical to the
+// uncompressed one, so its entity tag can no longer be strong.
+fn weaken_etag(res: &mut Response) {
+    let Some(etag) = res.headers.remove(&HeaderName::ETAG) else {
+        return;
+    };
+
+    let etag = EntityTag::from_bytes(&etag.to_buffer())
+        .and_then(|etag| EntityTag::weak(etag.tag().to_vec()));
+
+    if let Some(etag) = etag {
+        res.add_header_value(HeaderName::ETAG, etag.to_buffer().into());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use brotli::Decompressor;
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use http_lib::Method;
+
+    use super::*;
+
+    fn text(len: usize) -> String {
+        "All work and no play makes Jack a dull boy. "
+            .chars()
+            .cycle()
+            .take(len)
+            .collect()
+    }
+
+    fn page(content_type: &str, body: &str) -> Response {
+        Response::builder(Code::Ok)
+            .add_header_value(HeaderName::ETAG, "\"v1\"".into())
+            .add_header_value(HeaderName::ACCEPT_RANGES, "bytes".into())
+            .body_of_type(body.to_string(), content_type.to_string().into())
+            .finish()
+    }
+
+    // Members of `accept_encoding` are separate values, as split by the parser.
+    fn request(method: Method, accept_encoding: Option<&str>) -> Request {
+        let mut req = Request::new("localhost".into(), method, "/".parse().unwrap());
+        for member in accept_encoding.iter().flat_map(|value| value.split(", ")) {
+            req.add_header_value(HeaderName::ACCEPT_ENCODING, member.to_string().into());
+        }
+        req
+    }
+
+    fn compress(config: &Config, req: &Request, mut res: Response) -> Response {
+        Compressor::new(config).compress(req, &mut res);
+        res
+    }
+
+    fn header<'a>(res: &'a Response, name: &HeaderName) -> Option<&'a [u8]> {
+        res.headers.get_single(name)
+    }
+
+    fn body(res: &mut Response) -> Vec<u8> {
+        let mut body = Vec::new();
+        res.body
+            .write_to(&mut body, &mut Vec::new(), false)
+            .unwrap();
+        body
+    }
+
+    fn decode(coding: &[u8], encoded: &[u8]) -> String {
+        let mut decoded = String::new();
+        match coding {
+            b"gzip" => GzDecoder::new(encoded).read_to_string(&mut decoded),
+            b"deflate" => ZlibDecoder::new(encoded).read_to_string(&mut decoded),
+            b"br" => Decompressor::new(encoded, BROTLI_BUFFER_SIZE).read_to_string(&mut decoded),
+            _ => panic!("unexpected coding {coding:?}"),
+        }
+        .unwrap();
+        decoded
+    }
+
+    #[test]
+    fn encodings() {
+        let text = text(4000);
+        for (accept_encoding, coding) in [
+            ("gzip, deflate, br", "br"),
+            ("gzip", "gzip"),
+            ("deflate", "deflate"),
+            ("br;q=0.5, gzip;q=0.8", "gzip"),
+        ] {
+            for level in [1, 6, MAX_FLATE_LEVEL, 11] {
+                let config = Config {
+                    compression_level: level,
+                    ..Config::default()
+                };
+                let req = request(Method::Get, Some(accept_encoding));
+                let mut res = compress(&config, &req, page("text/plain", &text));
+                assert_eq!(
+                    header(&res, &HeaderName::CONTENT_ENCODING),
+                    Some(coding.as_bytes())
+                );
+                assert_eq!(
+                    header(&res, &HeaderName::VARY),
+                    Some(&b"Accept-Encoding"[..])
+                );
+                assert_eq!(header(&res, &HeaderName::ETAG), Some(&b"W/\"v1\""[..]));
+                assert_eq!(header(&res, &HeaderName::ACCEPT_RANGES), None);
+
+                let encoded = body(&mut res);
+                let len = encoded.len().to_string();
+                assert_eq!(
+                    header(&res, &HeaderName::CONTENT_LENGTH),
+                    Some(len.as_bytes())
+                );
+                assert!(encoded.len() < text.len());
+                assert_eq!(decode(coding.as_bytes(), &encoded), text);
+            }
+        }
+    }
+
+    #[test]
+    fn not_encoded() {
+        let config = Config::default();
+        let text = text(4000);
+        for accept_encoding in [None, Some("identity"), Some("zstd"), Some("gzip;q=0")] {
+            let req = request(Method::Get, accept_encoding);
+            let mut res = compress(&config, &req, page("text/plain", &text));
+            assert_eq!(header(&res, &HeaderName::CONTENT_ENCODING), None);
+            assert_eq!(
+                header(&res, &HeaderName::VARY),
+                Some(&b"Accept-Encoding"[..])
+            );
+            assert_eq!(header(&res, &HeaderName::ETAG), Some(&b"\"v1\""[..]));
+            assert_eq!(
+                header(&res, &HeaderName::ACCEPT_RANGES),
+                Some(&b"bytes"[..])
+            );
+            assert_eq!(body(&mut res), text.as_bytes());
+        }
+
+        // disabled
+        let config = Config {
+            compression_level: 0,
+            ..Config::default()
+        };
+        let req = request(Method::Get, Some("gzip"));
+        let res = compress(&config, &req, page("text/plain", &text));
+        assert_eq!(header(&res, &HeaderName::CONTENT_ENCODING), None);
+        assert_eq!(header(&res, &HeaderName::VARY), None);
+    }
+
+    #[test]
+    fn min_size() {
+        let config = Config {
+            compression_min_size: 100,
+            ..Config::default()
+        };
+        let req = request(Method::Get, Some("gzip"));
+
+        let res = compress(&config, &req, page("text/plain", &text(99)));
+        assert_eq!(header(&res, &HeaderName::CONTENT_ENCODING), None);
+        assert_eq!(
+            header(&res, &HeaderName::VARY),
+            Some(&b"Accept-Encoding"[..])
+        );
+
+        let res = compress(&config, &req, page("text/plain", &text(100)));
+        assert_eq!(
+            header(&res, &HeaderName::CONTENT_ENCODING),
+            Some(&b"gzip"[..])
+        );
+    }
+
+    #[test]
+    fn content_types() {
+        let config = Config {
+            compression_exclude: vec!["image/*".to_string(), "Application/JSON".to_string()],
+            ..Config::default()
+        };
+        let req = request(Method::Get, Some("gzip"));
+        let text = text(4000);
+
+        for (content_type, compressed) in [
+            ("text/html; charset=utf-8", true),
+            ("TEXT/CSS", true),
+            ("application/javascript", true),
+            ("application/ld+json", true),
+            ("application/atom+xml", true),
+            ("application/json", false),
+            ("image/svg+xml", false),
+            ("image/png", false),
+            ("application/zip", false),
+            ("application/octet-stream", false),
+        ] {
+            let res = compress(&config, &req, page(content_type, &text));
+            assert_eq!(
+                header(&res, &HeaderName::CONTENT_ENCODING).is_some(),
+                compressed,
+                "{content_type}"
+            );
+            assert_eq!(
+                header(&res, &HeaderName::VARY).is_some(),
+                compressed,
+                "{content_type}"
+            );
+        }
+
+        // already encoded
+        let mut res = page("text/plain", &text);
+        res.add_header_value(HeaderName::CONTENT_ENCODING, "br".into());
+        let mut res = compress(&config, &req, res);
+        assert_eq!(
+            res.headers
+                .get(&HeaderName::CONTENT_ENCODING)
+                .unwrap()
+                .count(),
+            1
+        );
+        assert_eq!(body(&mut res), text.as_bytes());
+    }
+
+    #[test]
+    fn status_codes() {
+        let config = Config::default();
+        let req = request(Method::Get, Some("gzip"));
+
+        let mut res = page("text/plain", &text(4000));
+        res.code = Code::NotFound;
+        let res = compress(&config, &req, res);
+        assert_eq!(header(&res, &HeaderName::CONTENT_ENCODING), None);
+
+        let res = compress(&config, &req, Response::new(Code::NotModified));
+        assert_eq!(header(&res, &HeaderName::CONTENT_ENCODING), None);
+        assert_eq!(
+            header(&res, &HeaderName::VARY),
+            Some(&b"Accept-Encoding"[..])
+        );
+    }
+
+    #[test]
+    fn head() {
+        let config = Config::default();
+        let text = text(4000);
+        let get = compress(
+            &config,
+            &request(Method::Get, Some("gzip")),
+            page("text/plain", &text),
+        );
+        let head = compress(
+            &config,
+            &request(Method::Head, Some("gzip")),
+            page("text/plain", &text),
+        );
+
+        for name in [
+            HeaderName::CONTENT_ENCODING,
+            HeaderName::CONTENT_LENGTH,
+            HeaderName::VARY,
+            HeaderName::ETAG,
+        ] {
+            assert_eq!(header(&head, &name), header(&get, &name), "{name:?}");
+        }
+    }
+}
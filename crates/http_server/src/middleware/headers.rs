@@ -0,0 +1,99 @@
+use bytes::Bytes;
+use http_lib::{HeaderName, Request, Response};
+use log::warn;
+
+use super::{Middleware, Next};
+use crate::config::ResponseHeader;
+
+/// Adds fixed fields to every response, unless the handler already set them.
+pub struct SetHeaders {
+    headers: Vec<(HeaderName, Bytes)>,
+}
+
+impl SetHeaders {
+    #[must_use]
+    pub fn new(headers: &[ResponseHeader]) -> Self {
+        // the configuration is checked when it is loaded, but the fields may
+        // also have been built by hand
+        let headers = headers
+            .iter()
+            .filter_map(|header| match header.to_field() {
+                Ok(field) => Some(field),
+                Err(err) => {
+                    warn!("Ignoring the response field {}: {err}", header.name);
+                    None
+                }
+            })
+            .collect();
+
+        Self { headers }
+    }
+}
+
+impl Middleware for SetHeaders {
+    fn handle(&self, req: &Request, next: Next<'_>) -> Response {
+        let mut res = next.run(req);
+        // checked up front, since a name may be configured more than once
+        let is_set: Vec<_> = self
+            .headers
+            .iter()
+            .map(|(name, _)| res.headers.contains_name(name))
+            .collect();
+
+        for ((name, value), is_set) in self.headers.iter().zip(is_set) {
+            if !is_set {
+                res.add_header_value(name.clone(), value.clone());
+            }
+        }
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http_lib::response::Code;
+    use http_lib::Method;
+
+    use super::*;
+    use crate::middleware::Stack;
+    use crate::Handler;
+
+    fn header(name: &str, value: &str) -> ResponseHeader {
+        ResponseHeader {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn set_headers() {
+        let handler = |_: &Request| {
+            Response::builder(Code::Ok)
+                .add_header_value(HeaderName::CACHE_CONTROL, "no-store".into())
+                .finish()
+        };
+        let stack = Stack::new(handler).with(SetHeaders::new(&[
+            header("Link", "</a.css>"),
+            header("Link", "</b.js>"),
+            header("Cache-Control", "max-age=60"),
+            header("X-Injected", "a\r\nSet-Cookie: id=1"),
+            header("Bad Name", "a"),
+        ]));
+
+        let req = Request::new("localhost".into(), Method::Get, "/".parse().unwrap());
+        let res = stack.handle(&req);
+        let values = |name: &[u8]| -> Vec<_> {
+            res.headers
+                .get(name)
+                .map(|values| values.iter_slices().collect())
+                .unwrap_or_default()
+        };
+        assert_eq!(values(b"Link"), [&b"</a.css>"[..], b"</b.js>"]);
+        // set by the handler
+        assert_eq!(values(b"Cache-Control"), [b"no-store"]);
+        assert!(values(b"X-Injected").is_empty());
+        assert!(values(b"Set-Cookie").is_empty());
+        assert!(values(b"Bad Name").is_empty());
+    }
+}
@@ -0,0 +1,79 @@
+use http_lib::response::Code;
+use http_lib::{HeaderName, Request, Response};
+
+use super::{Middleware, Next};
+use crate::config::Config;
+
+pub const DEFAULT_PORT: u16 = 80;
+pub const DEFAULT_TLS_PORT: u16 = 443;
+
+/// Answers requests for hosts other than the server's address or name with
+/// `421 Misdirected Request`.
+pub struct HostValidation {
+    ip: Vec<u8>,
+    ip_without_port: usize,
+    name: Vec<u8>,
+    name_without_port: usize,
+}
+
+impl HostValidation {
+    #[must_use]
+    pub fn new(config: &Config) -> Self {
+        let Config {
+            address,
+            port,
+            host,
+            ..
+        } = config;
+
+        let host_ip = format!("{address}:{port}");
+        let host_ns = if host.is_empty() {
+            String::new()
+        } else {
+            format!("{host}:{port}")
+        };
+
+        let mut ip_without_port = 0;
+        let mut name_without_port = 0;
+        let default_port = if config.is_tls() {
+            DEFAULT_TLS_PORT
+        } else {
+            DEFAULT_PORT
+        };
+
+        if *port == default_port {
+            // the length of `:<port>`
+            let suffix_len = port.to_string().len() + 1;
+            ip_without_port = host_ip.len().saturating_sub(suffix_len);
+            name_without_port = host_ns.len().saturating_sub(suffix_len);
+        }
+
+        Self {
+            ip: host_ip.into(),
+            ip_without_port,
+            name: host_ns.into(),
+            name_without_port,
+        }
+    }
+
+    pub(crate) fn validate_host(&self, host: &[u8]) -> bool {
+        (self.name_without_port != 0 && host == &self.name[..self.name_without_port])
+            || (self.ip_without_port != 0 && host == &self.ip[..self.ip_without_port])
+            || (!self.name.is_empty() && host == self.name)
+            || host == self.ip
+    }
+}
+
+impl Middleware for HostValidation {
+    fn handle(&self, req: &Request, next: Next<'_>) -> Response {
+        if req
+            .headers
+            .get_single(&HeaderName::HOST)
+            .is_some_and(|h| self.validate_host(h))
+        {
+            next.run(req)
+        } else {
+            next.error_response(Code::MisdirectedRequest)
+        }
+    }
+}
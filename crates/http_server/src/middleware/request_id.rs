@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use http_lib::{HeaderName, Request, Response};
+
+use super::{Middleware, Next};
+
+// Longer identifiers sent by clients are replaced.
+const MAX_LEN: usize = 128;
+
+/// Adds an `X-Request-Id` field to every response, so that it can be matched
+/// with the access log. Identifiers sent by the client are kept.
+pub struct RequestId {
+    seed: u32,
+    counter: AtomicU64,
+}
+
+impl RequestId {
+    #[must_use]
+    pub fn new() -> Self {
+        // distinguishes identifiers generated by different processes
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+
+        Self {
+            seed,
+            counter: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for RequestId {
+    fn handle(&self, req: &Request, next: Next<'_>) -> Response {
+        let id = match req.headers.get_single(&HeaderName::X_REQUEST_ID) {
+            Some(id)
+                if id.len() <= MAX_LEN && !id.is_empty() && id.iter().all(u8::is_ascii_graphic) =>
+            {
+                id.to_vec()
+            }
+            _ => {
+                let count = self.counter.fetch_add(1, Ordering::Relaxed);
+                format!("{:08x}{count:08x}", self.seed).into_bytes()
+            }
+        };
+
+        let mut res = next.run(req);
+        if !res.headers.contains_name(&HeaderName::X_REQUEST_ID) {
+            res.add_header_value(HeaderName::X_REQUEST_ID, id.into());
+        }
+
+        res
+    }
+}
@@ -0,0 +1,16 @@
+use http_lib::{Request, Response};
+use log::info;
+
+use super::{Middleware, Next};
+
+/// Logs the method, the path and the status of every response, for servers
+/// without an access log.
+pub struct RequestLog;
+
+impl Middleware for RequestLog {
+    fn handle(&self, req: &Request, next: Next<'_>) -> Response {
+        let res = next.run(req);
+        info!("{} {} {}", req.method, req.uri.path(), res.code);
+        res
+    }
+}
@@ -0,0 +1,64 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use http_lib::request::ParsingError;
+use http_lib::response::Code;
+use http_lib::{Request, Response};
+
+/// A request along with the response sent for it.
+pub struct Exchange<'a> {
+    pub req: &'a Request,
+    pub res: &'a Response,
+    pub peer_addr: Option<SocketAddr>,
+    /// When the whole request was received.
+    pub received: SystemTime,
+    /// Time taken to handle the request and send the response.
+    pub duration: Duration,
+    /// Bytes of the request read from the connection, including the head.
+    pub bytes_received: u64,
+    /// Bytes written to the connection, including the head of the response.
+    pub bytes_sent: u64,
+    /// Bytes of the response body written, without the head.
+    pub body_bytes_sent: u64,
+}
+
+/// A request which was answered without reaching the handler.
+#[derive(Clone, Copy, Debug)]
+pub struct Rejection {
+    pub code: Code,
+    /// Why the request could not be parsed, if it was read at all.
+    pub error: Option<ParsingError>,
+    pub peer_addr: Option<SocketAddr>,
+}
+
+/// Receives events from the connections of a server, e.g. to log them. Calls
+/// are made from the worker threads, after the response has been written,
+/// except for connections rejected by the accepting thread.
+pub trait Observer: Send + Sync + 'static {
+    fn response_sent(&self, exchange: &Exchange<'_>);
+
+    fn connection_opened(&self, _peer_addr: Option<SocketAddr>) {}
+
+    fn connection_closed(&self, _peer_addr: Option<SocketAddr>) {}
+
+    fn request_rejected(&self, _rejection: &Rejection) {}
+}
+
+impl<O: Observer> Observer for Arc<O> {
+    fn response_sent(&self, exchange: &Exchange<'_>) {
+        O::response_sent(self, exchange);
+    }
+
+    fn connection_opened(&self, peer_addr: Option<SocketAddr>) {
+        O::connection_opened(self, peer_addr);
+    }
+
+    fn connection_closed(&self, peer_addr: Option<SocketAddr>) {
+        O::connection_closed(self, peer_addr);
+    }
+
+    fn request_rejected(&self, rejection: &Rejection) {
+        O::request_rejected(self, rejection);
+    }
+}
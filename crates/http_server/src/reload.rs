@@ -0,0 +1,88 @@
+use std::sync::{Arc, PoisonError, RwLock};
+
+use http_lib::response::Code;
+use http_lib::{Request, Response};
+
+use crate::handler::Handler;
+
+/// A handler which can be replaced while the server is running. Clones share
+/// the same handler, so one can be given to the server and another kept to
+/// replace it later.
+///
+/// Connections which are already open keep the handler they started with, so
+/// that the requests of a persistent connection are all handled alike. The
+/// connections accepted afterwards are handled by the new one.
+pub struct Reloadable<H> {
+    current: Arc<RwLock<Arc<H>>>,
+}
+
+impl<H> Clone for Reloadable<H> {
+    fn clone(&self) -> Self {
+        Self {
+            current: Arc::clone(&self.current),
+        }
+    }
+}
+
+impl<H: Handler> Reloadable<H> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(handler))),
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self) -> Arc<H> {
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        Arc::clone(&current)
+    }
+
+    pub fn replace(&self, handler: H) {
+        let handler = Arc::new(handler);
+        let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+        // the previous handler is dropped once the last request using it is done
+        *current = handler;
+    }
+}
+
+impl<H: Handler> Handler for Reloadable<H> {
+    fn handle(&self, req: &Request) -> Response {
+        self.get().handle(req)
+    }
+
+    fn error_response(&self, code: Code) -> Response {
+        self.get().error_response(code)
+    }
+
+    fn max_body_len(&self, head: &Request) -> Option<usize> {
+        self.get().max_body_len(head)
+    }
+
+    fn for_connection(self: Arc<Self>) -> Arc<dyn Handler> {
+        self.get()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use http_lib::Method;
+
+    use super::*;
+
+    fn responding(code: Code) -> Box<dyn Fn(&Request) -> Response + Send + Sync> {
+        Box::new(move |_: &Request| Response::new(code))
+    }
+
+    #[test]
+    fn replace() {
+        let reloadable = Arc::new(Reloadable::new(responding(Code::Ok)));
+        let req = Request::new("localhost".into(), Method::Get, "/".parse().unwrap());
+        let connection = Arc::clone(&reloadable).for_connection();
+
+        reloadable.replace(responding(Code::NotFound));
+        assert_eq!(connection.handle(&req).code, Code::Ok);
+        assert_eq!(reloadable.handle(&req).code, Code::NotFound);
+        let connection = Arc::clone(&reloadable).for_connection();
+        assert_eq!(connection.handle(&req).code, Code::NotFound);
+    }
+}
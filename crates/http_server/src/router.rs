@@ -1,106 +1,162 @@
-use std::fs;
+use std::fs::{self, File};
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
 
-use bytes::Bytes;
-use handlebars::Handlebars;
-use log::{error, info, warn};
+use handlebars::{Context, Handlebars, Helper, HelperResult, Output, RenderContext, RenderError};
+use log::{debug, error, warn};
 use serde::Serialize;
 
-use crate::config::Config;
-use http_lib::{response::Code, transcode::percent_decode, Method, Request, Response};
+use crate::config::{Config, ErrorPage, VirtualHost};
+use crate::error_pages::ErrorPages;
+use crate::handler::Handler;
+use crate::upload;
+use http_lib::coding::{self, ContentCoding};
+use http_lib::conditional::{self, Outcome, Validators};
+use http_lib::etag::EntityTag;
+use http_lib::range::{self, ByteRange};
+use http_lib::response::{Body, Code};
+use http_lib::transcode::{percent_decode, percent_encode, EncodeSet};
+use http_lib::{HeaderName, Method, Request, Response};
 
-pub const DEFAULT_PORT: u16 = 80;
+// Extensions of precompressed files, in order of preference.
+const PRECOMPRESSED: [(ContentCoding, &str); 3] = [
+    (ContentCoding::Brotli, "br"),
+    (ContentCoding::Zstd, "zst"),
+    (ContentCoding::Gzip, "gz"),
+];
 
-// Responds to requests with appropriate resources.
+// Requests for more ranges than this are answered with the whole file.
+const MAX_RANGES: usize = 32;
+
+/// A root directory and how to present it.
+#[derive(Clone, Debug)]
+pub struct Site {
+    pub root: String,
+    pub directory_listing: bool,
+    /// Files sent instead of the default body of error responses.
+    pub error_pages: Vec<ErrorPage>,
+    /// Directory under the root which accepts uploads, along with its
+    /// subdirectories.
+    pub upload_dir: Option<String>,
+    pub max_upload_size: usize,
+}
+
+impl Site {
+    /// The site served for the server's own address and name.
+    #[must_use]
+    pub fn new(config: &Config) -> Self {
+        Self {
+            root: config.root.clone(),
+            directory_listing: config.directory_listing,
+            error_pages: config.error_pages.clone(),
+            upload_dir: config.upload_dir.clone(),
+            max_upload_size: config.max_upload_size,
+        }
+    }
+
+    /// Settings which are not given for the host are taken from the
+    /// configuration.
+    #[must_use]
+    pub fn for_host(host: &VirtualHost, config: &Config) -> Self {
+        Self {
+            root: host.root.clone(),
+            directory_listing: host.directory_listing.unwrap_or(config.directory_listing),
+            error_pages: if host.error_pages.is_empty() {
+                config.error_pages.clone()
+            } else {
+                host.error_pages.clone()
+            },
+            upload_dir: config.upload_dir.clone(),
+            max_upload_size: config.max_upload_size,
+        }
+    }
+}
+
+/// Serves static files from the root directory of a site, along with listings
+/// of its subdirectories if enabled.
 pub struct Router {
     handlebars: Handlebars<'static>,
     root: String,
-    host_ip: Vec<u8>,
-    host_ip_without_port: usize,
-    host_ns: Vec<u8>,
-    host_ns_without_port: usize,
+    directory_listing: bool,
+    error_pages: ErrorPages,
+    // e.g. `/uploads/`
+    upload_path: Option<String>,
+    max_upload_size: usize,
 }
 
 #[derive(Serialize)]
 struct DirTemplateData<'a> {
     path: &'a str,
     contents: Vec<String>,
+    upload: bool,
 }
 
 impl Router {
-    pub fn new(handlebars: Handlebars<'static>, config: &Config) -> Self {
-        let Config {
-            address,
-            port,
-            host,
-            root,
-            ..
-        } = config;
-
-        let host_ip = format!("{address}:{port}");
-        let host_ns = if host.is_empty() {
-            String::new()
-        } else {
-            format!("{host}:{port}")
-        };
+    #[must_use]
+    pub fn new(config: &Config) -> Self {
+        Self::for_site(Site::new(config))
+    }
 
-        let mut host_ip_without_port = 0;
-        let mut host_ns_without_port = 0;
-        if *port == DEFAULT_PORT {
-            host_ip_without_port = host_ip.len().saturating_sub(3);
-            host_ns_without_port = host_ns.len().saturating_sub(3);
-        }
+    #[must_use]
+    pub fn for_site(site: Site) -> Self {
+        let root = site.root.trim_end_matches('/').to_string();
+        let upload_path = site
+            .upload_dir
+            .map(|dir| format!("/{}/", dir.trim_matches('/')));
 
         Self {
-            handlebars,
-            root: root.trim_end_matches('/').to_string(),
-            host_ip: host_ip.into(),
-            host_ip_without_port,
-            host_ns: host_ns.into(),
-            host_ns_without_port,
+            handlebars: init_handlebars_registry(),
+            root,
+            directory_listing: site.directory_listing,
+            error_pages: ErrorPages::new(&site.error_pages),
+            upload_path,
+            max_upload_size: site.max_upload_size,
         }
     }
 
-    fn validate_host(&self, host: &[u8]) -> bool {
-        (!self.host_ns_without_port != 0 && host == &self.host_ns[..self.host_ns_without_port])
-            || (!self.host_ip_without_port != 0
-                && host == &self.host_ip[..self.host_ip_without_port])
-            || (!self.host_ns.is_empty() && host == self.host_ns)
-            || host == self.host_ip
+    fn accepts_uploads(&self, path: &str) -> bool {
+        self.upload_path
+            .as_deref()
+            .is_some_and(|upload_path| path.starts_with(upload_path))
     }
 
-    fn route(&self, req: &Request) -> Response {
-        if !req
-            .headers
-            .get_single(b"Host")
-            .is_some_and(|h| self.validate_host(h))
-        {
-            return Response::new(Code::MisdirectedRequest);
+    // Uploads are posted to the upload directory or one of its
+    // subdirectories.
+    fn accepts_post(&self, req: &Request) -> bool {
+        if self.upload_path.is_none() {
+            return false;
         }
 
-        let mut res = self.get_resource_for_path(req);
-        if req.method == Method::Head {
-            res.body = Bytes::new();
-        }
+        let Ok(path) = percent_decode(req.uri.path().as_bytes()) else {
+            return false;
+        };
 
-        res
+        std::str::from_utf8(&path)
+            .is_ok_and(|path| path.ends_with('/') && self.accepts_uploads(path))
     }
 
     fn get_resource_for_path(&self, req: &Request) -> Response {
-        if !matches!(req.method, Method::Get | Method::Head) {
-            return Response::new(Code::MethodNotAllowed);
+        let accepts_post = self.accepts_post(req);
+        let is_upload = req.method == Method::Post && accepts_post;
+        if !matches!(req.method, Method::Get | Method::Head) && !is_upload {
+            return method_not_allowed(accepts_post);
         }
 
-        if !req.path.first().is_some_and(|&b| b == b'/') {
+        let uri_path = req.uri.path().as_bytes();
+        if uri_path.first() != Some(&b'/') {
             return Response::builder(Code::NotFound)
                 .body("Not found".to_string())
                 .finish();
         }
 
-        if slice_contains(&req.path, b"..") {
+        if slice_contains(uri_path, b"..") {
             return Response::new(Code::BadRequest);
         }
 
-        let Ok(path) = percent_decode(&req.path) else {
+        let Ok(path) = percent_decode(uri_path) else {
             return Response::new(Code::BadRequest);
         };
 
@@ -109,7 +165,17 @@ impl Router {
         };
 
         let real_path = self.root.clone() + path;
-        if req.path.last().is_some_and(|&b| b == b'/') {
+        if is_upload {
+            return self.receive_upload(req, &real_path);
+        }
+
+        if uri_path.last().is_some_and(|&b| b == b'/') {
+            if !self.directory_listing {
+                return Response::builder(Code::Forbidden)
+                    .body("Forbidden".to_string())
+                    .finish();
+            }
+
             let contents = match fs::read_dir(&real_path) {
                 Ok(read_dir) => read_file_names(read_dir),
                 Err(err) => {
@@ -120,10 +186,14 @@ impl Router {
                 }
             };
 
-            let data = DirTemplateData { path, contents };
+            let data = DirTemplateData {
+                path,
+                contents,
+                upload: self.accepts_uploads(path),
+            };
             match self.handlebars.render("dir", &data) {
                 Ok(body) => Response::builder(Code::Ok)
-                    .body_of_type(body.into(), "text/html".into())
+                    .body_of_type(body, "text/html".into())
                     .finish(),
                 Err(err) => {
                     error!("Failed to render template: {err}");
@@ -131,29 +201,336 @@ impl Router {
                 }
             }
         } else {
-            match fs::read(&real_path) {
-                Ok(body) => {
-                    let mime_type = mime_guess::from_path(path).first_or_octet_stream();
-                    Response::builder(Code::Ok)
-                        .body_of_type(body.into(), mime_type.to_string().into())
-                        .finish()
+            match open_file(&real_path) {
+                Some((file, metadata)) => {
+                    let mut res = serve_static_file(req, path, &real_path, file, &metadata);
+                    if self.accepts_uploads(path) {
+                        // uploaded files come from clients, browsers must not
+                        // render them as part of the site
+                        res.add_header_value(HeaderName::CONTENT_DISPOSITION, "attachment".into());
+                        res.add_header_value(HeaderName::X_CONTENT_TYPE_OPTIONS, "nosniff".into());
+                    }
+                    res
                 }
-                Err(_) => Response::builder(Code::NotFound)
+                None => Response::builder(Code::NotFound)
                     .body("Not found".to_string())
                     .finish(),
             }
         }
     }
 
-    pub fn handle(&self, req: &Request) -> Response {
-        let method = req.method;
-        let path = req.path.clone();
-        let path = std::str::from_utf8(&path).unwrap();
-        let res = self.route(req);
-        let code = res.code;
-        info!("{method} {path} {code}");
+    fn receive_upload(&self, req: &Request, real_path: &str) -> Response {
+        let dir = Path::new(real_path);
+        if !dir.is_dir() {
+            return Response::builder(Code::NotFound)
+                .body("Not found".to_string())
+                .finish();
+        }
+
+        upload::receive(req, dir, self.max_upload_size)
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, req: &Request) -> Response {
+        let mut res = self.get_resource_for_path(req);
+        self.error_pages.apply(&mut res);
         res
     }
+
+    fn error_response(&self, code: Code) -> Response {
+        self.error_pages.response(code)
+    }
+
+    // only uploads may be larger than the server's limit
+    fn max_body_len(&self, head: &Request) -> Option<usize> {
+        (head.method == Method::Post && self.accepts_post(head)).then_some(self.max_upload_size)
+    }
+}
+
+fn method_not_allowed(accepts_post: bool) -> Response {
+    let allow = if accepts_post {
+        "GET, HEAD, POST"
+    } else {
+        "GET, HEAD"
+    };
+
+    Response::builder(Code::MethodNotAllowed)
+        .add_header_value(HeaderName::ALLOW, allow.into())
+        .finish()
+}
+
+fn init_handlebars_registry() -> Handlebars<'static> {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_helper("percent_encode", Box::new(percent_encode_helper));
+
+    handlebars
+        .register_template_string("dir", include_str!("dir.hbs"))
+        .unwrap();
+
+    handlebars
+}
+
+// `{{percent_encode value}}` encodes a path segment, another component of a URI
+// can be chosen with `set="path"`, `"query"`, `"userinfo"` or `"fragment"`.
+fn percent_encode_helper(
+    h: &Helper<'_, '_>,
+    _: &Handlebars<'_>,
+    _: &Context,
+    _: &mut RenderContext<'_, '_>,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let value = h
+        .param(0)
+        .ok_or_else(|| RenderError::new("percent_encode: expected a value"))?;
+
+    let set = match h.hash_get("set").map(|set| set.value().as_str()) {
+        None | Some(Some("path-segment")) => EncodeSet::PathSegment,
+        Some(Some("path")) => EncodeSet::Path,
+        Some(Some("query")) => EncodeSet::QueryValue,
+        Some(Some("userinfo")) => EncodeSet::Userinfo,
+        Some(Some("fragment")) => EncodeSet::Fragment,
+        Some(_) => return Err(RenderError::new(
+            "percent_encode: set must be one of path-segment, path, query, userinfo or fragment",
+        )),
+    };
+
+    out.write(&percent_encode(value.render().as_bytes(), set))?;
+    Ok(())
+}
+
+fn open_file(path: &str) -> Option<(File, fs::Metadata)> {
+    let file = File::open(path).ok()?;
+    let metadata = file.metadata().ok()?;
+    metadata.is_file().then_some((file, metadata))
+}
+
+// Serves the best precompressed sibling of the file accepted by the client, e.g.
+// `app.js.br` for `app.js`, falling back to the file itself. Only the siblings
+// of accepted codings are looked up, and those older than the file are ignored
+// as left over from a previous version.
+fn serve_static_file(
+    req: &Request,
+    path: &str,
+    real_path: &str,
+    file: File,
+    metadata: &fs::Metadata,
+) -> Response {
+    let accept_encoding = req.headers.get(&HeaderName::ACCEPT_ENCODING);
+    let mut candidates = PRECOMPRESSED.map(|(coding, _)| coding).to_vec();
+    loop {
+        let coding = coding::negotiate(accept_encoding, &candidates);
+        let Some(&(_, ext)) = PRECOMPRESSED.iter().find(|&&(c, _)| c == coding) else {
+            break;
+        };
+        candidates.retain(|&c| c != coding);
+
+        let Some((sidecar, sidecar_metadata)) = open_file(&format!("{real_path}.{ext}")) else {
+            continue;
+        };
+
+        if is_older(&sidecar_metadata, metadata) {
+            debug!("Ignoring {path}.{ext}, which is older than {path}");
+            continue;
+        }
+
+        debug!("Serving {path} precompressed with {coding}");
+        let mut res = serve_file(req, path, sidecar, &sidecar_metadata);
+        if matches!(
+            res.code,
+            Code::Ok | Code::PartialContent | Code::NotModified
+        ) {
+            res.add_header_value(HeaderName::CONTENT_ENCODING, coding.as_str().into());
+        }
+        res.add_header_value(HeaderName::VARY, "Accept-Encoding".into());
+        return res;
+    }
+
+    serve_file(req, path, file, metadata)
+}
+
+fn is_older(metadata: &fs::Metadata, than: &fs::Metadata) -> bool {
+    match (metadata.modified(), than.modified()) {
+        (Ok(modified), Ok(than)) => modified < than,
+        _ => false,
+    }
+}
+
+fn serve_file(req: &Request, path: &str, file: File, metadata: &fs::Metadata) -> Response {
+    let etag = etag_for(metadata);
+    let last_modified = metadata.modified().ok();
+    let validators = Validators {
+        etag: Some(&etag),
+        last_modified,
+    };
+
+    let mut res = match conditional::evaluate(req.method, &req.headers, validators) {
+        Outcome::Proceed => serve_file_contents(req, path, file, validators),
+        Outcome::NotModified => Response::new(Code::NotModified),
+        Outcome::PreconditionFailed => return Response::new(Code::PreconditionFailed),
+    };
+
+    res.add_header_value(HeaderName::ETAG, etag.to_buffer().into());
+    if let Some(last_modified) = last_modified {
+        let last_modified = httpdate::fmt_http_date(last_modified);
+        res.add_header_value(HeaderName::LAST_MODIFIED, last_modified.into());
+    }
+
+    res
+}
+
+fn serve_file_contents(req: &Request, path: &str, file: File, validators: Validators) -> Response {
+    let mime_type = mime_guess::from_path(path)
+        .first_or_octet_stream()
+        .to_string();
+    let len = file.metadata().map_or(0, |m| m.len());
+
+    let ranges = requested_ranges(req, validators).map(|ranges| range::resolve_all(&ranges, len));
+    let mut res = match ranges.as_deref() {
+        None => Response::builder(Code::Ok)
+            .body_of_type(Body::file(file, 0, len), mime_type.into())
+            .finish(),
+        Some([]) => Response::builder(Code::RangeNotSatisfiable)
+            .add_header_value(
+                HeaderName::CONTENT_RANGE,
+                range::unsatisfied_content_range(len).into(),
+            )
+            .finish(),
+        Some([single]) => Response::builder(Code::PartialContent)
+            .add_header_value(
+                HeaderName::CONTENT_RANGE,
+                range::content_range(single, len).into(),
+            )
+            .body_of_type(
+                Body::file(file, *single.start(), single.end() - single.start() + 1),
+                mime_type.into(),
+            )
+            .finish(),
+        Some(ranges) => match multipart_byteranges(&file, ranges, len, &mime_type) {
+            Ok(res) => res,
+            Err(err) => {
+                error!("Failed to prepare a multipart response: {err}");
+                Response::new(Code::InternalServerError)
+            }
+        },
+    };
+
+    res.add_header_value(HeaderName::ACCEPT_RANGES, "bytes".into());
+    res
+}
+
+// Derives a strong entity tag from the inode, size and modification time.
+fn etag_for(metadata: &fs::Metadata) -> EntityTag {
+    #[cfg(unix)]
+    let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+    #[cfg(not(unix))]
+    let inode = 0;
+
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|m| m.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+
+    let tag = format!("{inode:x}-{:x}-{modified:x}", metadata.len());
+    EntityTag::strong(tag).expect("hex digits and dashes are valid in entity tags")
+}
+
+// Returns the ranges requested by the client, or `None` if the whole file
+// should be sent.
+fn requested_ranges(req: &Request, validators: Validators) -> Option<Vec<ByteRange>> {
+    if req.method != Method::Get {
+        return None;
+    }
+
+    let value = req.headers.get(&HeaderName::RANGE)?.to_buffer();
+    let ranges = match range::parse(&value) {
+        Ok(ranges) => ranges,
+        Err(err) => {
+            debug!("Ignoring Range header: {err}");
+            return None;
+        }
+    };
+
+    if ranges.len() > MAX_RANGES {
+        debug!("Ignoring Range header with {} ranges", ranges.len());
+        return None;
+    }
+
+    if let Some(if_range) = req.headers.get(&HeaderName::IF_RANGE) {
+        if !if_range_matches(&if_range.to_buffer(), validators) {
+            return None;
+        }
+    }
+
+    Some(ranges)
+}
+
+// See https://httpwg.org/specs/rfc9110.html#field.if-range
+fn if_range_matches(value: &[u8], validators: Validators) -> bool {
+    if let Some(etag) = EntityTag::from_bytes(value) {
+        return validators.etag.is_some_and(|e| e.strong_eq(&etag));
+    }
+
+    let Some(date) = std::str::from_utf8(value)
+        .ok()
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+    else {
+        return false;
+    };
+
+    let Some(modified) = validators.last_modified else {
+        return false;
+    };
+
+    // only a strong validator can match, which requires the modification
+    // time to be at least one second in the past
+    // https://httpwg.org/specs/rfc9110.html#lastmod.comparison
+    let is_strong = modified + Duration::from_secs(1) <= SystemTime::now();
+    is_strong && httpdate::fmt_http_date(modified) == httpdate::fmt_http_date(date)
+}
+
+fn multipart_byteranges(
+    file: &File,
+    ranges: &[RangeInclusive<u64>],
+    len: u64,
+    mime_type: &str,
+) -> io::Result<Response> {
+    let boundary = generate_boundary();
+    let mut parts = Vec::with_capacity(ranges.len() * 2 + 1);
+    for (i, range) in ranges.iter().enumerate() {
+        let mut head = Vec::new();
+        range::write_part_head(
+            &mut head,
+            &boundary,
+            mime_type.as_bytes(),
+            range,
+            len,
+            i == 0,
+        );
+        parts.push(head.into());
+
+        let part_len = range.end() - range.start() + 1;
+        parts.push(Body::file(file.try_clone()?, *range.start(), part_len));
+    }
+
+    let mut tail = Vec::new();
+    range::write_close_delimiter(&mut tail, &boundary);
+    parts.push(tail.into());
+
+    let content_type = format!("multipart/byteranges; boundary={boundary}");
+    Ok(Response::builder(Code::PartialContent)
+        .body_of_type(Body::Parts(parts), content_type.into())
+        .finish())
+}
+
+fn generate_boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{nanos:08x}{count:08x}")
 }
 
 fn read_file_names(read_dir: fs::ReadDir) -> Vec<String> {
@@ -168,7 +545,7 @@ fn read_file_names(read_dir: fs::ReadDir) -> Vec<String> {
             // this should never happen, but just in case it does, prevent a crash
             continue;
         }
-        if file.metadata().map_or(false, |m| m.is_dir()) {
+        if file.metadata().is_ok_and(|m| m.is_dir()) {
             file_name.push('/');
         }
         file_names.push(file_name);
@@ -206,3 +583,453 @@ fn slice_contains<T: PartialEq>(mut haystack: &[T], needle: &[T]) -> bool {
 
     false
 }
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::server::test::Running;
+    use crate::server::{Limits, Server};
+
+    const DATA: &str = "0123456789abcdefghij";
+    const MAX_UPLOAD_SIZE: usize = 1024 * 1024;
+
+    // Serves a temporary directory holding `data.txt`.
+    fn site() -> (TempDir, Router) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("data.txt"), DATA).unwrap();
+        let router = Router::for_site(Site {
+            root: dir.path().to_str().unwrap().to_string(),
+            directory_listing: false,
+            error_pages: Vec::new(),
+            upload_dir: None,
+            max_upload_size: 0,
+        });
+        (dir, router)
+    }
+
+    // Like `site`, with uploads accepted into `uploads`.
+    fn upload_site() -> (TempDir, Router) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("data.txt"), DATA).unwrap();
+        fs::create_dir_all(dir.path().join("uploads/photos")).unwrap();
+        let router = Router::for_site(Site {
+            root: dir.path().to_str().unwrap().to_string(),
+            directory_listing: false,
+            error_pages: Vec::new(),
+            upload_dir: Some("uploads".to_string()),
+            max_upload_size: MAX_UPLOAD_SIZE,
+        });
+        (dir, router)
+    }
+
+    fn request(method: Method, path: &str) -> Request {
+        Request::new("localhost".into(), method, path.parse().unwrap())
+    }
+
+    fn upload(path: &str, file_name: &str, contents: &[u8]) -> Request {
+        let mut body = format!(
+            "--XyZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"{file_name}\"\r\n\
+            Content-Type: application/octet-stream\r\n\
+            \r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(contents);
+        body.extend_from_slice(b"\r\n--XyZ--\r\n");
+
+        let mut req = request(Method::Post, path);
+        req.add_header_value(
+            HeaderName::CONTENT_TYPE,
+            "multipart/form-data; boundary=XyZ".into(),
+        );
+        req.add_header_value(HeaderName::CONTENT_LENGTH, body.len().to_string().into());
+        req.body = body.into();
+        req
+    }
+
+    fn get(router: &Router, path: &str, headers: &[(HeaderName, &str)]) -> Response {
+        let mut req = Request::new(Bytes::new(), Method::Get, path.parse().unwrap());
+        for (name, value) in headers {
+            req.add_header_value(name.clone(), value.to_string().into());
+        }
+        router.handle(&req)
+    }
+
+    fn header<'a>(res: &'a Response, name: &HeaderName) -> Option<&'a str> {
+        let value = res.headers.get_single(name)?;
+        Some(std::str::from_utf8(value).unwrap())
+    }
+
+    fn body(res: &mut Response) -> String {
+        let mut body = Vec::new();
+        res.body
+            .write_to(&mut body, &mut Vec::new(), false)
+            .unwrap();
+        String::from_utf8(body).unwrap()
+    }
+
+    // Moves the modification time of `name` a minute into the past, so that it
+    // is old enough to be used as a strong validator.
+    fn backdate(dir: &TempDir, name: &str) -> SystemTime {
+        let modified = SystemTime::now() - Duration::from_mins(1);
+        File::options()
+            .write(true)
+            .open(dir.path().join(name))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        modified
+    }
+
+    fn range(router: &Router, value: &str) -> Response {
+        get(router, "/data.txt", &[(HeaderName::RANGE, value)])
+    }
+
+    #[test]
+    fn single_range() {
+        let (_dir, router) = site();
+        let mut res = get(&router, "/data.txt", &[]);
+        assert_eq!(res.code, Code::Ok);
+        assert_eq!(header(&res, &HeaderName::ACCEPT_RANGES), Some("bytes"));
+        assert_eq!(body(&mut res), DATA);
+
+        for (value, content_range, expected) in [
+            ("bytes=2-5", "bytes 2-5/20", "2345"),
+            ("bytes=15-", "bytes 15-19/20", "fghij"),
+            ("bytes=-3", "bytes 17-19/20", "hij"),
+            ("bytes=18-100", "bytes 18-19/20", "ij"),
+        ] {
+            let mut res = range(&router, value);
+            assert_eq!(res.code, Code::PartialContent, "{value}");
+            assert_eq!(
+                header(&res, &HeaderName::CONTENT_RANGE),
+                Some(content_range)
+            );
+            assert_eq!(body(&mut res), expected);
+        }
+    }
+
+    #[test]
+    fn unsatisfiable_range() {
+        let (_dir, router) = site();
+        for value in ["bytes=20-", "bytes=30-40", "bytes=-0"] {
+            let res = range(&router, value);
+            assert_eq!(res.code, Code::RangeNotSatisfiable, "{value}");
+            assert_eq!(header(&res, &HeaderName::CONTENT_RANGE), Some("bytes */20"));
+        }
+
+        // malformed ranges are ignored
+        let res = range(&router, "lines=1-2");
+        assert_eq!(res.code, Code::Ok);
+    }
+
+    #[test]
+    fn multiple_ranges() {
+        let (_dir, router) = site();
+        let mut res = range(&router, "bytes=0-1, 5-6, -2");
+        assert_eq!(res.code, Code::PartialContent);
+        assert_eq!(header(&res, &HeaderName::CONTENT_RANGE), None);
+
+        let content_type = header(&res, &HeaderName::CONTENT_TYPE).unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            body(&mut res),
+            format!(
+                "--{boundary}\r\n\
+                Content-Type: text/plain\r\n\
+                Content-Range: bytes 0-1/20\r\n\
+                \r\n\
+                01\r\n\
+                --{boundary}\r\n\
+                Content-Type: text/plain\r\n\
+                Content-Range: bytes 5-6/20\r\n\
+                \r\n\
+                56\r\n\
+                --{boundary}\r\n\
+                Content-Type: text/plain\r\n\
+                Content-Range: bytes 18-19/20\r\n\
+                \r\n\
+                ij\r\n\
+                --{boundary}--\r\n"
+            )
+        );
+
+        // too many ranges are answered with the whole file
+        let value = format!("bytes={}", vec!["0-0"; MAX_RANGES + 1].join(","));
+        let mut res = range(&router, &value);
+        assert_eq!(res.code, Code::Ok);
+        assert_eq!(body(&mut res), DATA);
+    }
+
+    #[test]
+    fn if_range() {
+        let (dir, router) = site();
+        let modified = backdate(&dir, "data.txt");
+
+        let res = get(&router, "/data.txt", &[]);
+        let etag = header(&res, &HeaderName::ETAG).unwrap().to_string();
+        let last_modified = httpdate::fmt_http_date(modified);
+        let earlier = httpdate::fmt_http_date(modified - Duration::from_mins(1));
+
+        for (if_range, code) in [
+            (etag.as_str(), Code::PartialContent),
+            (last_modified.as_str(), Code::PartialContent),
+            ("\"other\"", Code::Ok),
+            (&format!("W/{etag}"), Code::Ok),
+            (earlier.as_str(), Code::Ok),
+            ("not a date", Code::Ok),
+        ] {
+            let res = get(
+                &router,
+                "/data.txt",
+                &[
+                    (HeaderName::RANGE, "bytes=0-1"),
+                    (HeaderName::IF_RANGE, if_range),
+                ],
+            );
+            assert_eq!(res.code, code, "{if_range}");
+        }
+    }
+
+    #[test]
+    fn not_modified() {
+        let (dir, router) = site();
+        let modified = backdate(&dir, "data.txt");
+
+        let res = get(&router, "/data.txt", &[]);
+        let etag = header(&res, &HeaderName::ETAG).unwrap().to_string();
+        let last_modified = httpdate::fmt_http_date(modified);
+        let earlier = httpdate::fmt_http_date(modified - Duration::from_mins(1));
+
+        for (name, value, code) in [
+            (HeaderName::IF_NONE_MATCH, etag.as_str(), Code::NotModified),
+            (HeaderName::IF_NONE_MATCH, "*", Code::NotModified),
+            (HeaderName::IF_NONE_MATCH, "\"other\"", Code::Ok),
+            (
+                HeaderName::IF_MODIFIED_SINCE,
+                &last_modified,
+                Code::NotModified,
+            ),
+            (HeaderName::IF_MODIFIED_SINCE, &earlier, Code::Ok),
+        ] {
+            let mut res = get(&router, "/data.txt", &[(name.clone(), value)]);
+            assert_eq!(res.code, code, "{name:?}: {value}");
+            assert_eq!(header(&res, &HeaderName::ETAG), Some(etag.as_str()));
+            assert_eq!(
+                header(&res, &HeaderName::LAST_MODIFIED),
+                Some(last_modified.as_str())
+            );
+            if code == Code::NotModified {
+                assert_eq!(header(&res, &HeaderName::CONTENT_TYPE), None);
+                assert_eq!(body(&mut res), "");
+            }
+        }
+
+        // If-None-Match takes precedence over If-Modified-Since
+        let res = get(
+            &router,
+            "/data.txt",
+            &[
+                (HeaderName::IF_NONE_MATCH, "\"other\""),
+                (HeaderName::IF_MODIFIED_SINCE, &last_modified),
+            ],
+        );
+        assert_eq!(res.code, Code::Ok);
+    }
+
+    #[test]
+    fn precondition_failed() {
+        let (dir, router) = site();
+        let modified = backdate(&dir, "data.txt");
+
+        let res = get(&router, "/data.txt", &[]);
+        let etag = header(&res, &HeaderName::ETAG).unwrap().to_string();
+        let earlier = httpdate::fmt_http_date(modified - Duration::from_mins(1));
+        let later = httpdate::fmt_http_date(modified + Duration::from_mins(1));
+
+        for (name, value, code) in [
+            (HeaderName::IF_MATCH, "\"other\"", Code::PreconditionFailed),
+            (
+                HeaderName::IF_MATCH,
+                &format!("W/{etag}"),
+                Code::PreconditionFailed,
+            ),
+            (HeaderName::IF_MATCH, etag.as_str(), Code::Ok),
+            (HeaderName::IF_MATCH, "*", Code::Ok),
+            (
+                HeaderName::IF_UNMODIFIED_SINCE,
+                &earlier,
+                Code::PreconditionFailed,
+            ),
+            (HeaderName::IF_UNMODIFIED_SINCE, &later, Code::Ok),
+        ] {
+            let res = get(&router, "/data.txt", &[(name.clone(), value)]);
+            assert_eq!(res.code, code, "{name:?}: {value}");
+        }
+
+        // a failed precondition wins over a range
+        let res = get(
+            &router,
+            "/data.txt",
+            &[
+                (HeaderName::IF_MATCH, "\"other\""),
+                (HeaderName::RANGE, "bytes=0-1"),
+            ],
+        );
+        assert_eq!(res.code, Code::PreconditionFailed);
+    }
+
+    fn accepting(router: &Router, accept_encoding: &[&str]) -> Response {
+        let headers: Vec<_> = accept_encoding
+            .iter()
+            .map(|&value| (HeaderName::ACCEPT_ENCODING, value))
+            .collect();
+        get(router, "/data.txt", &headers)
+    }
+
+    #[test]
+    fn precompressed() {
+        let (dir, router) = site();
+        backdate(&dir, "data.txt");
+        fs::write(dir.path().join("data.txt.br"), "br").unwrap();
+        fs::write(dir.path().join("data.txt.gz"), "gzip").unwrap();
+
+        for (accept_encoding, coding) in [
+            (&["gzip", "deflate", "br"][..], "br"),
+            (&["gzip"], "gzip"),
+            (&["br;q=0.5", "gzip"], "gzip"),
+            // there is no zstd sibling
+            (&["zstd", "gzip;q=0.1"], "gzip"),
+            (&["*"], "br"),
+        ] {
+            let mut res = accepting(&router, accept_encoding);
+            assert_eq!(res.code, Code::Ok);
+            assert_eq!(header(&res, &HeaderName::CONTENT_ENCODING), Some(coding));
+            assert_eq!(header(&res, &HeaderName::CONTENT_TYPE), Some("text/plain"));
+            assert_eq!(header(&res, &HeaderName::VARY), Some("Accept-Encoding"));
+            assert_eq!(body(&mut res), coding);
+        }
+
+        for accept_encoding in [&[][..], &["identity"], &["zstd"], &["br;q=0", "gzip;q=0"]] {
+            let mut res = accepting(&router, accept_encoding);
+            assert_eq!(header(&res, &HeaderName::CONTENT_ENCODING), None);
+            assert_eq!(header(&res, &HeaderName::VARY), None);
+            assert_eq!(body(&mut res), DATA);
+        }
+    }
+
+    #[test]
+    fn stale_precompressed() {
+        let (dir, router) = site();
+        fs::write(dir.path().join("data.txt.br"), "br").unwrap();
+        fs::write(dir.path().join("data.txt.gz"), "gzip").unwrap();
+        backdate(&dir, "data.txt.br");
+
+        let mut res = accepting(&router, &["br", "gzip;q=0.5"]);
+        assert_eq!(header(&res, &HeaderName::CONTENT_ENCODING), Some("gzip"));
+        assert_eq!(body(&mut res), "gzip");
+
+        backdate(&dir, "data.txt.gz");
+        let mut res = accepting(&router, &["br", "gzip;q=0.5"]);
+        assert_eq!(header(&res, &HeaderName::CONTENT_ENCODING), None);
+        assert_eq!(body(&mut res), DATA);
+    }
+
+    #[test]
+    fn upload_body_limit() {
+        let (_dir, router) = upload_site();
+        for (method, path, limit) in [
+            (Method::Post, "/uploads/", Some(MAX_UPLOAD_SIZE)),
+            (Method::Post, "/uploads/photos/", Some(MAX_UPLOAD_SIZE)),
+            (Method::Post, "/%75ploads/", Some(MAX_UPLOAD_SIZE)),
+            (Method::Post, "/uploads", None),
+            (Method::Post, "/uploads/a.txt", None),
+            (Method::Post, "/", None),
+            (Method::Put, "/uploads/", None),
+            (Method::Get, "/uploads/", None),
+        ] {
+            let req = request(method, path);
+            assert_eq!(router.max_body_len(&req), limit, "{method:?} {path}");
+        }
+
+        let (_dir, router) = site();
+        assert_eq!(
+            router.max_body_len(&request(Method::Post, "/uploads/")),
+            None
+        );
+    }
+
+    #[test]
+    fn upload_allow() {
+        let (_dir, router) = upload_site();
+        for (method, path, allow) in [
+            (Method::Put, "/uploads/", "GET, HEAD, POST"),
+            (Method::Delete, "/uploads/photos/", "GET, HEAD, POST"),
+            (Method::Put, "/uploads/a.txt", "GET, HEAD"),
+            (Method::Post, "/uploads/a.txt", "GET, HEAD"),
+            (Method::Post, "/data.txt", "GET, HEAD"),
+        ] {
+            let res = router.handle(&request(method, path));
+            assert_eq!(res.code, Code::MethodNotAllowed, "{method:?} {path}");
+            assert_eq!(
+                header(&res, &HeaderName::ALLOW),
+                Some(allow),
+                "{method:?} {path}"
+            );
+        }
+    }
+
+    #[test]
+    fn upload_in_pieces() {
+        let (dir, router) = upload_site();
+        // spans several of the pieces the body is parsed in
+        let contents: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let res = router.handle(&upload("/uploads/photos/", "a.bin", &contents));
+        assert_eq!(res.code, Code::SeeOther);
+        assert_eq!(
+            fs::read(dir.path().join("uploads/photos/a.bin")).unwrap(),
+            contents
+        );
+    }
+
+    #[test]
+    fn upload_limit_over_sockets() {
+        let (dir, router) = upload_site();
+        let limits = Limits {
+            max_body_len: 1024,
+            ..Limits::default()
+        };
+        let server = Running::start(Server::builder(router).limits(limits));
+        let contents = vec![b'a'; 4096];
+
+        for (path, code) in [
+            ("/uploads/", Code::SeeOther),
+            ("/data.txt", Code::PayloadTooLarge),
+            ("/uploads/a.txt", Code::PayloadTooLarge),
+        ] {
+            let req = upload(path, "a.txt", &contents);
+            let mut client = server.connect();
+            client.send(std::str::from_utf8(&req.to_buffer()).unwrap());
+            assert_eq!(client.response().unwrap().code, code, "{path}");
+        }
+        assert_eq!(
+            fs::read(dir.path().join("uploads/a.txt")).unwrap(),
+            contents
+        );
+
+        // uploads have their own limit, checked before the body is read
+        let req = upload("/uploads/", "b.txt", &vec![b'a'; MAX_UPLOAD_SIZE]);
+        let req = String::from_utf8(req.to_buffer()).unwrap();
+        let (head, _) = req.split_once("\r\n\r\n").unwrap();
+        let mut client = server.connect();
+        client.send(&format!("{head}\r\n\r\n"));
+        assert_eq!(client.response().unwrap().code, Code::PayloadTooLarge);
+        assert!(!dir.path().join("uploads/b.txt").exists());
+    }
+}
@@ -0,0 +1,391 @@
+use http_lib::response::Code;
+use http_lib::transcode::percent_decode;
+use http_lib::{HeaderName, Method, Request, Response};
+
+use crate::handler::Handler;
+
+/// Parameters captured from the path by `:name` and `*name` segments.
+#[derive(Clone, Default, Debug)]
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// A handler of a single route, which receives the captured parameters along
+/// with the request.
+pub trait Endpoint: Send + Sync + 'static {
+    fn call(&self, req: &Request, params: &Params) -> Response;
+}
+
+impl<F> Endpoint for F
+where
+    F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+{
+    fn call(&self, req: &Request, params: &Params) -> Response {
+        self(req, params)
+    }
+}
+
+#[derive(Debug)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Rest(String),
+}
+
+// A path pattern, e.g. `/api/users/:id` or `/static/*rest`.
+#[derive(Debug)]
+struct Pattern(Vec<Segment>);
+
+impl Pattern {
+    fn parse(pattern: &str) -> Self {
+        let Some(pattern) = pattern.strip_prefix('/') else {
+            panic!("pattern {pattern:?} does not start with a slash");
+        };
+
+        let mut segments = Vec::new();
+        let mut parts = pattern.split('/').peekable();
+        while let Some(part) = parts.next() {
+            let segment = if let Some(name) = part.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = part.strip_prefix('*') {
+                assert!(
+                    parts.peek().is_none(),
+                    "wildcard {part:?} is not the last segment"
+                );
+                Segment::Rest(name.to_string())
+            } else {
+                Segment::Literal(part.to_string())
+            };
+
+            segments.push(segment);
+        }
+
+        Self(segments)
+    }
+
+    // Returns the captured parameters if the path matches.
+    fn matches(&self, path: &[u8]) -> Option<Params> {
+        let mut rest = path.strip_prefix(b"/")?;
+        let mut params = Vec::new();
+        for (i, segment) in self.0.iter().enumerate() {
+            let end = rest.iter().position(|&b| b == b'/').unwrap_or(rest.len());
+            let (part, remaining) = rest.split_at(end);
+            let is_last = i + 1 == self.0.len();
+            match segment {
+                Segment::Literal(literal) if literal.as_bytes() == part => {}
+                Segment::Param(name) if !part.is_empty() => {
+                    params.push((name.clone(), decode(part)?));
+                }
+                Segment::Rest(name) => {
+                    params.push((name.clone(), decode(rest)?));
+                    return Some(Params(params));
+                }
+                _ => return None,
+            }
+
+            match remaining.strip_prefix(b"/") {
+                Some(remaining) if !is_last => rest = remaining,
+                None if is_last => return Some(Params(params)),
+                _ => return None,
+            }
+        }
+
+        None
+    }
+}
+
+fn decode(bytes: &[u8]) -> Option<String> {
+    let decoded = percent_decode(bytes).ok()?;
+    String::from_utf8(decoded).ok()
+}
+
+enum Route {
+    Endpoint {
+        method: Method,
+        pattern: Pattern,
+        endpoint: Box<dyn Endpoint>,
+    },
+    // Handlers mounted under a prefix receive the request with the prefix
+    // removed from the path.
+    Mount {
+        prefix: Vec<u8>,
+        handler: Box<dyn Handler>,
+    },
+}
+
+/// Dispatches requests to handlers based on the method and the path. Routes
+/// are tried in the order they were added.
+///
+/// `HEAD` requests are answered by `GET` routes. Paths which match a route,
+/// but not its method, are answered with `405 Method Not Allowed`.
+#[derive(Default)]
+pub struct Routes {
+    routes: Vec<Route>,
+}
+
+impl Routes {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Panics
+    ///
+    /// If the pattern does not start with a slash, or a `*name` segment is not
+    /// the last one.
+    #[must_use]
+    pub fn route(mut self, method: Method, pattern: &str, endpoint: impl Endpoint) -> Self {
+        self.routes.push(Route::Endpoint {
+            method,
+            pattern: Pattern::parse(pattern),
+            endpoint: Box::new(endpoint),
+        });
+        self
+    }
+
+    #[must_use]
+    pub fn get(self, pattern: &str, endpoint: impl Endpoint) -> Self {
+        self.route(Method::Get, pattern, endpoint)
+    }
+
+    #[must_use]
+    pub fn post(self, pattern: &str, endpoint: impl Endpoint) -> Self {
+        self.route(Method::Post, pattern, endpoint)
+    }
+
+    #[must_use]
+    pub fn put(self, pattern: &str, endpoint: impl Endpoint) -> Self {
+        self.route(Method::Put, pattern, endpoint)
+    }
+
+    #[must_use]
+    pub fn delete(self, pattern: &str, endpoint: impl Endpoint) -> Self {
+        self.route(Method::Delete, pattern, endpoint)
+    }
+
+    /// Passes requests for the prefix and everything below it to the handler,
+    /// regardless of the method, e.g. `/static/app.js` is passed as `/app.js`
+    /// when mounted under `/static`.
+    #[must_use]
+    pub fn mount(mut self, prefix: &str, handler: impl Handler) -> Self {
+        self.routes.push(Route::Mount {
+            prefix: prefix.trim_end_matches('/').as_bytes().to_vec(),
+            handler: Box::new(handler),
+        });
+        self
+    }
+}
+
+impl Handler for Routes {
+    fn handle(&self, req: &Request) -> Response {
+        let path = req.uri.path().as_bytes();
+
+        let mut allowed = Vec::new();
+        for route in &self.routes {
+            match route {
+                Route::Endpoint {
+                    method,
+                    pattern,
+                    endpoint,
+                } => {
+                    let Some(params) = pattern.matches(path) else {
+                        continue;
+                    };
+
+                    if *method == req.method || (*method, req.method) == (Method::Get, Method::Head)
+                    {
+                        return endpoint.call(req, &params);
+                    }
+
+                    if !allowed.contains(method) {
+                        allowed.push(*method);
+                    }
+                }
+                Route::Mount { prefix, handler } => {
+                    if is_under(path, prefix) {
+                        return handler.handle(&strip_prefix(req, prefix.len()));
+                    }
+                }
+            }
+        }
+
+        if allowed.is_empty() {
+            return Response::builder(Code::NotFound)
+                .body("Not found".to_string())
+                .finish();
+        }
+
+        if allowed.contains(&Method::Get) && !allowed.contains(&Method::Head) {
+            allowed.push(Method::Head);
+        }
+
+        let allow: Vec<&str> = allowed.iter().map(|m| m.as_str()).collect();
+        Response::builder(Code::MethodNotAllowed)
+            .add_header_value(HeaderName::ALLOW, allow.join(", ").into())
+            .finish()
+    }
+
+    // Asks the handler the request would be dispatched to.
+    fn max_body_len(&self, head: &Request) -> Option<usize> {
+        let path = head.uri.path().as_bytes();
+        for route in &self.routes {
+            match route {
+                Route::Endpoint {
+                    method, pattern, ..
+                } => {
+                    let matches_method = *method == head.method
+                        || (*method, head.method) == (Method::Get, Method::Head);
+                    if matches_method && pattern.matches(path).is_some() {
+                        return None;
+                    }
+                }
+                Route::Mount { prefix, handler } => {
+                    if is_under(path, prefix) {
+                        return handler.max_body_len(&strip_prefix(head, prefix.len()));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+fn is_under(path: &[u8], prefix: &[u8]) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest[0] == b'/')
+}
+
+fn strip_prefix(req: &Request, len: usize) -> Request {
+    let rest = &req.uri.path()[len..];
+    let path = if rest.starts_with('/') {
+        rest.to_string()
+    } else {
+        format!("/{rest}")
+    };
+
+    let mut req = req.clone();
+    req.uri
+        .set_path(&path)
+        .expect("the rest of a normalized path is a valid path");
+    req
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(method: Method, path: &str) -> Request {
+        Request::new("localhost".into(), method, path.parse().unwrap())
+    }
+
+    fn body(res: &Response) -> &[u8] {
+        res.body.as_bytes().map_or(&[], |b| b)
+    }
+
+    fn echo_path(req: &Request) -> Response {
+        Response::builder(Code::Ok)
+            .body(req.uri.path().to_string())
+            .finish()
+    }
+
+    #[test]
+    fn pattern() {
+        let pattern = Pattern::parse("/users/:id/posts/:post");
+        let params = pattern.matches(b"/users/42/posts/a%20b").unwrap();
+        assert_eq!(params.get("id"), Some("42"));
+        assert_eq!(params.get("post"), Some("a b"));
+        assert!(pattern.matches(b"/users/42/posts").is_none());
+        assert!(pattern.matches(b"/users//posts/1").is_none());
+        assert!(pattern.matches(b"/users/42/posts/1/").is_none());
+        assert!(pattern.matches(b"/users/42/posts/%ff").is_none());
+
+        let pattern = Pattern::parse("/static/*rest");
+        let params = pattern.matches(b"/static/css/app.css").unwrap();
+        assert_eq!(params.get("rest"), Some("css/app.css"));
+        assert_eq!(pattern.matches(b"/static/").unwrap().get("rest"), Some(""));
+        assert!(pattern.matches(b"/static").is_none());
+        assert!(pattern.matches(b"/other/app.css").is_none());
+
+        let pattern = Pattern::parse("/");
+        assert!(pattern.matches(b"/").unwrap().is_empty());
+        assert!(pattern.matches(b"/a").is_none());
+    }
+
+    #[test]
+    #[should_panic = "is not the last segment"]
+    fn rest_not_last() {
+        let _ = Pattern::parse("/*rest/more");
+    }
+
+    #[test]
+    fn methods() {
+        let routes = Routes::new()
+            .get("/items/:id", |_: &Request, params: &Params| {
+                Response::builder(Code::Ok)
+                    .body(params.get("id").unwrap_or_default().to_string())
+                    .finish()
+            })
+            .post("/items/:id", |_: &Request, _: &Params| {
+                Response::new(Code::Created)
+            })
+            .delete("/items/:id", |_: &Request, _: &Params| {
+                Response::new(Code::NoContent)
+            });
+
+        let res = routes.handle(&request(Method::Get, "/items/7"));
+        assert_eq!(body(&res), b"7");
+        let res = routes.handle(&request(Method::Head, "/items/7"));
+        assert_eq!(res.code, Code::Ok);
+        let res = routes.handle(&request(Method::Post, "/items/7"));
+        assert_eq!(res.code, Code::Created);
+
+        let res = routes.handle(&request(Method::Put, "/items/7"));
+        assert_eq!(res.code, Code::MethodNotAllowed);
+        assert_eq!(
+            res.headers.get_single(b"allow"),
+            Some(&b"GET, POST, DELETE, HEAD"[..])
+        );
+
+        let res = routes.handle(&request(Method::Put, "/other"));
+        assert_eq!(res.code, Code::NotFound);
+    }
+
+    #[test]
+    fn mount() {
+        let routes = Routes::new()
+            .get("/api/status", |_: &Request, _: &Params| {
+                Response::new(Code::NoContent)
+            })
+            .mount("/static/", echo_path);
+
+        let res = routes.handle(&request(Method::Get, "/static/js/app.js"));
+        assert_eq!(body(&res), b"/js/app.js");
+        let res = routes.handle(&request(Method::Post, "/static"));
+        assert_eq!(body(&res), b"/");
+        let res = routes.handle(&request(Method::Get, "/static/?v=1"));
+        assert_eq!(body(&res), b"/");
+
+        let res = routes.handle(&request(Method::Get, "/staticfile"));
+        assert_eq!(res.code, Code::NotFound);
+        let res = routes.handle(&request(Method::Get, "/api/status"));
+        assert_eq!(res.code, Code::NoContent);
+    }
+}
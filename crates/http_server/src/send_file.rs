@@ -0,0 +1,41 @@
+use std::fs::File;
+use std::io;
+use std::net::TcpStream;
+use std::os::fd::AsRawFd;
+
+// The largest amount of data Linux transfers in a single `sendfile` call.
+const MAX_SEND: usize = 0x7fff_f000;
+
+// Sends `len` bytes of the file starting at `offset` without copying them
+// through user space.
+pub fn send_file(stream: &mut TcpStream, file: &File, offset: u64, len: u64) -> io::Result<()> {
+    let mut offset = libc::off_t::try_from(offset)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file offset too large"))?;
+    let mut remaining = len;
+
+    while remaining > 0 {
+        let count = usize::try_from(remaining).map_or(MAX_SEND, |r| r.min(MAX_SEND));
+        // Safety: both descriptors are valid for the duration of the call and
+        // `offset` points to a live variable.
+        let sent =
+            unsafe { libc::sendfile(stream.as_raw_fd(), file.as_raw_fd(), &raw mut offset, count) };
+
+        match sent {
+            0 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "file is shorter than expected",
+                ))
+            }
+            sent if sent < 0 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            sent => remaining -= sent.unsigned_abs() as u64,
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,495 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use http_lib::request::parser::{DEFAULT_MAX_BODY_LEN, DEFAULT_MAX_HEAD_LEN};
+use log::{info, warn};
+use rustls::ServerConfig;
+
+use crate::handler::Handler;
+use crate::observer::Observer;
+use crate::worker_pool::WorkerPool;
+
+/// Limits applied to connections and the requests read from them.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Idle timeout of persistent connections, zero disables them.
+    pub keep_alive_timeout: Duration,
+    /// Time given to a client to send a whole request, counted from its first
+    /// byte.
+    pub request_timeout: Duration,
+    /// Time a client may take to read some of a response before the
+    /// connection is closed, zero waits forever.
+    pub write_timeout: Duration,
+    /// Requests served per connection.
+    pub max_requests: usize,
+    pub workers: usize,
+    /// Connections open at the same time, the rest is rejected with
+    /// `503 Service Unavailable`.
+    pub max_connections: usize,
+    pub max_head_len: usize,
+    /// Largest request body accepted, unless the handler allows another size
+    /// for a request, see [`Handler::max_body_len`].
+    pub max_body_len: usize,
+    /// How long open connections are given to finish after a shutdown.
+    pub drain_timeout: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            keep_alive_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(10),
+            max_requests: 100,
+            workers: std::thread::available_parallelism().map_or(1, usize::from),
+            max_connections: 256,
+            max_head_len: DEFAULT_MAX_HEAD_LEN,
+            max_body_len: DEFAULT_MAX_BODY_LEN,
+            drain_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// How the server stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shutdown {
+    /// All connections were closed within the drain timeout.
+    Drained,
+    /// Some connections were still open when the drain timeout expired.
+    TimedOut,
+}
+
+/// Stops a running server, can be sent to other threads.
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle {
+    requested: Arc<AtomicBool>,
+    // where to connect to wake up the accept loop
+    wake_addr: SocketAddr,
+}
+
+impl ShutdownHandle {
+    /// Makes `Server::run` stop accepting connections and drain the open ones.
+    pub fn shutdown(&self) {
+        if self.requested.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        if let Err(err) = TcpStream::connect_timeout(&self.wake_addr, Duration::from_secs(1)) {
+            warn!("Failed to wake up the listener {err}");
+        }
+    }
+
+    #[must_use]
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Acquire)
+    }
+}
+
+/// A bound listener, which dispatches accepted connections to a pool of
+/// workers.
+pub struct Server {
+    listener: TcpListener,
+    pool: WorkerPool,
+    is_tls: bool,
+    shutdown: ShutdownHandle,
+    drain_timeout: Duration,
+}
+
+impl Server {
+    pub fn builder<H: Handler>(handler: H) -> Builder<H> {
+        Builder::new(handler)
+    }
+
+    /// Useful when bound to port 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    #[must_use]
+    pub fn workers(&self) -> usize {
+        self.pool.workers()
+    }
+
+    #[must_use]
+    pub fn is_tls(&self) -> bool {
+        self.is_tls
+    }
+
+    #[must_use]
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Accepts connections until a shutdown is requested, then waits for the
+    /// open ones to finish. Responses sent in the meantime close persistent
+    /// connections.
+    pub fn run(self) -> io::Result<Shutdown> {
+        for stream in self.listener.incoming() {
+            if self.shutdown.is_requested() {
+                break;
+            }
+
+            match stream {
+                Ok(stream) => self.pool.dispatch(stream),
+                Err(err) => warn!("Connection failed {err}"),
+            }
+        }
+
+        drop(self.listener);
+        info!("Stopped accepting connections, draining");
+        if self.pool.drain(self.drain_timeout) {
+            Ok(Shutdown::Drained)
+        } else {
+            Ok(Shutdown::TimedOut)
+        }
+    }
+}
+
+pub struct Builder<H> {
+    handler: H,
+    address: SocketAddr,
+    limits: Limits,
+    tls: Option<Arc<ServerConfig>>,
+    observers: Vec<Box<dyn Observer>>,
+}
+
+impl<H: Handler> Builder<H> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler,
+            address: (Ipv4Addr::LOCALHOST, 8000).into(),
+            limits: Limits::default(),
+            tls: None,
+            observers: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn bind(mut self, address: impl Into<SocketAddr>) -> Self {
+        self.address = address.into();
+        self
+    }
+
+    #[must_use]
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    #[must_use]
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.limits.workers = workers;
+        self
+    }
+
+    #[must_use]
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.keep_alive_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.request_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.write_timeout = timeout;
+        self
+    }
+
+    #[must_use]
+    pub fn max_requests(mut self, max_requests: usize) -> Self {
+        self.limits.max_requests = max_requests;
+        self
+    }
+
+    #[must_use]
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.limits.max_connections = max_connections;
+        self
+    }
+
+    #[must_use]
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.drain_timeout = timeout;
+        self
+    }
+
+    /// Serves HTTPS, see `tls::server_config`.
+    #[must_use]
+    pub fn tls(mut self, tls: Option<Arc<ServerConfig>>) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Notifies the observer about every response sent.
+    #[must_use]
+    pub fn observer(mut self, observer: impl Observer) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Binds the listener and starts the workers.
+    pub fn build(self) -> io::Result<Server> {
+        let listener = TcpListener::bind(self.address)?;
+        let mut wake_addr = listener.local_addr()?;
+        if wake_addr.ip().is_unspecified() {
+            wake_addr.set_ip(match wake_addr.ip() {
+                IpAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                IpAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+
+        let handler = Arc::new(self.handler);
+        let observers = Arc::from(self.observers);
+        let pool = WorkerPool::new(&handler, &self.limits, self.tls.as_ref(), &observers);
+        Ok(Server {
+            listener,
+            pool,
+            is_tls: self.tls.is_some(),
+            shutdown: ShutdownHandle {
+                requested: Arc::new(AtomicBool::new(false)),
+                wake_addr,
+            },
+            drain_timeout: self.limits.drain_timeout,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::sync::{mpsc, Mutex};
+    use std::thread::{self, JoinHandle};
+    use std::time::Instant;
+
+    use http_lib::response::{Body, Code};
+    use http_lib::{HeaderName, Request, Response};
+
+    use super::*;
+
+    // A server on an ephemeral port, running on its own thread.
+    pub struct Running {
+        pub addr: SocketAddr,
+        pub shutdown: ShutdownHandle,
+        thread: JoinHandle<io::Result<Shutdown>>,
+    }
+
+    impl Running {
+        pub fn start<H: Handler>(builder: Builder<H>) -> Self {
+            let server = builder.bind((Ipv4Addr::LOCALHOST, 0)).build().unwrap();
+            Self {
+                addr: server.local_addr().unwrap(),
+                shutdown: server.shutdown_handle(),
+                thread: thread::spawn(move || server.run()),
+            }
+        }
+
+        pub fn connect(&self) -> Client {
+            Client::connect(self.addr)
+        }
+
+        // Waits for the server to stop on its own.
+        pub fn join(self) -> Shutdown {
+            self.thread.join().unwrap().unwrap()
+        }
+
+        pub fn stop(self) -> Shutdown {
+            self.shutdown.shutdown();
+            self.join()
+        }
+    }
+
+    // A connection reading responses framed by their `Content-Length`.
+    pub struct Client<S = TcpStream> {
+        reader: BufReader<S>,
+    }
+
+    impl Client {
+        pub fn connect(addr: SocketAddr) -> Self {
+            Self::new(connect(addr))
+        }
+    }
+
+    // Reads time out, so that a test fails instead of hanging.
+    pub fn connect(addr: SocketAddr) -> TcpStream {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+    }
+
+    impl<S: Read + Write> Client<S> {
+        pub fn new(stream: S) -> Self {
+            Self {
+                reader: BufReader::new(stream),
+            }
+        }
+
+        pub fn stream(&mut self) -> &mut S {
+            self.reader.get_mut()
+        }
+
+        pub fn send(&mut self, data: &str) {
+            self.reader.get_mut().write_all(data.as_bytes()).unwrap();
+        }
+
+        pub fn get(&mut self, path: &str) -> Response {
+            self.send(&format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+            self.response().expect("the connection was closed")
+        }
+
+        // Returns `None` if the connection was closed instead.
+        pub fn response(&mut self) -> Option<Response> {
+            let mut data = Vec::new();
+            while !data.ends_with(b"\r\n\r\n") {
+                if self.reader.read_until(b'\n', &mut data).ok()? == 0 {
+                    return None;
+                }
+            }
+
+            let len = String::from_utf8_lossy(&data)
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    let is_length = name.eq_ignore_ascii_case("Content-Length");
+                    is_length.then(|| value.trim().parse::<usize>().unwrap())
+                })
+                .unwrap_or(0);
+            let start = data.len();
+            data.resize(start + len, 0);
+            self.reader.read_exact(&mut data[start..]).ok()?;
+            Some(Response::from_bytes(&mut data.into()).unwrap())
+        }
+
+        // Reads everything until the server closes the connection.
+        pub fn read_to_end(&mut self) -> String {
+            let mut data = String::new();
+            self.reader.read_to_string(&mut data).unwrap();
+            data
+        }
+
+        // Whether the server closes the connection within the read timeout,
+        // without sending anything else.
+        pub fn is_closed(&mut self) -> bool {
+            matches!(self.reader.read(&mut [0]), Ok(0))
+        }
+    }
+
+    pub fn body(res: &Response) -> &str {
+        std::str::from_utf8(res.body.as_bytes().unwrap()).unwrap()
+    }
+
+    // All the values of a field, joined with commas.
+    pub fn header(res: &Response, name: &HeaderName) -> Option<String> {
+        let values = res.headers.get(name)?;
+        let values: Vec<_> = values.iter_slices().map(String::from_utf8_lossy).collect();
+        Some(values.join(", "))
+    }
+
+    // Responds with the path of the request.
+    pub fn echo(req: &Request) -> Response {
+        Response::builder(Code::Ok)
+            .body(req.uri.path().to_string())
+            .finish()
+    }
+
+    #[test]
+    fn serve() {
+        let server = Running::start(Server::builder(echo).workers(2));
+        let mut client = server.connect();
+        let res = client.get("/hello");
+        assert_eq!(res.code, Code::Ok);
+        assert_eq!(body(&res), "/hello");
+        assert_eq!(
+            header(&res, &HeaderName::CONNECTION).as_deref(),
+            Some("keep-alive")
+        );
+
+        assert_eq!(server.stop(), Shutdown::Drained);
+        // open connections are closed while draining
+        assert!(client.is_closed());
+    }
+
+    // Blocks every request until it is released, tells when one has arrived.
+    fn blocking() -> (impl Handler, mpsc::Receiver<()>, mpsc::Sender<()>) {
+        let (arrived, arrivals) = mpsc::channel();
+        let (release, released) = mpsc::channel();
+        let released = Mutex::new(released);
+        let handler = move |req: &Request| {
+            arrived.send(()).unwrap();
+            released.lock().unwrap().recv().unwrap();
+            echo(req)
+        };
+        (handler, arrivals, release)
+    }
+
+    #[test]
+    fn drain() {
+        let (handler, arrivals, release) = blocking();
+        let server = Running::start(Server::builder(handler));
+        let mut client = server.connect();
+        client.send("GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        arrivals.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // the listener is closed while the request is still being handled
+        server.shutdown.shutdown();
+        let refused = (0..100).any(|_| {
+            thread::sleep(Duration::from_millis(20));
+            TcpStream::connect(server.addr).is_err()
+        });
+        assert!(refused);
+
+        release.send(()).unwrap();
+        let res = client.response().unwrap();
+        assert_eq!(body(&res), "/slow");
+        assert_eq!(
+            header(&res, &HeaderName::CONNECTION).as_deref(),
+            Some("close")
+        );
+        assert!(client.is_closed());
+        assert_eq!(server.join(), Shutdown::Drained);
+    }
+
+    #[test]
+    fn drain_timeout() {
+        let (handler, arrivals, release) = blocking();
+        let server =
+            Running::start(Server::builder(handler).drain_timeout(Duration::from_millis(200)));
+        let mut client = server.connect();
+        client.send("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        arrivals.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        assert_eq!(server.stop(), Shutdown::TimedOut);
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn drain_stalled_client() {
+        let server = Running::start(
+            Server::builder(|_: &Request| {
+                Response::builder(Code::Ok)
+                    .body_of_type(Body::reader(io::repeat(b'a')), "text/plain".into())
+                    .finish()
+            })
+            .write_timeout(Duration::from_millis(200)),
+        );
+
+        // never reads the response, the drain only waits for the write to
+        // time out
+        let mut stalled = server.connect();
+        stalled.send("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        thread::sleep(Duration::from_millis(100));
+
+        let started = Instant::now();
+        assert_eq!(server.stop(), Shutdown::Drained);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,100 +1,675 @@
-use std::io::{self, Read as _, Write as _};
-use std::net::TcpStream;
+use std::io::{self, Read as _, Write};
+use std::net::{self, SocketAddr};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-use bytes::BytesMut;
-use log::{error, warn};
+use log::{debug, error, warn};
 
-use crate::router::Router;
-use http_lib::{response::Code, Request, Response};
+use crate::connection::Connection;
+use crate::handler::Handler;
+use crate::observer::{Exchange, Observer, Rejection};
+use crate::server::Limits;
+use http_lib::request::{Parser, ParsingError, Status};
+use http_lib::response::{body, Body, Code};
+use http_lib::{HeaderName, Method, Request, Response, Version};
 
-const REQ_GROWTH_RATE: usize = 8192;
-const REQ_MAX_CAPACITY: usize = REQ_GROWTH_RATE * 2;
+const READ_CHUNK_SIZE: usize = 8192;
 
-// Buffers requests and sends responses.
-pub struct StreamHandler {
-    req_buffer: BytesMut,
+// Reads time out at least this often, so that idle connections are closed soon
+// after the server starts draining.
+const READ_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Time given to a rejected client for every read and write, and to close its
+// side once the response is sent.
+const REJECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Buffers requests and sends responses produced by the handler.
+pub struct StreamHandler<H> {
+    req_buffer: Vec<u8>,
+    req_pending: Range<usize>,
+    // bytes of the current request consumed by the parser so far
+    req_received: u64,
     res_buffer: Vec<u8>,
-    router: Router,
+    parser: Parser,
+    handler: Arc<H>,
+    keep_alive_timeout: Duration,
+    request_timeout: Duration,
+    write_timeout: Duration,
+    max_requests: usize,
+    draining: Arc<AtomicBool>,
+    observers: Arc<[Box<dyn Observer>]>,
 }
 
-impl StreamHandler {
-    pub fn new(router: Router) -> Self {
-        let mut req_buffer = BytesMut::new();
-        req_buffer.resize(REQ_GROWTH_RATE, 0);
-
+impl<H: Handler> StreamHandler<H> {
+    pub fn new(
+        handler: Arc<H>,
+        limits: &Limits,
+        draining: Arc<AtomicBool>,
+        observers: Arc<[Box<dyn Observer>]>,
+    ) -> Self {
         Self {
-            req_buffer,
+            req_buffer: vec![0; READ_CHUNK_SIZE],
+            req_pending: 0..0,
+            req_received: 0,
             res_buffer: Vec::with_capacity(8192),
-            router,
+            parser: Parser::with_limits(limits.max_head_len, limits.max_body_len),
+            handler,
+            keep_alive_timeout: limits.keep_alive_timeout,
+            request_timeout: limits.request_timeout,
+            write_timeout: limits.write_timeout,
+            max_requests: limits.max_requests.max(1),
+            draining,
+            observers,
         }
     }
 
-    pub fn dispatch(&mut self, stream: &mut TcpStream) {
-        let Self {
-            req_buffer,
-            res_buffer,
-            router,
-        } = self;
-
-        if let Err(err) = buffer_request(stream, req_buffer) {
-            warn!("An error occurred while buffering request {err}");
-            return;
+    pub fn dispatch(&mut self, stream: &mut Connection) {
+        let peer_addr = stream.tcp().peer_addr().ok();
+        for observer in self.observers.iter() {
+            observer.connection_opened(peer_addr);
         }
 
-        res_buffer.clear();
+        let _closed = ClosedOnDrop {
+            observers: Arc::clone(&self.observers),
+            peer_addr,
+        };
 
-        if req_buffer.len() >= REQ_MAX_CAPACITY {
-            warn!("Request too large, skipping!");
+        // taken once, so that a reload does not change the handler in the
+        // middle of a connection
+        let handler = Arc::clone(&self.handler).for_connection();
+        self.serve(stream, &*handler, peer_addr);
+    }
 
-            Response::new(Code::PayloadTooLarge).write_to_buffer(res_buffer);
-            if let Err(err) = stream.write_all(res_buffer) {
-                error!("Failed to send the response: {err}");
-            }
+    fn serve(
+        &mut self,
+        stream: &mut Connection,
+        handler: &dyn Handler,
+        peer_addr: Option<SocketAddr>,
+    ) {
+        self.parser.reset();
+        self.req_pending = 0..0;
 
+        // reads always time out, so that the connection is closed when the
+        // client stops sending or the server starts draining; a zero timeout
+        // is rejected by the socket
+        let read_timeout = Some(self.idle_timeout().min(READ_POLL_INTERVAL))
+            .filter(|timeout| !timeout.is_zero())
+            .unwrap_or(READ_POLL_INTERVAL);
+        if let Err(err) = stream.set_read_timeout(Some(read_timeout)) {
+            warn!("Failed to set read timeout {err}");
             return;
         }
 
-        let req = match Request::from_bytes(&mut req_buffer.clone().into()) {
-            Ok(req) => req,
-            Err(err) => {
-                warn!("Failed to parse request: {err}");
+        // a client which stops reading would otherwise keep the worker
+        // blocked on a write
+        let write_timeout = Some(self.write_timeout).filter(|timeout| !timeout.is_zero());
+        if let Err(err) = stream.set_write_timeout(write_timeout) {
+            warn!("Failed to set write timeout {err}");
+            return;
+        }
+
+        for served in 1..=self.max_requests {
+            let req = match self.read_request(stream, handler) {
+                Ok(Some(req)) => req,
+                // the client has closed the connection
+                Ok(None) => return,
+                Err(err) if is_timeout(&err) => {
+                    if self.parser.is_idle() {
+                        debug!("Closing idle connection");
+                    } else {
+                        warn!("Timed out while reading request");
+                        self.reject(
+                            stream,
+                            handler,
+                            Rejection {
+                                code: Code::RequestTimeout,
+                                error: None,
+                                peer_addr,
+                            },
+                        );
+                    }
+                    return;
+                }
+                Err(err) => {
+                    warn!("An error occurred while buffering request {err}");
+                    return;
+                }
+            };
 
-                Response::new(Code::InternalServerError).write_to_buffer(res_buffer);
-                if let Err(err) = stream.write_all(res_buffer) {
-                    error!("Failed to send the response: {err}");
+            let req = match req {
+                Ok(req) => req,
+                Err(err) => {
+                    warn!("Failed to parse request: {err}");
+                    self.reject(
+                        stream,
+                        handler,
+                        Rejection {
+                            code: code_for_error(err),
+                            error: Some(err),
+                            peer_addr,
+                        },
+                    );
+                    return;
                 }
+            };
 
+            let remaining = if self.keep_alive_timeout.is_zero() {
+                0
+            } else {
+                self.max_requests - served
+            };
+
+            if !self.respond(stream, handler, &req, remaining, peer_addr) {
                 return;
             }
-        };
+        }
+    }
+
+    // Reads from the stream until a whole request is parsed. Returns `None` if
+    // the connection was closed before that happened. The request has to
+    // arrive within the request timeout from its first byte, however often the
+    // client sends data.
+    fn read_request(
+        &mut self,
+        stream: &mut Connection,
+        handler: &dyn Handler,
+    ) -> io::Result<Option<Result<Request, ParsingError>>> {
+        let mut last_read = Instant::now();
+        let mut started = None;
+        self.req_received = 0;
+        loop {
+            if !self.req_pending.is_empty() {
+                let pending = &self.req_buffer[self.req_pending.clone()];
+                match self
+                    .parser
+                    .feed_with_limit(pending, |head| handler.max_body_len(head))
+                {
+                    Ok(Status::Complete { request, consumed }) => {
+                        self.req_pending.start += consumed;
+                        self.req_received += consumed as u64;
+                        return Ok(Some(Ok(*request)));
+                    }
+                    Ok(Status::Partial) => {
+                        self.req_received += pending.len() as u64;
+                        self.req_pending = 0..0;
+                        started.get_or_insert_with(Instant::now);
+                    }
+                    Err(err) => return Ok(Some(Err(err))),
+                }
+            }
+
+            if started.is_some_and(|started| started.elapsed() >= self.request_timeout) {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+
+            let read = match stream.read(&mut self.req_buffer) {
+                Ok(read) => read,
+                Err(err) if is_timeout(&err) && !self.stop_waiting(last_read) => continue,
+                Err(err) => return Err(err),
+            };
+
+            if read == 0 {
+                return Ok(None);
+            }
+
+            last_read = Instant::now();
+            self.req_pending = 0..read;
+        }
+    }
+
+    // Whether to give up on reading after a read has timed out. Connections
+    // between requests are closed right away while draining.
+    fn stop_waiting(&self, last_read: Instant) -> bool {
+        let is_draining = self.draining.load(Ordering::Acquire);
+        (is_draining && self.parser.is_idle()) || last_read.elapsed() >= self.idle_timeout()
+    }
+
+    // How long the client may stay silent. Without persistent connections
+    // only the request timeout applies.
+    fn idle_timeout(&self) -> Duration {
+        if self.keep_alive_timeout.is_zero() {
+            self.request_timeout
+        } else {
+            self.keep_alive_timeout
+        }
+    }
 
-        router.handle(&req).write_to_buffer(res_buffer);
-        if let Err(err) = stream.write_all(res_buffer) {
+    // Responds to the request, returns whether the connection should be kept
+    // open.
+    fn respond(
+        &mut self,
+        stream: &mut Connection,
+        handler: &dyn Handler,
+        req: &Request,
+        remaining: usize,
+        peer_addr: Option<SocketAddr>,
+    ) -> bool {
+        let received = SystemTime::now();
+        let started = Instant::now();
+        let mut keep_alive = (remaining > 0 && wants_keep_alive(req)).then_some(KeepAlive {
+            timeout: self.keep_alive_timeout,
+            remaining,
+        });
+
+        // the connection is closed after a panic, as the handler may be left
+        // in any state
+        let mut res =
+            panic::catch_unwind(AssertUnwindSafe(|| handler.handle(req))).unwrap_or_else(|_| {
+                error!("The handler has panicked");
+                keep_alive = None;
+                Response::new(Code::InternalServerError)
+            });
+
+        // the server may have started draining while the handler was running
+        if self.draining.load(Ordering::Acquire) {
+            keep_alive = None;
+        }
+
+        // HTTP/1.0 clients do not know the chunked transfer coding, bodies of
+        // unknown length are delimited by closing the connection instead
+        let supports_chunked = supports_chunked(req.version);
+        if !supports_chunked && res.body.len().is_none() {
+            keep_alive = None;
+        }
+        prepare_response(&mut res, keep_alive, supports_chunked);
+
+        let head_only = req.method == Method::Head;
+        let mut sent = Sent::default();
+        let result = write_response(stream, &mut res, &mut self.res_buffer, head_only, &mut sent);
+
+        if !self.observers.is_empty() {
+            let exchange = Exchange {
+                req,
+                res: &res,
+                peer_addr,
+                received,
+                duration: started.elapsed(),
+                bytes_received: self.req_received,
+                bytes_sent: sent.head + sent.body,
+                body_bytes_sent: sent.body,
+            };
+
+            for observer in self.observers.iter() {
+                observer.response_sent(&exchange);
+            }
+        }
+
+        if let Err(err) = result {
+            error!("Failed to send the response: {err}");
+            return false;
+        }
+
+        keep_alive.is_some()
+    }
+
+    fn reject(&mut self, stream: &mut Connection, handler: &dyn Handler, rejection: Rejection) {
+        for observer in self.observers.iter() {
+            observer.request_rejected(&rejection);
+        }
+
+        let mut res = handler.error_response(rejection.code);
+        prepare_response(&mut res, None, true);
+
+        if let Err(err) = res
+            .write_to(stream, &mut self.res_buffer)
+            .and_then(|()| stream.flush())
+        {
             error!("Failed to send the response: {err}");
         }
     }
 }
 
-fn buffer_request(stream: &mut TcpStream, req_buffer: &mut BytesMut) -> io::Result<()> {
-    let mut writable_from = 0;
-    req_buffer.resize(req_buffer.capacity(), 0);
+// Tells the observers that a connection was closed, even if serving it has
+// panicked.
+struct ClosedOnDrop {
+    observers: Arc<[Box<dyn Observer>]>,
+    peer_addr: Option<SocketAddr>,
+}
 
-    loop {
-        let space = req_buffer.len() - writable_from;
-        let read = match stream.read(&mut req_buffer[writable_from..]) {
-            Ok(read) => read,
-            Err(err) => {
-                req_buffer.truncate(writable_from);
-                return Err(err);
-            }
-        };
-        let read_more = read == space && req_buffer.len() < REQ_MAX_CAPACITY;
-        if read_more {
-            writable_from = req_buffer.len();
-            req_buffer.resize(req_buffer.len() + REQ_GROWTH_RATE, 0);
-        } else {
-            req_buffer.truncate(writable_from + read);
+impl Drop for ClosedOnDrop {
+    fn drop(&mut self) {
+        for observer in self.observers.iter() {
+            observer.connection_closed(self.peer_addr);
+        }
+    }
+}
+
+// Sends a response and closes the connection without reading the request.
+// Whatever the client sends is discarded until it closes its side, as unread
+// data would make the connection reset, which may lose the response.
+pub fn reject(stream: &mut Connection, code: Code) {
+    let timeout = Some(REJECT_TIMEOUT);
+    if let Err(err) = stream
+        .set_read_timeout(timeout)
+        .and_then(|()| stream.set_write_timeout(timeout))
+    {
+        warn!("Failed to set timeouts {err}");
+        return;
+    }
+
+    let mut res = Response::new(code);
+    prepare_response(&mut res, None, true);
+    if let Err(err) = res
+        .write_to(stream, &mut Vec::new())
+        .and_then(|()| stream.flush())
+    {
+        error!("Failed to send the response: {err}");
+        return;
+    }
+
+    stream.close();
+    let mut tcp = stream.tcp();
+    if tcp.shutdown(net::Shutdown::Write).is_err() {
+        return;
+    }
+
+    let deadline = Instant::now() + REJECT_TIMEOUT;
+    let mut buffer = [0; 1024];
+    while Instant::now() < deadline {
+        if matches!(tcp.read(&mut buffer), Ok(0) | Err(_)) {
+            return;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct KeepAlive {
+    timeout: Duration,
+    remaining: usize,
+}
+
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+// Counts the bytes written through it.
+struct Counting<'a, W> {
+    inner: &'a mut W,
+    count: &'a mut u64,
+}
+
+impl<W: Write> Write for Counting<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        *self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Bytes of a response written to the connection.
+#[derive(Default)]
+struct Sent {
+    head: u64,
+    // including the framing of chunked bodies
+    body: u64,
+}
+
+// Writes the response in fixed-size chunks, sending files directly from the
+// kernel where possible. Files sent over TLS have to be encrypted in userspace.
+// Responses to `HEAD` requests keep the framing headers of the body, but the
+// body itself is never sent. The number of bytes written is added to `sent`.
+fn write_response(
+    stream: &mut Connection,
+    res: &mut Response,
+    buffer: &mut Vec<u8>,
+    head_only: bool,
+    sent: &mut Sent,
+) -> io::Result<()> {
+    buffer.clear();
+    res.write_head_to_buffer(buffer);
+    let head_len = buffer.len() as u64;
+    if head_only {
+        stream.write_all(buffer)?;
+        sent.head += head_len;
+        return stream.flush();
+    }
+
+    #[cfg(target_os = "linux")]
+    if let (Connection::Plain(stream), Body::File { file, offset, len }) = (&mut *stream, &res.body)
+    {
+        if !res.is_chunked() {
+            stream.write_all(buffer)?;
+            sent.head += head_len;
+            crate::send_file::send_file(stream, file, *offset, *len)?;
+            sent.body += len;
             return Ok(());
         }
     }
+
+    // small bodies are sent along with the head
+    let small_body = res
+        .body
+        .as_bytes()
+        .filter(|body| body.len() <= body::CHUNK_SIZE && !res.is_chunked());
+    if let Some(body) = small_body {
+        buffer.extend_from_slice(body);
+        stream.write_all(buffer)?;
+        sent.head += head_len;
+        sent.body += body.len() as u64;
+        return stream.flush();
+    }
+
+    stream.write_all(buffer)?;
+    sent.head += head_len;
+    let mut counting = Counting {
+        inner: stream,
+        count: &mut sent.body,
+    };
+    res.write_body_to(&mut counting, buffer)?;
+    stream.flush()
+}
+
+fn code_for_error(err: ParsingError) -> Code {
+    match err {
+        ParsingError::HeadTooLong => Code::RequestHeaderFieldsTooLarge,
+        ParsingError::BodyTooLong => Code::PayloadTooLarge,
+        ParsingError::MethodUnsupported | ParsingError::UnsupportedTransferCoding => {
+            Code::NotImplemented
+        }
+        _ => Code::BadRequest,
+    }
+}
+
+fn supports_chunked(version: Version) -> bool {
+    let Version(major, minor) = version;
+    (major, minor) >= (1, 1)
+}
+
+fn wants_keep_alive(req: &Request) -> bool {
+    let has_option = |option: &[u8]| {
+        req.headers
+            .get(&HeaderName::CONNECTION)
+            .is_some_and(|vs| vs.iter_slices().any(|v| v.eq_ignore_ascii_case(option)))
+    };
+
+    let Version(major, minor) = req.version;
+    if (major, minor) >= (1, 1) {
+        !has_option(b"close")
+    } else {
+        has_option(b"keep-alive")
+    }
+}
+
+// Adds connection management headers and makes sure the end of the body can
+// be determined without closing the connection, unless the client does not
+// support the chunked transfer coding.
+fn prepare_response(res: &mut Response, keep_alive: Option<KeepAlive>, supports_chunked: bool) {
+    if !supports_chunked && res.is_chunked() {
+        res.headers.remove(&HeaderName::TRANSFER_ENCODING);
+    }
+
+    if !res.headers.contains_name(&HeaderName::CONTENT_LENGTH)
+        && !res.is_chunked()
+        && !matches!(res.code, Code::NoContent | Code::NotModified)
+    {
+        if let Some(len) = res.body.len() {
+            res.add_header_value(HeaderName::CONTENT_LENGTH, len.to_string().into());
+        } else if supports_chunked {
+            res.set_chunked();
+        }
+    }
+
+    if let Some(KeepAlive { timeout, remaining }) = keep_alive {
+        let timeout = timeout.as_secs();
+        res.add_header_value(HeaderName::CONNECTION, "keep-alive".into());
+        res.add_header_value(HeaderName::KEEP_ALIVE, format!("timeout={timeout}").into());
+        res.add_header_value(HeaderName::KEEP_ALIVE, format!("max={remaining}").into());
+    } else {
+        res.add_header_value(HeaderName::CONNECTION, "close".into());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::server::test::{body, echo, header, Running};
+    use crate::Server;
+
+    use super::*;
+
+    #[test]
+    fn keep_alive() {
+        let server = Running::start(Server::builder(echo));
+        let mut client = server.connect();
+        for path in ["/a", "/b", "/c"] {
+            let res = client.get(path);
+            assert_eq!(body(&res), path);
+            assert_eq!(
+                header(&res, &HeaderName::CONNECTION).as_deref(),
+                Some("keep-alive")
+            );
+        }
+
+        let res = client.get("/d");
+        assert_eq!(
+            header(&res, &HeaderName::KEEP_ALIVE).as_deref(),
+            Some("timeout=5, max=96")
+        );
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let server =
+            Running::start(Server::builder(echo).keep_alive_timeout(Duration::from_millis(200)));
+        let mut client = server.connect();
+        assert_eq!(body(&client.get("/")), "/");
+        let idle = Instant::now();
+        assert!(client.is_closed());
+        assert!(idle.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn max_requests() {
+        let server = Running::start(Server::builder(echo).max_requests(2));
+        let mut client = server.connect();
+        let res = client.get("/a");
+        assert_eq!(
+            header(&res, &HeaderName::KEEP_ALIVE).as_deref(),
+            Some("timeout=5, max=1")
+        );
+
+        let res = client.get("/b");
+        assert_eq!(body(&res), "/b");
+        assert_eq!(
+            header(&res, &HeaderName::CONNECTION).as_deref(),
+            Some("close")
+        );
+        assert!(client.is_closed());
+    }
+
+    #[test]
+    fn pipelining() {
+        let server = Running::start(Server::builder(echo));
+        let mut client = server.connect();
+        client.send(
+            "GET /a HTTP/1.1\r\nHost: localhost\r\n\r\n\
+            GET /b HTTP/1.1\r\nHost: localhost\r\n\r\n\
+            GET /c HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        );
+
+        for path in ["/a", "/b", "/c"] {
+            assert_eq!(body(&client.response().unwrap()), path);
+        }
+        assert!(client.is_closed());
+    }
+
+    #[test]
+    fn http_1_0() {
+        let server = Running::start(Server::builder(|req: &Request| match req.uri.path() {
+            "/stream" => Response::builder(Code::Ok)
+                .body_of_type(Body::reader(&b"streamed"[..]), "text/plain".into())
+                .finish(),
+            _ => echo(req),
+        }));
+
+        // closed after every response, unless asked otherwise
+        let mut client = server.connect();
+        client.send("GET /a HTTP/1.0\r\n\r\n");
+        let res = client.response().unwrap();
+        assert_eq!(body(&res), "/a");
+        assert_eq!(
+            header(&res, &HeaderName::CONNECTION).as_deref(),
+            Some("close")
+        );
+        assert!(client.is_closed());
+
+        let mut client = server.connect();
+        client.send("GET /a HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+        let res = client.response().unwrap();
+        assert_eq!(
+            header(&res, &HeaderName::CONNECTION).as_deref(),
+            Some("keep-alive")
+        );
+
+        // bodies of unknown length are delimited by closing the connection
+        client.send("GET /stream HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+        let res = client.read_to_end();
+        assert!(res.contains("Connection: close\r\n"));
+        assert!(!res.contains("Transfer-Encoding"));
+        assert!(res.ends_with("\r\n\r\nstreamed"));
+    }
+
+    #[test]
+    fn write_timeout() {
+        let server = Running::start(
+            Server::builder(|req: &Request| match req.uri.path() {
+                "/endless" => Response::builder(Code::Ok)
+                    .body_of_type(Body::reader(io::repeat(b'a')), "text/plain".into())
+                    .finish(),
+                _ => echo(req),
+            })
+            .workers(1)
+            .write_timeout(Duration::from_millis(200)),
+        );
+
+        // never reads the response
+        let mut stalled = server.connect();
+        stalled.send("GET /endless HTTP/1.1\r\nHost: localhost\r\n\r\n");
+
+        // the only worker is freed once the write times out
+        let mut client = server.connect();
+        assert_eq!(body(&client.get("/")), "/");
+    }
+
+    #[test]
+    fn transfer_coding_with_content_length() {
+        let server = Running::start(Server::builder(echo));
+
+        let mut client = server.connect();
+        client.send(
+            "POST / HTTP/1.1\r\nHost: localhost\r\n\
+             Transfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n",
+        );
+        assert_eq!(client.response().unwrap().code, Code::BadRequest);
+        assert!(client.is_closed());
+    }
 }
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use log::{debug, info};
+use rustls::crypto::ring::{self as provider, sign::any_supported_type};
+use rustls::pki_types::pem::{self, PemObject};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+
+use crate::config::{Config, SniCertificate};
+
+// Names the self-signed certificate is valid for.
+const SELF_SIGNED_NAMES: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+
+#[derive(Debug)]
+pub enum Error {
+    MissingKey,
+    MissingCertificate,
+    Pem(String, pem::Error),
+    NoCertificates(String),
+    Rustls(rustls::Error),
+    SelfSigned(rcgen::Error),
+}
+
+impl From<rustls::Error> for Error {
+    fn from(err: rustls::Error) -> Self {
+        Self::Rustls(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingKey => write!(f, "--tls-cert requires --tls-key"),
+            Self::MissingCertificate => write!(f, "--tls-key requires --tls-cert"),
+            Self::Pem(path, err) => write!(f, "failed to read {path}: {err}"),
+            Self::NoCertificates(path) => write!(f, "no certificates found in {path}"),
+            Self::Rustls(err) => fmt::Display::fmt(err, f),
+            Self::SelfSigned(err) => write!(f, "failed to generate a certificate: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Picks the certificate based on the server name sent by the client, falling
+// back to the default one.
+#[derive(Debug)]
+struct CertResolver {
+    default: Option<Arc<CertifiedKey>>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let by_name = client_hello
+            .server_name()
+            .and_then(|name| self.by_name.get(&name.to_ascii_lowercase()));
+
+        by_name.or(self.default.as_ref()).cloned()
+    }
+}
+
+// Builds the rustls configuration, returns `None` if TLS is disabled.
+pub fn server_config(config: &Config) -> Result<Option<Arc<ServerConfig>>, Error> {
+    let default = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => Some(load_certified_key(cert, key)?),
+        (Some(_), None) => return Err(Error::MissingKey),
+        (None, Some(_)) => return Err(Error::MissingCertificate),
+        (None, None) if config.tls_self_signed => Some(self_signed()?),
+        (None, None) => None,
+    };
+
+    let by_name = config
+        .tls_sni
+        .iter()
+        .map(|SniCertificate { name, cert, key }| {
+            load_certified_key(cert, key).map(|key| (name.to_ascii_lowercase(), key))
+        })
+        .collect::<Result<HashMap<_, _>, _>>()?;
+
+    if default.is_none() && by_name.is_empty() {
+        return Ok(None);
+    }
+
+    let resolver = CertResolver { default, by_name };
+    let mut server_config =
+        ServerConfig::builder_with_provider(Arc::new(provider::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(resolver));
+
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Some(Arc::new(server_config)))
+}
+
+// Loads a PEM certificate chain and a PKCS#8, PKCS#1 or SEC1 private key.
+fn load_certified_key(cert_path: &str, key_path: &str) -> Result<Arc<CertifiedKey>, Error> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|err| Error::Pem(cert_path.to_string(), err))?;
+
+    if certs.is_empty() {
+        return Err(Error::NoCertificates(cert_path.to_string()));
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|err| Error::Pem(key_path.to_string(), err))?;
+
+    debug!("Loaded {} certificates from {cert_path}", certs.len());
+    Ok(Arc::new(CertifiedKey::new(
+        certs,
+        any_supported_type(&key)?,
+    )))
+}
+
+// Generates an ephemeral certificate for local development.
+fn self_signed() -> Result<Arc<CertifiedKey>, Error> {
+    let names = SELF_SIGNED_NAMES.map(String::from).to_vec();
+    let rcgen::CertifiedKey { cert, key_pair } =
+        rcgen::generate_simple_self_signed(names).map_err(Error::SelfSigned)?;
+
+    let key = PrivateKeyDer::from(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
+    info!(
+        "Generated a self-signed certificate for {}",
+        SELF_SIGNED_NAMES.join(", ")
+    );
+    Ok(Arc::new(CertifiedKey::new(
+        vec![cert.der().clone()],
+        any_supported_type(&key)?,
+    )))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::io::{self, Write as _};
+    use std::net::{SocketAddr, TcpStream};
+    use std::path::Path;
+
+    use http_lib::response::Code;
+
+    use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+    use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+    use rustls::pki_types::{ServerName, UnixTime};
+    use rustls::{
+        ClientConfig, ClientConnection, DigitallySignedStruct, SignatureScheme, StreamOwned,
+    };
+
+    use super::*;
+    use crate::server::test::{self as server, echo, Client, Running};
+    use crate::server::Server;
+
+    pub type TlsClient = Client<StreamOwned<ClientConnection, TcpStream>>;
+
+    // Accepts any certificate, the tests check which one was sent.
+    #[derive(Debug)]
+    struct AcceptAny(Arc<CryptoProvider>);
+
+    impl ServerCertVerifier for AcceptAny {
+        fn verify_server_cert(
+            &self,
+            _: &CertificateDer<'_>,
+            _: &[CertificateDer<'_>],
+            _: &ServerName<'_>,
+            _: &[u8],
+            _: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            Ok(ServerCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            verify_tls12_signature(
+                message,
+                cert,
+                dss,
+                &self.0.signature_verification_algorithms,
+            )
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            verify_tls13_signature(
+                message,
+                cert,
+                dss,
+                &self.0.signature_verification_algorithms,
+            )
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.0.signature_verification_algorithms.supported_schemes()
+        }
+    }
+
+    pub fn self_signed() -> Arc<ServerConfig> {
+        let config = Config {
+            tls_self_signed: true,
+            ..Config::default()
+        };
+        server_config(&config).unwrap().unwrap()
+    }
+
+    pub fn connect(addr: SocketAddr, server_name: &str) -> TlsClient {
+        let provider = Arc::new(provider::default_provider());
+        let config = ClientConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAny(provider)))
+            .with_no_client_auth();
+        let server_name = ServerName::try_from(server_name.to_string()).unwrap();
+        let conn = ClientConnection::new(Arc::new(config), server_name).unwrap();
+        Client::new(StreamOwned::new(conn, server::connect(addr)))
+    }
+
+    // Writes a certificate for `name` and its key to `dir`, returns the paths
+    // and the certificate.
+    fn generate(dir: &Path, name: &str) -> (String, String, CertificateDer<'static>) {
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let cert_path = dir.join(format!("{name}.pem"));
+        let key_path = dir.join(format!("{name}.key"));
+        std::fs::write(&cert_path, cert.pem()).unwrap();
+        std::fs::write(&key_path, key_pair.serialize_pem()).unwrap();
+        (
+            cert_path.to_str().unwrap().to_string(),
+            key_path.to_str().unwrap().to_string(),
+            cert.der().clone(),
+        )
+    }
+
+    // The certificate sent by the server, after a request completed the
+    // handshake.
+    fn peer_certificate(client: &mut TlsClient) -> CertificateDer<'static> {
+        assert_eq!(client.get("/").code, Code::Ok);
+        let certs = client.stream().conn.peer_certificates().unwrap();
+        assert_eq!(certs.len(), 1);
+        certs[0].clone().into_owned()
+    }
+
+    #[test]
+    fn disabled() {
+        assert!(server_config(&Config::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn self_signed_config() {
+        let config = self_signed();
+        assert_eq!(config.alpn_protocols, [b"http/1.1"]);
+
+        let server = Running::start(Server::builder(echo).tls(Some(config)));
+        for name in ["localhost", "127.0.0.1"] {
+            peer_certificate(&mut connect(server.addr, name));
+        }
+    }
+
+    #[test]
+    fn certificate_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key, der) = generate(dir.path(), "example.com");
+        let config = Config {
+            tls_cert: Some(cert.clone()),
+            tls_key: Some(key.clone()),
+            ..Config::default()
+        };
+
+        let server = Running::start(Server::builder(echo).tls(server_config(&config).unwrap()));
+        let mut client = connect(server.addr, "example.com");
+        assert_eq!(peer_certificate(&mut client), der);
+
+        let error = |tls_cert: Option<&str>, tls_key: Option<&str>| {
+            let config = Config {
+                tls_cert: tls_cert.map(String::from),
+                tls_key: tls_key.map(String::from),
+                ..Config::default()
+            };
+            server_config(&config).unwrap_err()
+        };
+
+        assert!(matches!(error(Some(&cert), None), Error::MissingKey));
+        assert!(matches!(error(None, Some(&key)), Error::MissingCertificate));
+
+        let missing = dir.path().join("missing.pem");
+        let missing = missing.to_str().unwrap();
+        assert!(matches!(error(Some(missing), Some(&key)), Error::Pem(path, _) if path == missing));
+        assert!(
+            matches!(error(Some(&cert), Some(missing)), Error::Pem(path, _) if path == missing)
+        );
+        // the key is not a certificate
+        assert!(
+            matches!(error(Some(&key), Some(&key)), Error::NoCertificates(path) if path == key)
+        );
+    }
+
+    #[test]
+    fn sni() {
+        let dir = tempfile::tempdir().unwrap();
+        let (default_cert, default_key, default_der) = generate(dir.path(), "localhost");
+        let mut tls_sni = Vec::new();
+        let mut ders = Vec::new();
+        for name in ["a.example", "b.example"] {
+            let (cert, key, der) = generate(dir.path(), name);
+            tls_sni.push(
+                format!("{}={cert},{key}", name.to_uppercase())
+                    .parse()
+                    .unwrap(),
+            );
+            ders.push(der);
+        }
+
+        let config = Config {
+            tls_cert: Some(default_cert),
+            tls_key: Some(default_key),
+            tls_sni: tls_sni.clone(),
+            ..Config::default()
+        };
+        let server = Running::start(Server::builder(echo).tls(server_config(&config).unwrap()));
+        for (name, der) in [
+            ("a.example", &ders[0]),
+            ("b.example", &ders[1]),
+            ("B.Example", &ders[1]),
+            ("c.example", &default_der),
+            // IP addresses are not sent as server names
+            ("127.0.0.1", &default_der),
+        ] {
+            let mut client = connect(server.addr, name);
+            assert_eq!(&peer_certificate(&mut client), der, "{name}");
+        }
+
+        // without a default certificate, unknown names fail the handshake
+        let config = Config {
+            tls_sni,
+            ..Config::default()
+        };
+        let server = Running::start(Server::builder(echo).tls(server_config(&config).unwrap()));
+        assert_eq!(
+            peer_certificate(&mut connect(server.addr, "a.example")),
+            ders[0]
+        );
+
+        let mut client = connect(server.addr, "c.example");
+        let err = client
+            .stream()
+            .write_all(b"GET / HTTP/1.1\r\n")
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
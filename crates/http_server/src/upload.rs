@@ -0,0 +1,201 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use bytes::BytesMut;
+use http_lib::multipart::{Event, Limits, Parser, ParsingError};
+use http_lib::response::Code;
+use http_lib::{HeaderName, Request, Response};
+use log::{info, warn};
+
+// Files with the same name get a number appended, up to this many.
+const MAX_DUPLICATES: u32 = 100;
+
+// The body is handed to the parser in pieces of this size, so that it is not
+// copied as a whole.
+const FEED_LEN: usize = 64 * 1024;
+
+enum Error {
+    Parsing(ParsingError),
+    Truncated,
+    InvalidFileName,
+    Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Saves the files of a `multipart/form-data` request into `dir` and
+/// redirects to its listing. Fields which are not files are ignored, and none
+/// of the files is kept if any of them fails.
+pub fn receive(req: &Request, dir: &Path, max_file_len: usize) -> Response {
+    let content_type = req
+        .headers
+        .get_single(&HeaderName::CONTENT_TYPE)
+        .unwrap_or_default();
+    let limits = Limits {
+        max_part_len: max_file_len,
+        ..Limits::default()
+    };
+
+    let mut parser = match Parser::from_content_type(content_type, limits) {
+        Ok(parser) => parser,
+        Err(ParsingError::NotMultipart) => return Response::new(Code::UnsupportedMediaType),
+        Err(_) => return Response::new(Code::BadRequest),
+    };
+
+    let mut saved = Vec::new();
+    if let Err(err) = save_files(&mut parser, &req.body, dir, &mut saved) {
+        for path in &saved {
+            if let Err(err) = fs::remove_file(path) {
+                warn!("Failed to remove {}: {err}", path.display());
+            }
+        }
+
+        return match err {
+            Error::Parsing(ParsingError::PartTooLong) => Response::new(Code::PayloadTooLarge),
+            Error::Parsing(_) | Error::Truncated | Error::InvalidFileName => {
+                Response::new(Code::BadRequest)
+            }
+            Error::Io(err) => {
+                warn!("Failed to save an upload into {}: {err}", dir.display());
+                Response::new(Code::InternalServerError)
+            }
+        };
+    }
+
+    let mut body = String::new();
+    for path in &saved {
+        info!("Saved an upload to {}", path.display());
+        if let Some(name) = path.file_name() {
+            body.push_str(&name.to_string_lossy());
+            body.push('\n');
+        }
+    }
+
+    Response::builder(Code::SeeOther)
+        .add_header_value(HeaderName::LOCATION, req.uri.path().to_string().into())
+        .body(body)
+        .finish()
+}
+
+fn save_files(
+    parser: &mut Parser,
+    body: &[u8],
+    dir: &Path,
+    saved: &mut Vec<PathBuf>,
+) -> Result<(), Error> {
+    let mut buffer = BytesMut::with_capacity(FEED_LEN);
+    let mut file = None;
+    for piece in body.chunks(FEED_LEN) {
+        buffer.extend_from_slice(piece);
+        while let Some(event) = parser.next_event(&mut buffer).map_err(Error::Parsing)? {
+            match event {
+                Event::Part(part) => {
+                    file = match part.filename() {
+                        // sent for file inputs left empty
+                        None | Some("") => None,
+                        Some(name) => {
+                            let name = sanitize_file_name(name).ok_or(Error::InvalidFileName)?;
+                            let (path, created) = create_unique(dir, name)?;
+                            saved.push(path);
+                            Some(created)
+                        }
+                    };
+                }
+                Event::Data(data) => {
+                    if let Some(file) = &mut file {
+                        file.write_all(&data)?;
+                    }
+                }
+                Event::PartEnd => file = None,
+            }
+        }
+    }
+
+    if parser.is_finished() {
+        Ok(())
+    } else {
+        Err(Error::Truncated)
+    }
+}
+
+// Keeps only the last component of the name, as some clients send whole paths,
+// and refuses hidden files and names with control characters.
+fn sanitize_file_name(name: &str) -> Option<&str> {
+    let name = name.rsplit(['/', '\\']).next()?.trim();
+    let is_valid = !name.is_empty()
+        && name.len() <= 255
+        && !name.starts_with('.')
+        && !name.chars().any(char::is_control);
+
+    is_valid.then_some(name)
+}
+
+// Never overwrites existing files, a second `a.txt` is saved as `a-1.txt`.
+fn create_unique(dir: &Path, name: &str) -> io::Result<(PathBuf, File)> {
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name, ""),
+    };
+
+    for i in 0..MAX_DUPLICATES {
+        let path = if i == 0 {
+            dir.join(name)
+        } else {
+            dir.join(format!("{stem}-{i}{extension}"))
+        };
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "too many files with the same name",
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        assert_eq!(sanitize_file_name("a.txt"), Some("a.txt"));
+        assert_eq!(sanitize_file_name(" a b.txt "), Some("a b.txt"));
+        assert_eq!(sanitize_file_name("C:\\Users\\me\\a.txt"), Some("a.txt"));
+        assert_eq!(sanitize_file_name("../../etc/passwd"), Some("passwd"));
+        assert_eq!(sanitize_file_name("dir/"), None);
+        assert_eq!(sanitize_file_name(""), None);
+        assert_eq!(sanitize_file_name(".htaccess"), None);
+        assert_eq!(sanitize_file_name(".."), None);
+        assert_eq!(sanitize_file_name("a\nb.txt"), None);
+        assert_eq!(sanitize_file_name(&"a".repeat(256)), None);
+    }
+
+    #[test]
+    fn unique_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let created = |name: &str| {
+            let (path, _) = create_unique(dir.path(), name).unwrap();
+            path.file_name().unwrap().to_str().unwrap().to_string()
+        };
+
+        assert_eq!(created("a.txt"), "a.txt");
+        assert_eq!(created("a.txt"), "a-1.txt");
+        assert_eq!(created("a.txt"), "a-2.txt");
+        assert_eq!(created("a.tar.gz"), "a.tar.gz");
+        assert_eq!(created("a.tar.gz"), "a.tar-1.gz");
+        assert_eq!(created("README"), "README");
+        assert_eq!(created("README"), "README-1");
+        assert_eq!(created(".env"), ".env");
+        assert_eq!(created(".env"), ".env-1");
+    }
+}
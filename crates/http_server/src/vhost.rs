@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use http_lib::response::Code;
+use http_lib::{HeaderName, Request, Response};
+
+use crate::config::Config;
+use crate::error_pages::ErrorPages;
+use crate::handler::Handler;
+use crate::middleware::{HostValidation, SetHeaders, Stack};
+use crate::router::{Router, Site};
+
+/// Serves the site of the virtual host named in the `Host` field. Exact names
+/// and aliases take precedence over wildcards, e.g. `*.example.com`, which are
+/// tried from the longest.
+///
+/// Requests for the server's own address or name are served from the root
+/// directory, the rest by the default host if there is one, or answered with
+/// `421 Misdirected Request`.
+pub struct VirtualHosts {
+    names: HashMap<String, usize>,
+    // suffixes starting with a dot, e.g. `.example.com`
+    wildcards: Vec<(String, usize)>,
+    hosts: Vec<Stack<Router>>,
+    default: Option<usize>,
+    main_host: HostValidation,
+    main: Router,
+    error_pages: ErrorPages,
+}
+
+impl VirtualHosts {
+    #[must_use]
+    pub fn new(config: &Config) -> Self {
+        let mut names = HashMap::new();
+        let mut wildcards = Vec::new();
+        let mut hosts = Vec::with_capacity(config.hosts.len());
+        for (i, host) in config.hosts.iter().enumerate() {
+            for name in host.names() {
+                match name.strip_prefix('*') {
+                    Some(suffix) => wildcards.push((suffix.to_string(), i)),
+                    // the names are unique, which is checked by `Config::load`
+                    None => {
+                        names.insert(name.to_string(), i);
+                    }
+                }
+            }
+
+            let router = Router::for_site(Site::for_host(host, config));
+            hosts.push(Stack::new(router).with(SetHeaders::new(&host.headers)));
+        }
+
+        wildcards.sort_by_key(|(suffix, _)| std::cmp::Reverse(suffix.len()));
+        let default = config
+            .default_host
+            .as_ref()
+            .and_then(|name| names.get(&name.to_ascii_lowercase()).copied());
+
+        Self {
+            names,
+            wildcards,
+            hosts,
+            default,
+            main_host: HostValidation::new(config),
+            main: Router::new(config),
+            error_pages: ErrorPages::new(&config.error_pages),
+        }
+    }
+
+    // Returns the index of the virtual host named in the `Host` field.
+    fn find(&self, host: &[u8]) -> Option<usize> {
+        let name = host_name(host)?;
+        self.names.get(&name).copied().or_else(|| {
+            self.wildcards
+                .iter()
+                .find(|(suffix, _)| name.ends_with(suffix.as_str()))
+                .map(|&(_, i)| i)
+        })
+    }
+
+    // Returns the site serving the request, `None` if it is misdirected.
+    fn select(&self, req: &Request) -> Option<&dyn Handler> {
+        let host = req.headers.get_single(&HeaderName::HOST);
+        if let Some(index) = host.and_then(|host| self.find(host)) {
+            return Some(&self.hosts[index]);
+        }
+
+        if host.is_some_and(|host| self.main_host.validate_host(host)) {
+            return Some(&self.main);
+        }
+
+        self.default.map(|index| &self.hosts[index] as &dyn Handler)
+    }
+}
+
+impl Handler for VirtualHosts {
+    fn handle(&self, req: &Request) -> Response {
+        match self.select(req) {
+            Some(site) => site.handle(req),
+            None => self.error_pages.response(Code::MisdirectedRequest),
+        }
+    }
+
+    fn error_response(&self, code: Code) -> Response {
+        self.error_pages.response(code)
+    }
+
+    fn max_body_len(&self, head: &Request) -> Option<usize> {
+        self.select(head)?.max_body_len(head)
+    }
+}
+
+// Returns the name from the `Host` field in lowercase, without the port and
+// the trailing dot.
+fn host_name(host: &[u8]) -> Option<String> {
+    let host = std::str::from_utf8(host).ok()?;
+    let name = if host.starts_with('[') {
+        &host[..=host.find(']')?]
+    } else {
+        host.rsplit_once(':').map_or(host, |(name, _)| name)
+    };
+
+    Some(name.trim_end_matches('.').to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use bytes::Bytes;
+    use http_lib::Method;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::config::OptionalConfigValues;
+
+    fn config(hosts: &[&str], default_host: Option<&str>, root: &str) -> Config {
+        let mut config = Config::default();
+        config.apply_optional(OptionalConfigValues {
+            root: Some(root.to_string()),
+            hosts: Some(hosts.iter().map(|host| host.parse().unwrap()).collect()),
+            default_host: default_host.map(str::to_string),
+            host: Some("localhost".to_string()),
+            ..OptionalConfigValues::default()
+        });
+        config
+    }
+
+    // A directory holding a single file named after it.
+    fn site(name: &str) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(format!("{name}.txt")), name).unwrap();
+        dir
+    }
+
+    fn get(hosts: &VirtualHosts, host: Option<&str>, path: &str) -> Code {
+        let mut req = Request::new(Bytes::new(), Method::Get, path.parse().unwrap());
+        req.headers.remove(&HeaderName::HOST);
+        if let Some(host) = host {
+            req.add_header_value(HeaderName::HOST, host.to_string().into());
+        }
+        hosts.handle(&req).code
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(host_name(b"Example.COM").unwrap(), "example.com");
+        assert_eq!(host_name(b"example.com:8080").unwrap(), "example.com");
+        assert_eq!(host_name(b"example.com.:8080").unwrap(), "example.com");
+        assert_eq!(host_name(b"127.0.0.1:80").unwrap(), "127.0.0.1");
+        assert_eq!(host_name(b"[::1]:8080").unwrap(), "[::1]");
+        assert_eq!(host_name(b"[::1]").unwrap(), "[::1]");
+        assert_eq!(host_name(b"[::1:8080"), None);
+        assert_eq!(host_name(b"caf\xe9.example"), None);
+    }
+
+    #[test]
+    fn precedence() {
+        let hosts = VirtualHosts::new(&config(
+            &[
+                "example.com=a",
+                "*.example.com=b",
+                "*.api.example.com=c",
+                "www.api.example.com,example.net=d",
+            ],
+            None,
+            ".",
+        ));
+
+        assert_eq!(hosts.find(b"example.com"), Some(0));
+        assert_eq!(hosts.find(b"EXAMPLE.com.:8080"), Some(0));
+        assert_eq!(hosts.find(b"www.example.com"), Some(1));
+        assert_eq!(hosts.find(b"api.example.com"), Some(1));
+        assert_eq!(hosts.find(b"v1.api.example.com"), Some(2));
+        assert_eq!(hosts.find(b"www.api.example.com"), Some(3));
+        assert_eq!(hosts.find(b"example.net"), Some(3));
+        assert_eq!(hosts.find(b"www.example.net"), None);
+        assert_eq!(hosts.find(b"badexample.com"), None);
+    }
+
+    #[test]
+    fn fallback() {
+        let (main, site) = (site("main"), site("site"));
+        let vhost = format!("site.example={}", site.path().display());
+        let root = main.path().to_str().unwrap();
+
+        let hosts = VirtualHosts::new(&config(&[&vhost], None, root));
+        assert_eq!(get(&hosts, Some("site.example"), "/site.txt"), Code::Ok);
+        assert_eq!(get(&hosts, Some("localhost:8000"), "/main.txt"), Code::Ok);
+        assert_eq!(get(&hosts, Some("127.0.0.1:8000"), "/main.txt"), Code::Ok);
+        assert_eq!(
+            get(&hosts, Some("other.example"), "/site.txt"),
+            Code::MisdirectedRequest
+        );
+        assert_eq!(get(&hosts, None, "/main.txt"), Code::MisdirectedRequest);
+
+        let hosts = VirtualHosts::new(&config(&[&vhost], Some("site.example"), root));
+        assert_eq!(get(&hosts, Some("other.example"), "/site.txt"), Code::Ok);
+        assert_eq!(get(&hosts, None, "/site.txt"), Code::Ok);
+        // the server's own name is still served from the root
+        assert_eq!(
+            get(&hosts, Some("localhost:8000"), "/site.txt"),
+            Code::NotFound
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use std::net::TcpStream;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::{debug, error, warn};
+use rustls::ServerConfig;
+
+use crate::connection::Connection;
+use crate::handler::Handler;
+use crate::observer::{Observer, Rejection};
+use crate::server::Limits;
+use crate::stream_handler::{self, StreamHandler};
+use http_lib::response::Code;
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Connections over the limit being answered at the same time, any more are
+// closed right away.
+const MAX_REJECTING: usize = 64;
+
+// Distributes accepted connections between a fixed number of worker threads,
+// each of which owns its own `StreamHandler`.
+pub struct WorkerPool {
+    sender: mpsc::Sender<TcpStream>,
+    workers: Vec<JoinHandle<()>>,
+    active_connections: Arc<AtomicUsize>,
+    max_connections: usize,
+    rejecting: Arc<AtomicUsize>,
+    tls: Option<Arc<ServerConfig>>,
+    draining: Arc<AtomicBool>,
+    observers: Arc<[Box<dyn Observer>]>,
+}
+
+impl WorkerPool {
+    pub fn new<H: Handler>(
+        handler: &Arc<H>,
+        limits: &Limits,
+        tls: Option<&Arc<ServerConfig>>,
+        observers: &Arc<[Box<dyn Observer>]>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let active_connections = Arc::new(AtomicUsize::new(0));
+        let draining = Arc::new(AtomicBool::new(false));
+
+        let workers = (0..limits.workers.max(1))
+            .map(|id| {
+                let handler = StreamHandler::new(
+                    Arc::clone(handler),
+                    limits,
+                    Arc::clone(&draining),
+                    Arc::clone(observers),
+                );
+                let receiver = Arc::clone(&receiver);
+                let active_connections = Arc::clone(&active_connections);
+                let tls = tls.cloned();
+                thread::Builder::new()
+                    .name(format!("worker-{id}"))
+                    .spawn(move || {
+                        run_worker(handler, &receiver, &active_connections, tls.as_ref());
+                    })
+                    .expect("unable to spawn a worker thread")
+            })
+            .collect();
+
+        Self {
+            sender,
+            workers,
+            active_connections,
+            max_connections: limits.max_connections.max(1),
+            rejecting: Arc::new(AtomicUsize::new(0)),
+            tls: tls.cloned(),
+            draining,
+            observers: Arc::clone(observers),
+        }
+    }
+
+    // Queues the connection for one of the workers, or answers with
+    // `503 Service Unavailable` on a thread of its own if too many connections
+    // are already open, so that slow clients never hold up the accepting
+    // thread.
+    pub fn dispatch(&self, stream: TcpStream) {
+        let active = self.active_connections.fetch_add(1, Ordering::AcqRel);
+        if active >= self.max_connections {
+            self.active_connections.fetch_sub(1, Ordering::AcqRel);
+            warn!("Too many connections, rejecting!");
+            let rejection = Rejection {
+                code: Code::ServiceUnavailable,
+                error: None,
+                peer_addr: stream.peer_addr().ok(),
+            };
+            for observer in self.observers.iter() {
+                observer.request_rejected(&rejection);
+            }
+
+            self.reject(stream);
+            return;
+        }
+
+        if self.sender.send(stream).is_err() {
+            self.active_connections.fetch_sub(1, Ordering::AcqRel);
+            error!("All workers have stopped, dropping connection");
+        }
+    }
+
+    fn reject(&self, stream: TcpStream) {
+        if self.rejecting.fetch_add(1, Ordering::AcqRel) >= MAX_REJECTING {
+            self.rejecting.fetch_sub(1, Ordering::AcqRel);
+            warn!("Too many rejected connections, closing");
+            return;
+        }
+
+        let rejecting = Arc::clone(&self.rejecting);
+        let tls = self.tls.clone();
+        let spawned = thread::Builder::new()
+            .name("rejecter".to_string())
+            .spawn(move || {
+                match Connection::new(stream, tls.as_ref()) {
+                    Ok(mut connection) => {
+                        stream_handler::reject(&mut connection, Code::ServiceUnavailable);
+                    }
+                    Err(err) => error!("Failed to set up the connection: {err}"),
+                }
+                rejecting.fetch_sub(1, Ordering::AcqRel);
+            });
+
+        if let Err(err) = spawned {
+            self.rejecting.fetch_sub(1, Ordering::AcqRel);
+            error!("Failed to spawn a thread for the rejected connection: {err}");
+        }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    // Lets the workers finish the queued and open connections, returns whether
+    // they did so before the timeout. Workers which are still busy are left
+    // running.
+    pub fn drain(self, timeout: Duration) -> bool {
+        self.draining.store(true, Ordering::Release);
+        drop(self.sender);
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let active = self.active_connections.load(Ordering::Acquire);
+            if active == 0 {
+                break;
+            }
+
+            if Instant::now() >= deadline {
+                warn!("Drain timed out with {active} connections open");
+                return false;
+            }
+
+            thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+
+        for worker in self.workers {
+            if worker.join().is_err() {
+                error!("A worker has panicked");
+            }
+        }
+
+        true
+    }
+}
+
+fn run_worker<H: Handler>(
+    mut handler: StreamHandler<H>,
+    receiver: &Mutex<mpsc::Receiver<TcpStream>>,
+    active_connections: &AtomicUsize,
+    tls: Option<&Arc<ServerConfig>>,
+) {
+    loop {
+        // the lock is released as soon as a connection is received
+        let stream = receiver
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .recv();
+
+        let Ok(stream) = stream else {
+            debug!("Worker shutting down");
+            return;
+        };
+
+        // a panic must neither stop the worker nor leak the connection slot
+        match Connection::new(stream, tls) {
+            Ok(mut connection) => {
+                let dispatched =
+                    panic::catch_unwind(AssertUnwindSafe(|| handler.dispatch(&mut connection)));
+                if dispatched.is_err() {
+                    error!("A worker has panicked while serving a connection");
+                }
+                connection.close();
+            }
+            Err(err) => error!("Failed to set up the connection: {err}"),
+        }
+
+        active_connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write as _;
+
+    use http_lib::{HeaderName, Request};
+
+    use super::*;
+    use crate::server::test::{echo, header, Client, Running};
+    use crate::{tls, Server};
+
+    #[test]
+    fn workers() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let most_active = Arc::new(AtomicUsize::new(0));
+        let handler = {
+            let (active, most_active) = (Arc::clone(&active), Arc::clone(&most_active));
+            move |req: &Request| {
+                let now_active = active.fetch_add(1, Ordering::AcqRel) + 1;
+                most_active.fetch_max(now_active, Ordering::AcqRel);
+                thread::sleep(Duration::from_millis(200));
+                active.fetch_sub(1, Ordering::AcqRel);
+                echo(req)
+            }
+        };
+
+        let server = Running::start(Server::builder(handler).workers(3));
+        let clients: Vec<_> = (0..6)
+            .map(|_| {
+                let addr = server.addr;
+                thread::spawn(move || Client::connect(addr).get("/").code)
+            })
+            .collect();
+
+        for client in clients {
+            assert_eq!(client.join().unwrap(), Code::Ok);
+        }
+        assert_eq!(most_active.load(Ordering::Acquire), 3);
+    }
+
+    #[test]
+    fn reject() {
+        let server = Running::start(Server::builder(echo).workers(2).max_connections(1));
+        let mut open = server.connect();
+        assert_eq!(open.get("/").code, Code::Ok);
+
+        // the request is read and discarded, so that a client still sending
+        // it gets to see the response instead of a reset
+        let mut rejected = server.connect();
+        let body = "a".repeat(256 * 1024);
+        rejected.send(&format!(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        ));
+        thread::sleep(Duration::from_millis(100));
+        rejected.send(&body);
+        let res = rejected.response().unwrap();
+        assert_eq!(res.code, Code::ServiceUnavailable);
+        assert_eq!(
+            header(&res, &HeaderName::CONNECTION).as_deref(),
+            Some("close")
+        );
+        assert!(rejected.is_closed());
+
+        // the slot is released along with the connection
+        drop(open);
+        let served = (0..50).any(|_| {
+            thread::sleep(Duration::from_millis(20));
+            server.connect().get("/").code == Code::Ok
+        });
+        assert!(served);
+    }
+
+    #[test]
+    fn reject_tls() {
+        let server = Running::start(
+            Server::builder(echo)
+                .max_connections(1)
+                .tls(Some(tls::test::self_signed())),
+        );
+        let mut open = tls::test::connect(server.addr, "localhost");
+        assert_eq!(open.get("/").code, Code::Ok);
+
+        let mut rejected = tls::test::connect(server.addr, "localhost");
+        let res = rejected.get("/");
+        assert_eq!(res.code, Code::ServiceUnavailable);
+        assert!(rejected.is_closed());
+    }
+
+    #[test]
+    fn accept_while_rejecting() {
+        // a rejected client which never reads must not hold up the others
+        let server = Running::start(Server::builder(echo).max_connections(1));
+        let mut open = server.connect();
+        assert_eq!(open.get("/").code, Code::Ok);
+
+        let stalled: Vec<_> = (0..4)
+            .map(|_| {
+                let mut stream = crate::server::test::connect(server.addr);
+                stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+                stream
+            })
+            .collect();
+
+        let started = Instant::now();
+        let res = server.connect().get("/");
+        assert_eq!(res.code, Code::ServiceUnavailable);
+        assert!(started.elapsed() < Duration::from_millis(500));
+        drop(stalled);
+        assert_eq!(open.get("/").code, Code::Ok);
+    }
+}